@@ -0,0 +1,73 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use lucid8::emulator::display::Display;
+
+// The previous Vec<Vec<bool>> display, drawing one bit at a time, kept here
+// as a baseline for the packed representation.
+struct BoolDisplay {
+    pixel_map: Vec<Vec<bool>>,
+    height: u8,
+    width: u8
+}
+
+impl BoolDisplay {
+    fn new(width: u8, height: u8) -> Self {
+        Self { pixel_map: vec![vec![false; width as usize]; height as usize], height, width }
+    }
+
+    fn draw_bit_at(&mut self, x: u8, y: u8, bit: bool) -> bool {
+        let px = self.pixel_map[(y % self.height) as usize][(x % self.width) as usize];
+        let new = px ^ bit;
+        self.pixel_map[(y % self.height) as usize][(x % self.width) as usize] = new;
+        px && !new
+    }
+
+    fn draw_bytes_at(&mut self, x: u8, y: u8, bytes: &[u8]) -> bool {
+        let mut collision = false;
+        for (i, byte) in bytes.iter().enumerate() {
+            for bit in 0..8 {
+                collision |= self.draw_bit_at(x.wrapping_add(bit), y.wrapping_add(i as u8), byte & (0x80 >> bit) != 0);
+            }
+        }
+        collision
+    }
+}
+
+const SPRITE: [u8; 15] = [0xFF, 0x81, 0xBD, 0xA5, 0xA5, 0xBD, 0x81, 0xFF, 0x3C, 0x42, 0x99, 0xA5, 0x99, 0x42, 0x3C];
+
+fn draw_sprites(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw 15-row sprites across the screen");
+    group.bench_function("Vec<Vec<bool>>", |b| {
+        let mut display = BoolDisplay::new(64, 32);
+        b.iter(|| {
+            for x in (0..64).step_by(3) {
+                black_box(display.draw_bytes_at(black_box(x), black_box(x / 2), &SPRITE));
+            }
+        })
+    });
+    group.bench_function("packed rows", |b| {
+        let mut display = Display::new(64, 32);
+        b.iter(|| {
+            for x in (0..64).step_by(3) {
+                black_box(display.draw_bytes_at(black_box(x), black_box(x / 2), &SPRITE).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn clear(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clear");
+    group.bench_function("Vec<Vec<bool>>", |b| {
+        let mut display = BoolDisplay::new(64, 32);
+        b.iter(|| display.pixel_map = vec![vec![false; display.width.into()]; display.height.into()])
+    });
+    group.bench_function("packed rows", |b| {
+        let mut display = Display::new(64, 32);
+        b.iter(|| display.clear())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, draw_sprites, clear);
+criterion_main!(benches);
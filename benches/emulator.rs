@@ -0,0 +1,40 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use lucid8::emulator::emulator::Emulator;
+use lucid8::emulator::quirks::Quirks;
+
+// A busy loop like a game's main loop: arithmetic, a BCD store and reload
+// through I, a one-row sprite and a jump back.
+const PROGRAM: [u8; 24] = [
+    0x60, 0x00, // LD V0, 0
+    0x61, 0x00, // LD V1, 0
+    0xA3, 0x00, // LD I, 0x300
+    0x70, 0x01, // loop: ADD V0, 1
+    0x81, 0x04, // ADD V1, V0
+    0xF0, 0x33, // LD B, V0
+    0xF2, 0x65, // LD V2, [I]
+    0xD0, 0x11, // DRW V0, V1, 1
+    0x82, 0x06, // SHR V2
+    0x30, 0x00, // SE V0, 0
+    0x12, 0x06, // JP loop
+    0x12, 0x06  // JP loop
+];
+
+const CYCLES: usize = 100_000;
+
+fn run_cycles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run 100k cycles");
+    group.throughput(Throughput::Elements(CYCLES as u64));
+    for (name, cached) in [("decode every step", false), ("decode cache", true)] {
+        group.bench_function(name, |b| {
+            let mut emulator = Emulator::new(0, Quirks::default());
+            emulator.load_program(&PROGRAM).unwrap();
+            emulator.memory_mut().set_decode_cache(cached);
+            b.iter(|| emulator.run_cycles(black_box(CYCLES)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run_cycles);
+criterion_main!(benches);
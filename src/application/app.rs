@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::emulator::Result;
+use crate::emulator::platform::{Platform, PlatformProfile};
+use crate::emulator::quirks::{QuirkProfile, Quirks};
+use crate::frontend::palette::Palette;
+use crate::input::keymap::KeymapProfile;
+use crate::util::content_hash;
+
+pub const MANIFEST_NAME: &str = "lucid8.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetKind {
+    // `db` lines as written by `Sprite::to_db_lines`.
+    Sprite,
+    // A font file as read by `Font::load`.
+    Font,
+    // 16 bytes of XO-CHIP audio pattern.
+    Audio
+}
+
+// Data the build pulls in next to the source, addressable by `name`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub kind: AssetKind,
+    pub path: PathBuf,
+    // Sprite width in pixels, 8 unless it's a 16x16 sprite.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u8>
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from("build/game.ch8")
+}
+
+// The on-disk project file. Paths are relative to the directory holding it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default)]
+    pub sources: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<Asset>,
+    #[serde(default = "default_platform")]
+    pub platform: PlatformProfile,
+    // The interpreter's own defaults when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<QuirkProfile>,
+    #[serde(default = "default_output")]
+    pub output: PathBuf,
+    #[serde(default)]
+    pub keymap: KeymapProfile,
+    #[serde(default)]
+    pub palette: Palette
+}
+
+fn default_platform() -> PlatformProfile {
+    PlatformProfile::Chip8
+}
+
+impl Manifest {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            sources: vec![],
+            assets: vec![],
+            platform: default_platform(),
+            quirks: None,
+            output: default_output(),
+            keymap: KeymapProfile::default(),
+            palette: Palette::default()
+        }
+    }
+}
+
+// A game under development: its manifest plus whether it has unsaved
+// edits and which of its files changed since `mark_built`.
+#[derive(Debug, Clone)]
+pub struct Project {
+    path: PathBuf,
+    manifest: Manifest,
+    modified: bool,
+    fingerprints: BTreeMap<PathBuf, Option<u64>>
+}
+
+impl Project {
+    // A new, unsaved project whose manifest will be written to `path`.
+    pub fn new(path: &Path, name: &str) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            manifest: Manifest::new(name),
+            modified: true,
+            fingerprints: BTreeMap::new()
+        }
+    }
+
+    // Accepts the manifest itself or the directory holding it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let path = if path.is_dir() { path.join(MANIFEST_NAME) } else { path.to_path_buf() };
+        let manifest: Manifest = toml::from_str(&fs::read_to_string(&path)?)?;
+        let mut project = Self { path, manifest, modified: false, fingerprints: BTreeMap::new() };
+        project.mark_built();
+        Ok(project)
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        fs::write(&self.path, toml::to_string(&self.manifest)?)?;
+        self.modified = false;
+        Ok(())
+    }
+
+    pub fn save_as(&mut self, path: &Path) -> Result<()> {
+        self.path = path.to_path_buf();
+        self.save()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn root(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    // A manifest path made absolute against the project directory.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root().join(path)
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    // Unsaved changes to the manifest.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    fn edit(&mut self) -> &mut Manifest {
+        self.modified = true;
+        &mut self.manifest
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.edit().name = name.to_string();
+    }
+
+    pub fn add_source(&mut self, path: &Path) -> Result<()> {
+        if self.manifest.sources.iter().any(|source| source == path) {
+            return Err(Box::new(ProjectError::Duplicate(path.to_path_buf())));
+        }
+        self.edit().sources.push(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn remove_source(&mut self, path: &Path) -> Result<()> {
+        let index = self.manifest.sources.iter().position(|source| source == path)
+            .ok_or_else(|| ProjectError::NotInProject(path.to_path_buf()))?;
+        self.edit().sources.remove(index);
+        Ok(())
+    }
+
+    pub fn add_asset(&mut self, asset: Asset) -> Result<()> {
+        if self.manifest.assets.iter().any(|existing| existing.name == asset.name) {
+            return Err(Box::new(ProjectError::Duplicate(PathBuf::from(&asset.name))));
+        }
+        self.edit().assets.push(asset);
+        Ok(())
+    }
+
+    pub fn remove_asset(&mut self, name: &str) -> Result<Asset> {
+        let index = self.manifest.assets.iter().position(|asset| asset.name == name)
+            .ok_or_else(|| ProjectError::NotInProject(PathBuf::from(name)))?;
+        Ok(self.edit().assets.remove(index))
+    }
+
+    pub fn set_platform(&mut self, platform: PlatformProfile) {
+        self.edit().platform = platform;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Option<QuirkProfile>) {
+        self.edit().quirks = quirks;
+    }
+
+    pub fn set_output(&mut self, path: &Path) {
+        self.edit().output = path.to_path_buf();
+    }
+
+    pub fn set_keymap(&mut self, keymap: KeymapProfile) {
+        self.edit().keymap = keymap;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.edit().palette = palette;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.manifest.platform.into()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.manifest.quirks.map(Quirks::from).unwrap_or_default()
+    }
+
+    // Sources and assets, in manifest order.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.manifest.sources.iter().cloned()
+            .chain(self.manifest.assets.iter().map(|asset| asset.path.clone()))
+            .collect()
+    }
+
+    fn fingerprint(&self, path: &Path) -> Option<u64> {
+        fs::read(self.resolve(path)).ok().map(|bytes| content_hash(&bytes))
+    }
+
+    // Records the current contents of every file as the last build's inputs.
+    pub fn mark_built(&mut self) {
+        self.fingerprints = self.files().into_iter()
+            .map(|path| {
+                let fingerprint = self.fingerprint(&path);
+                (path, fingerprint)
+            })
+            .collect();
+    }
+
+    // Files added, edited or deleted since `mark_built`.
+    pub fn changed_files(&self) -> Vec<PathBuf> {
+        self.files().into_iter()
+            .filter(|path| match self.fingerprints.get(path) {
+                Some(fingerprint) => *fingerprint != self.fingerprint(path),
+                None => true
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Duplicate(PathBuf),
+    NotInProject(PathBuf)
+}
+
+impl Display for ProjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectError::Duplicate(path) => write!(f, "{} is already part of the project", path.display()),
+            ProjectError::NotInProject(path) => write!(f, "{} is not part of the project", path.display())
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lucid8-project-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sprite(name: &str) -> Asset {
+        Asset { name: name.to_string(), kind: AssetKind::Sprite, path: PathBuf::from(format!("{}.db", name)), width: Some(16) }
+    }
+
+    #[test]
+    fn manifests_round_trip_with_scalars_before_assets() {
+        let dir = temp_dir("round-trip");
+        let mut project = Project::new(&dir.join(MANIFEST_NAME), "pong");
+        project.add_source(Path::new("main.asm")).unwrap();
+        project.add_asset(sprite("ball")).unwrap();
+        project.add_asset(Asset { name: String::from("beep"), kind: AssetKind::Audio, path: PathBuf::from("beep.bin"), width: None }).unwrap();
+        project.set_platform(PlatformProfile::Schip);
+        project.set_quirks(Some(QuirkProfile::Chip48));
+        project.set_output(Path::new("out/pong.ch8"));
+        assert!(project.is_modified());
+        project.save().unwrap();
+        assert!(!project.is_modified());
+
+        let text = fs::read_to_string(project.path()).unwrap();
+        let loaded = Project::load(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.manifest(), project.manifest());
+        assert_eq!(loaded.platform(), PlatformProfile::Schip.into());
+        assert_eq!(loaded.quirks(), QuirkProfile::Chip48.into());
+
+        // TOML scalars after a table would belong to it, so they must come
+        // first.
+        let assets = text.find("[[assets]]").unwrap();
+        for key in ["name =", "sources =", "platform =", "quirks =", "output ="] {
+            assert!(text.find(key).unwrap() < assets, "{} comes after [[assets]] in\n{}", key, text);
+        }
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let manifest: Manifest = toml::from_str("name = \"empty\"").unwrap();
+        assert_eq!(manifest, Manifest::new("empty"));
+        assert_eq!(manifest.output, PathBuf::from("build/game.ch8"));
+    }
+
+    #[test]
+    fn sources_and_assets_are_unique() {
+        let mut project = Project::new(Path::new("lucid8.toml"), "pong");
+        project.add_source(Path::new("main.asm")).unwrap();
+        let error = project.add_source(Path::new("main.asm")).unwrap_err();
+        assert!(matches!(error.downcast_ref::<ProjectError>(), Some(ProjectError::Duplicate(_))));
+        project.add_asset(sprite("ball")).unwrap();
+        assert!(project.add_asset(sprite("ball")).is_err());
+        assert_eq!(project.files(), vec![PathBuf::from("main.asm"), PathBuf::from("ball.db")]);
+
+        assert_eq!(project.remove_asset("ball").unwrap(), sprite("ball"));
+        let error = project.remove_source(Path::new("other.asm")).unwrap_err();
+        assert_eq!(error.to_string(), "other.asm is not part of the project");
+    }
+
+    #[test]
+    fn changed_files_are_those_edited_since_the_last_build() {
+        let dir = temp_dir("changes");
+        fs::write(dir.join("main.asm"), "CLS\n").unwrap();
+        fs::write(dir.join("ball.db"), "db 0x80\n").unwrap();
+        let mut project = Project::new(&dir.join(MANIFEST_NAME), "pong");
+        project.add_source(Path::new("main.asm")).unwrap();
+        assert_eq!(project.changed_files(), vec![PathBuf::from("main.asm")]);
+        project.mark_built();
+        let unchanged = project.changed_files();
+
+        project.add_asset(sprite("ball")).unwrap();
+        fs::write(dir.join("main.asm"), "RET\n").unwrap();
+        let edited = project.changed_files();
+        project.mark_built();
+        fs::remove_file(dir.join("ball.db")).unwrap();
+        let deleted = project.changed_files();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(unchanged.is_empty());
+        assert_eq!(edited, vec![PathBuf::from("main.asm"), PathBuf::from("ball.db")]);
+        assert_eq!(deleted, vec![PathBuf::from("ball.db")]);
+    }
+}
@@ -0,0 +1,468 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+// Zero-based line and byte columns [start, end) within that line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // The file the problem is in, if it's in one.
+    pub file: Option<PathBuf>,
+    pub span: Option<Span>,
+    pub message: String
+}
+
+impl Diagnostic {
+    pub fn error(span: Option<Span>, message: String) -> Self {
+        Self { severity: Severity::Error, file: None, span, message }
+    }
+
+    pub fn in_file(mut self, file: PathBuf) -> Self {
+        self.file = Some(file);
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        match (&self.file, self.span) {
+            (Some(file), Some(span)) => write!(f, "{}:{}:{}: {}: {}", file.display(), span.line + 1, span.start + 1, severity, self.message),
+            (Some(file), None) => write!(f, "{}: {}: {}", file.display(), severity, self.message),
+            (None, _) => write!(f, "{}: {}", severity, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    Number(u16),
+    Label(String),
+    I,
+    // [I]
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B
+}
+
+impl Operand {
+    fn parse(text: &str) -> Option<Operand> {
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DT,
+            "ST" => Operand::ST,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::HF,
+            "B" => Operand::B,
+            _ if is_register(&upper) => Operand::Register(u8::from_str_radix(&upper[1..], 16).ok()?),
+            _ if is_label(text) => Operand::Label(text.to_string()),
+            _ => Operand::Number(parse_number(text)?)
+        };
+        Some(operand)
+    }
+}
+
+// Numbers may be hex (0x, # or $), binary (0b or %) or decimal.
+pub fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')).or_else(|| lower.strip_prefix('$')) {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        (digits, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    u16::from_str_radix(digits, radix).ok()
+}
+
+fn is_register(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some('V' | 'v')) && chars.next().is_some_and(|c| c.is_ascii_hexdigit()) && chars.next().is_none()
+}
+
+// Operand keywords, which can't be used as names.
+const KEYWORDS: [&str; 7] = ["I", "DT", "ST", "K", "F", "HF", "B"];
+
+// Names that parse as a register or keyword wherever they're used, so a
+// label or constant with one could never be referred to.
+pub fn is_reserved(name: &str) -> bool {
+    is_register(name) || KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(name))
+}
+
+pub fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+pub const MNEMONICS: [&str; 23] = [
+    "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "AUDIO", "PITCH", "DB", "DW"
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    Label(String),
+    // `name = value`; takes no space.
+    Constant { name: String, value: u16 },
+    Instruction { mnemonic: String, operands: Vec<(Operand, Span)> },
+    // `db` (width 1) or `dw` (width 2, big-endian).
+    Data { width: usize, values: Vec<(Operand, Span)> }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span
+}
+
+impl Statement {
+    // Bytes this statement takes up in the ROM.
+    pub fn size(&self) -> usize {
+        match &self.kind {
+            StatementKind::Label(_) | StatementKind::Constant { .. } => 0,
+            StatementKind::Instruction { .. } => 2,
+            StatementKind::Data { width, values } => width * values.len()
+        }
+    }
+
+    // Labels this statement refers to, with where.
+    pub fn references(&self) -> Vec<(&str, Span)> {
+        let operands = match &self.kind {
+            StatementKind::Label(_) | StatementKind::Constant { .. } => return vec![],
+            StatementKind::Instruction { operands, .. } => operands,
+            StatementKind::Data { values, .. } => values
+        };
+        operands.iter().filter_map(|(operand, span)| match operand {
+            Operand::Label(name) => Some((name.as_str(), *span)),
+            _ => None
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedSource {
+    pub statements: Vec<Statement>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl ParsedSource {
+    // Label definitions with their spans.
+    pub fn labels(&self) -> Vec<(&str, Span)> {
+        self.statements.iter().filter_map(|statement| match &statement.kind {
+            StatementKind::Label(name) => Some((name.as_str(), statement.span)),
+            _ => None
+        }).collect()
+    }
+
+    // Labels and constants with the span of their name.
+    pub fn definitions(&self) -> Vec<(&str, Span)> {
+        self.statements.iter().filter_map(|statement| match &statement.kind {
+            StatementKind::Label(name) | StatementKind::Constant { name, .. } => Some((name.as_str(), statement.span)),
+            _ => None
+        }).collect()
+    }
+}
+
+// Splits `text` at commas, keeping each piece's columns relative to `offset`.
+fn split_operands(text: &str, offset: usize) -> Vec<(&str, usize)> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    for (index, c) in text.char_indices().chain(std::iter::once((text.len(), ','))) {
+        if c == ',' {
+            let piece = &text[start..index];
+            let trimmed = piece.trim_start();
+            pieces.push((trimmed.trim_end(), offset + start + piece.len() - trimmed.len()));
+            start = index + 1;
+        }
+    }
+    pieces
+}
+
+// One statement per line, optionally preceded by `label:`, or a
+// `name = value` constant. Comments start with `;`. Mnemonics and register names are case-insensitive.
+pub fn parse(source: &str) -> ParsedSource {
+    let mut parsed = ParsedSource::default();
+    for (line, text) in source.lines().enumerate() {
+        let code = text.split(';').next().unwrap_or("");
+        let mut rest = code;
+        let mut column = 0;
+        loop {
+            let trimmed = rest.trim_start();
+            column += rest.len() - trimmed.len();
+            rest = trimmed;
+            let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            if name_len > 0 && rest[name_len..].starts_with(':') && is_label(&rest[..name_len]) {
+                let span = Span { line, start: column, end: column + name_len };
+                if is_reserved(&rest[..name_len]) {
+                    parsed.diagnostics.push(Diagnostic::error(Some(span), format!("'{}' is a register or keyword and can't be a label", &rest[..name_len])));
+                } else {
+                    parsed.statements.push(Statement { kind: StatementKind::Label(rest[..name_len].to_string()), span });
+                }
+                rest = &rest[name_len + 1..];
+                column += name_len + 1;
+            } else {
+                break;
+            }
+        }
+        let rest = rest.trim_end();
+        if rest.is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = rest.split_once('=') {
+            let name = name.trim_end();
+            let span = Span { line, start: column, end: column + name.len() };
+            if !is_label(name) {
+                parsed.diagnostics.push(Diagnostic::error(Some(span), format!("Invalid constant name '{}'", name)));
+                continue;
+            }
+            if is_reserved(name) {
+                parsed.diagnostics.push(Diagnostic::error(Some(span), format!("'{}' is a register or keyword and can't be a constant", name)));
+                continue;
+            }
+            match parse_number(value.trim()) {
+                Some(value) => parsed.statements.push(Statement { kind: StatementKind::Constant { name: name.to_string(), value }, span }),
+                None => {
+                    let start = column + rest.len() - value.trim_start().len();
+                    let span = Span { line, start, end: column + rest.len() };
+                    parsed.diagnostics.push(Diagnostic::error(Some(span), format!("Expected a number, got '{}'", value.trim())));
+                }
+            }
+            continue;
+        }
+        let mnemonic_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let mnemonic = rest[..mnemonic_len].to_ascii_uppercase();
+        let span = Span { line, start: column, end: column + rest.len() };
+        let operand_text = &rest[mnemonic_len..];
+        let mut operands = vec![];
+        if !operand_text.trim().is_empty() {
+            for (text, start) in split_operands(operand_text, column + mnemonic_len) {
+                let operand_span = Span { line, start, end: start + text.len() };
+                match Operand::parse(text) {
+                    Some(operand) => operands.push((operand, operand_span)),
+                    None => parsed.diagnostics.push(Diagnostic::error(Some(operand_span), format!("Expected a register, number or label, got '{}'", text)))
+                }
+            }
+        }
+        let kind = match mnemonic.as_str() {
+            "DB" => StatementKind::Data { width: 1, values: operands },
+            "DW" => StatementKind::Data { width: 2, values: operands },
+            _ if MNEMONICS.contains(&mnemonic.as_str()) => StatementKind::Instruction { mnemonic, operands },
+            _ => {
+                let span = Span { line, start: column, end: column + mnemonic_len };
+                parsed.diagnostics.push(Diagnostic::error(Some(span), format!("Unknown instruction '{}'", &rest[..mnemonic_len])));
+                continue;
+            }
+        };
+        parsed.statements.push(Statement { kind, span });
+    }
+    parsed
+}
+
+// Encodes a statement once label addresses are known.
+pub fn encode(statement: &Statement, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<Vec<u8>, Diagnostic> {
+    let value = |(operand, span): &(Operand, Span), max: u16| -> Result<u16, Diagnostic> {
+        let value = match operand {
+            Operand::Number(value) => *value,
+            Operand::Label(name) => resolve(name).ok_or_else(|| Diagnostic::error(Some(*span), format!("Undefined label or constant '{}'", name)))?,
+            _ => return Err(Diagnostic::error(Some(*span), String::from("Expected a number or label")))
+        };
+        if value > max {
+            return Err(Diagnostic::error(Some(*span), format!("{:#x} is out of range, the maximum is {:#x}", value, max)));
+        }
+        Ok(value)
+    };
+    let (mnemonic, operands) = match &statement.kind {
+        StatementKind::Label(_) | StatementKind::Constant { .. } => return Ok(vec![]),
+        StatementKind::Data { width, values } => {
+            let mut bytes = vec![];
+            for operand in values {
+                let word = value(operand, if *width == 1 { 0xFF } else { 0xFFFF })?;
+                bytes.extend_from_slice(&word.to_be_bytes()[2 - width..]);
+            }
+            return Ok(bytes);
+        },
+        StatementKind::Instruction { mnemonic, operands } => (mnemonic.as_str(), operands)
+    };
+    let kinds: Vec<&Operand> = operands.iter().map(|(operand, _)| operand).collect();
+    let addr = |index: usize| value(&operands[index], 0xFFF);
+    let byte = |index: usize| value(&operands[index], 0xFF);
+    let xy = |op: u16, x: u8, y: u8, n: u16| Ok(op << 12 | (x as u16) << 8 | (y as u16) << 4 | n);
+    let opcode: Result<u16, Diagnostic> = match (mnemonic, kinds.as_slice()) {
+        ("CLS", []) => Ok(0x00E0),
+        ("RET", []) => Ok(0x00EE),
+        ("JP", [Operand::Register(0), _]) => Ok(0xB000 | addr(1)?),
+        ("JP", [_]) => Ok(0x1000 | addr(0)?),
+        ("CALL", [_]) => Ok(0x2000 | addr(0)?),
+        ("SE", [Operand::Register(x), Operand::Register(y)]) => xy(0x5, *x, *y, 0),
+        ("SE", [Operand::Register(x), _]) => Ok(0x3000 | (*x as u16) << 8 | byte(1)?),
+        ("SNE", [Operand::Register(x), Operand::Register(y)]) => xy(0x9, *x, *y, 0),
+        ("SNE", [Operand::Register(x), _]) => Ok(0x4000 | (*x as u16) << 8 | byte(1)?),
+        ("LD", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 0),
+        ("LD", [Operand::Register(x), Operand::DT]) => xy(0xF, *x, 0, 0x07),
+        ("LD", [Operand::Register(x), Operand::K]) => xy(0xF, *x, 0, 0x0A),
+        ("LD", [Operand::Register(x), Operand::IndirectI]) => xy(0xF, *x, 6, 5),
+        ("LD", [Operand::Register(x), _]) => Ok(0x6000 | (*x as u16) << 8 | byte(1)?),
+        ("LD", [Operand::I, _]) => Ok(0xA000 | addr(1)?),
+        ("LD", [Operand::DT, Operand::Register(x)]) => xy(0xF, *x, 1, 5),
+        ("LD", [Operand::ST, Operand::Register(x)]) => xy(0xF, *x, 1, 8),
+        ("LD", [Operand::F, Operand::Register(x)]) => xy(0xF, *x, 2, 9),
+        ("LD", [Operand::HF, Operand::Register(x)]) => xy(0xF, *x, 3, 0),
+        ("LD", [Operand::B, Operand::Register(x)]) => xy(0xF, *x, 3, 3),
+        ("LD", [Operand::IndirectI, Operand::Register(x)]) => xy(0xF, *x, 5, 5),
+        ("ADD", [Operand::I, Operand::Register(x)]) => xy(0xF, *x, 1, 0xE),
+        ("ADD", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 4),
+        ("ADD", [Operand::Register(x), _]) => Ok(0x7000 | (*x as u16) << 8 | byte(1)?),
+        ("OR", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 1),
+        ("AND", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 2),
+        ("XOR", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 3),
+        ("SUB", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 5),
+        ("SHR", [Operand::Register(x)]) => xy(0x8, *x, 0, 6),
+        ("SHR", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 6),
+        ("SUBN", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 7),
+        ("SHL", [Operand::Register(x)]) => xy(0x8, *x, 0, 0xE),
+        ("SHL", [Operand::Register(x), Operand::Register(y)]) => xy(0x8, *x, *y, 0xE),
+        ("RND", [Operand::Register(x), _]) => Ok(0xC000 | (*x as u16) << 8 | byte(1)?),
+        ("DRW", [Operand::Register(x), Operand::Register(y), _]) => xy(0xD, *x, *y, value(&operands[2], 0xF)?),
+        ("SKP", [Operand::Register(x)]) => xy(0xE, *x, 9, 0xE),
+        ("SKNP", [Operand::Register(x)]) => xy(0xE, *x, 0xA, 1),
+        ("AUDIO", []) => Ok(0xF002),
+        ("PITCH", [Operand::Register(x)]) => xy(0xF, *x, 3, 0xA),
+        _ => Err(Diagnostic::error(Some(statement.span), format!("Invalid operands for {}", mnemonic)))
+    };
+    Ok(opcode?.to_be_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(name: &str) -> Option<u16> {
+        match name {
+            "start" => Some(0x234),
+            "big" => Some(0x1000),
+            _ => None
+        }
+    }
+
+    fn assemble(line: &str) -> Result<Vec<u8>, Diagnostic> {
+        let parsed = parse(line);
+        assert!(parsed.diagnostics.is_empty(), "{:?}", parsed.diagnostics);
+        encode(&parsed.statements[0], &resolve)
+    }
+
+    fn error(line: &str) -> String {
+        let parsed = parse(line);
+        match parsed.diagnostics.first() {
+            Some(diagnostic) => diagnostic.message.clone(),
+            None => encode(&parsed.statements[0], &resolve).unwrap_err().message
+        }
+    }
+
+    #[test]
+    fn every_mnemonic_encodes() {
+        let cases: [(&str, u16); 40] = [
+            ("CLS", 0x00E0), ("RET", 0x00EE),
+            ("JP start", 0x1234), ("JP V0, 0x300", 0xB300), ("CALL start", 0x2234),
+            ("SE V1, 0x22", 0x3122), ("SE V1, V2", 0x5120),
+            ("SNE V1, 0x22", 0x4122), ("SNE V1, V2", 0x9120),
+            ("LD V1, 0x22", 0x6122), ("LD V1, V2", 0x8120), ("LD V1, DT", 0xF107),
+            ("LD V1, K", 0xF10A), ("LD V1, [I]", 0xF165), ("LD I, start", 0xA234),
+            ("LD DT, V1", 0xF115), ("LD ST, V1", 0xF118), ("LD F, V1", 0xF129),
+            ("LD HF, V1", 0xF130), ("LD B, V1", 0xF133), ("LD [I], V1", 0xF155),
+            ("ADD V1, 0x22", 0x7122), ("ADD V1, V2", 0x8124), ("ADD I, V1", 0xF11E),
+            ("OR V1, V2", 0x8121), ("AND V1, V2", 0x8122), ("XOR V1, V2", 0x8123),
+            ("SUB V1, V2", 0x8125), ("SHR V1", 0x8106), ("SHR V1, V2", 0x8126),
+            ("SUBN V1, V2", 0x8127), ("SHL V1", 0x810E), ("SHL V1, V2", 0x812E),
+            ("RND V1, 0x22", 0xC122), ("DRW V1, V2, 5", 0xD125),
+            ("SKP V1", 0xE19E), ("SKNP V1", 0xE1A1),
+            ("AUDIO", 0xF002), ("PITCH V1", 0xF13A),
+            ("ld vf, %101", 0x6F05)
+        ];
+        for (line, opcode) in cases {
+            assert_eq!(assemble(line).unwrap(), opcode.to_be_bytes(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn data_is_stored_big_endian() {
+        assert_eq!(assemble("db 1, #ff, $10, 0b11").unwrap(), vec![1, 0xFF, 0x10, 3]);
+        assert_eq!(assemble("dw 0x1234, start").unwrap(), vec![0x12, 0x34, 0x02, 0x34]);
+        assert_eq!(parse("dw 1, 2, 3").statements[0].size(), 6);
+    }
+
+    #[test]
+    fn labels_and_constants_take_no_space() {
+        let parsed = parse("start: loop: JP loop ; forever\nspeed = 0x10\n");
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.labels(), vec![
+            ("start", Span { line: 0, start: 0, end: 5 }),
+            ("loop", Span { line: 0, start: 7, end: 11 })
+        ]);
+        assert_eq!(parsed.definitions()[2], ("speed", Span { line: 1, start: 0, end: 5 }));
+        assert_eq!(parsed.statements.iter().map(Statement::size).sum::<usize>(), 2);
+        assert_eq!(parsed.statements[2].references(), vec![("loop", Span { line: 0, start: 16, end: 20 })]);
+        assert_eq!(parsed.statements[2].span, Span { line: 0, start: 13, end: 20 });
+    }
+
+    #[test]
+    fn reserved_names_are_rejected_where_they_are_defined() {
+        for name in ["b", "F", "k", "i", "DT", "st", "hf", "v0", "VF"] {
+            let parsed = parse(&format!("  {}: CLS", name));
+            assert_eq!(parsed.diagnostics[0].span, Some(Span { line: 0, start: 2, end: 2 + name.len() }), "{}", name);
+            assert!(parsed.labels().is_empty(), "{}", name);
+        }
+        // Not registers or keywords, so ordinary labels.
+        for name in ["vz", "v10", "dt2"] {
+            let parsed = parse(&format!("{}: JP {}", name, name));
+            assert!(parsed.diagnostics.is_empty(), "{}", name);
+            assert_eq!(parsed.labels()[0].0, name);
+            assert_eq!(parsed.statements[1].references()[0].0, name);
+        }
+    }
+
+    #[test]
+    fn mistakes_are_reported_where_they_are() {
+        assert_eq!(error("MOV V1, V2"), "Unknown instruction 'MOV'");
+        assert_eq!(error("LD V1, 1x"), "Expected a register, number or label, got '1x'");
+        assert_eq!(error("2fast = 1"), "Invalid constant name '2fast'");
+        assert_eq!(error("speed = fast"), "Expected a number, got 'fast'");
+        assert_eq!(error("LD V1, 0x100"), "0x100 is out of range, the maximum is 0xff");
+        assert_eq!(error("JP big"), "0x1000 is out of range, the maximum is 0xfff");
+        assert_eq!(error("DRW V1, V2, 16"), "0x10 is out of range, the maximum is 0xf");
+        assert_eq!(error("CALL missing"), "Undefined label or constant 'missing'");
+        assert_eq!(error("SE V1, K"), "Expected a number or label");
+        assert_eq!(error("OR V1, 3"), "Invalid operands for OR");
+        assert_eq!(error("CLS V0"), "Invalid operands for CLS");
+        assert_eq!(error("ve: CLS"), "'ve' is a register or keyword and can't be a label");
+        assert_eq!(error("Dt = 3"), "'Dt' is a register or keyword and can't be a constant");
+
+        let parsed = parse("CLS\n  LD V1, zz!");
+        let diagnostic = parsed.diagnostics[0].clone().in_file(PathBuf::from("main.asm"));
+        assert_eq!(diagnostic.span, Some(Span { line: 1, start: 9, end: 12 }));
+        assert_eq!(diagnostic.to_string(), "main.asm:2:10: error: Expected a register, number or label, got 'zz!'");
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::emulator::{Emulator, MemoryAddress, Result};
+use crate::emulator::font::Font;
+use crate::emulator::quirks::Quirks;
+use crate::frontend::session::Session;
+use crate::input::program::Program;
+use super::app::{Asset, AssetKind, Project};
+use super::assembler::{self, Diagnostic, ParsedSource, Severity, Span};
+use super::sprite::Sprite;
+
+// Where a range of ROM bytes came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    pub addr: MemoryAddress,
+    pub len: usize,
+    pub file: PathBuf,
+    pub line: usize,
+    // The statement as written, or the asset's name.
+    pub text: String
+}
+
+// Every emitted statement in address order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    entries: Vec<SourceMapEntry>
+}
+
+impl SourceMap {
+    pub fn entries(&self) -> &[SourceMapEntry] {
+        &self.entries
+    }
+
+    pub fn lookup(&self, addr: MemoryAddress) -> Option<&SourceMapEntry> {
+        self.entries.iter().find(|entry| addr >= entry.addr && (addr as usize) < entry.addr as usize + entry.len)
+    }
+
+    // First address emitted for a source line.
+    pub fn address_of(&self, file: &Path, line: usize) -> Option<MemoryAddress> {
+        self.entries.iter().find(|entry| entry.file == file && entry.line == line).map(|entry| entry.addr)
+    }
+
+    // Where `addr` ended up in a rebuild: at the same offset into the same
+    // statement of the same file, the copy nearest its old line if there are
+    // several. Addresses outside this map are returned as they are; None
+    // means the statement was edited or deleted.
+    pub fn remap(&self, new: &SourceMap, addr: MemoryAddress) -> Option<MemoryAddress> {
+        let old = match self.lookup(addr) {
+            Some(entry) => entry,
+            None => return Some(addr)
+        };
+        let offset = addr - old.addr;
+        new.entries.iter()
+            .filter(|entry| entry.file == old.file && entry.text == old.text && offset < entry.len as MemoryAddress)
+            .min_by_key(|entry| (entry.line as isize - old.line as isize).abs())
+            .map(|entry| entry.addr + offset)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Build {
+    pub rom: Vec<u8>,
+    // Label addresses, including one per asset.
+    pub labels: BTreeMap<String, MemoryAddress>,
+    pub constants: BTreeMap<String, u16>,
+    pub source_map: SourceMap,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Build {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    // Addresses to label names, for `Emulator::call_stack`.
+    pub fn symbols(&self) -> BTreeMap<MemoryAddress, String> {
+        let mut symbols = BTreeMap::new();
+        for (name, addr) in &self.labels {
+            symbols.entry(*addr).or_insert_with(|| name.clone());
+        }
+        symbols
+    }
+
+    // A fresh emulator for the project's platform and quirks with the ROM
+    // loaded and ready to run.
+    pub fn emulator(&self, project: &Project, seed: u64) -> Result<Emulator> {
+        let mut emulator = Emulator::new(seed, Quirks::default());
+        emulator.set_platform(project.platform())?;
+        emulator.set_quirks(project.quirks());
+        emulator.load_program(&self.rom)?;
+        Ok(emulator)
+    }
+}
+
+// A chunk of the image before linking: assembled source or converted asset.
+enum Unit {
+    Source { file: PathBuf, source: String, parsed: ParsedSource },
+    Asset { asset: Asset, bytes: Vec<u8> }
+}
+
+fn convert_asset(project: &Project, asset: &Asset) -> Result<Vec<u8>> {
+    let path = project.resolve(&asset.path);
+    match asset.kind {
+        AssetKind::Sprite => Ok(Sprite::from_db_lines(asset.width.unwrap_or(8), &fs::read_to_string(path)?)?.to_bytes()),
+        AssetKind::Font => Ok(Font::load(&path)?.to_bytes()),
+        AssetKind::Audio => {
+            let bytes = fs::read(path)?;
+            if bytes.len() != 16 {
+                return Err(format!("An audio pattern is 16 bytes, got {}", bytes.len()).into());
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+// Assembles every source in manifest order, then appends the assets, all
+// sharing one label namespace. Nothing is written to disk.
+pub fn build(project: &Project) -> Build {
+    let mut build = Build::default();
+    let mut units = vec![];
+    for file in &project.manifest().sources {
+        match fs::read_to_string(project.resolve(file)) {
+            Ok(source) => {
+                let parsed = assembler::parse(&source);
+                build.diagnostics.extend(parsed.diagnostics.iter().map(|diagnostic| diagnostic.clone().in_file(file.clone())));
+                units.push(Unit::Source { file: file.clone(), source, parsed });
+            },
+            Err(e) => build.diagnostics.push(Diagnostic::error(None, e.to_string()).in_file(file.clone()))
+        }
+    }
+    for asset in &project.manifest().assets {
+        match convert_asset(project, asset) {
+            Ok(bytes) => units.push(Unit::Asset { asset: asset.clone(), bytes }),
+            Err(e) => build.diagnostics.push(Diagnostic::error(None, format!("Asset '{}': {}", asset.name, e)).in_file(asset.path.clone()))
+        }
+    }
+
+    // Pass 1: lay everything out and collect label addresses.
+    let platform = project.platform();
+    let mut addr = platform.load_address as usize;
+    let mut definitions: BTreeMap<String, (Option<PathBuf>, Option<Span>)> = BTreeMap::new();
+    // Labels, constants and assets share a namespace; false if `name` is
+    // taken. `kind` names what the new definition is.
+    let mut define = |build: &mut Build, kind: &str, name: &str, file: Option<&PathBuf>, span: Option<Span>| {
+        if let Some((first_file, _)) = definitions.get(name) {
+            let first = first_file.as_ref().map_or_else(|| String::from("the assets"), |file| file.display().to_string());
+            let diagnostic = Diagnostic::error(span, format!("{} '{}' is already defined in {}", kind, name, first));
+            build.diagnostics.push(match file {
+                Some(file) => diagnostic.in_file(file.clone()),
+                None => diagnostic
+            });
+            return false;
+        }
+        definitions.insert(name.to_string(), (file.cloned(), span));
+        true
+    };
+    for unit in &units {
+        match unit {
+            Unit::Source { file, parsed, .. } => {
+                for statement in &parsed.statements {
+                    match &statement.kind {
+                        assembler::StatementKind::Label(name) if define(&mut build, "Label", name, Some(file), Some(statement.span)) => {
+                            build.labels.insert(name.clone(), addr as MemoryAddress);
+                        },
+                        assembler::StatementKind::Constant { name, value } if define(&mut build, "Constant", name, Some(file), Some(statement.span)) => {
+                            build.constants.insert(name.clone(), *value);
+                        },
+                        _ => {}
+                    }
+                    addr += statement.size();
+                }
+            },
+            Unit::Asset { asset, bytes } => {
+                if define(&mut build, "Asset", &asset.name, None, None) {
+                    build.labels.insert(asset.name.clone(), addr as MemoryAddress);
+                }
+                addr += bytes.len();
+            }
+        }
+    }
+
+    // Pass 2: encode with every label known.
+    let labels = build.labels.clone();
+    let constants = build.constants.clone();
+    let resolve = |name: &str| labels.get(name).or_else(|| constants.get(name)).copied();
+    let mut addr = platform.load_address as usize;
+    for unit in &units {
+        match unit {
+            Unit::Source { file, source, parsed } => {
+                for statement in &parsed.statements {
+                    let bytes = match assembler::encode(statement, &resolve) {
+                        Ok(bytes) => bytes,
+                        Err(diagnostic) => {
+                            build.diagnostics.push(diagnostic.in_file(file.clone()));
+                            vec![0; statement.size()]
+                        }
+                    };
+                    if !bytes.is_empty() {
+                        let span = statement.span;
+                        let text = source.lines().nth(span.line).and_then(|line| line.get(span.start..span.end)).unwrap_or("").to_string();
+                        build.source_map.entries.push(SourceMapEntry { addr: addr as MemoryAddress, len: bytes.len(), file: file.clone(), line: span.line, text });
+                    }
+                    addr += bytes.len();
+                    build.rom.extend(bytes);
+                }
+            },
+            Unit::Asset { asset, bytes } => {
+                build.source_map.entries.push(SourceMapEntry { addr: addr as MemoryAddress, len: bytes.len(), file: asset.path.clone(), line: 0, text: asset.name.clone() });
+                addr += bytes.len();
+                build.rom.extend(bytes);
+            }
+        }
+    }
+
+    if let Err(e) = Program::new(&platform).save_bytes(&build.rom) {
+        build.diagnostics.push(Diagnostic::error(None, e.to_string()).in_file(project.manifest().output.clone()));
+    }
+    build
+}
+
+fn write_output(project: &mut Project, build: &mut Build) -> bool {
+    let output = project.resolve(&project.manifest().output);
+    let written = output.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&output, &build.rom));
+    if let Err(e) = written {
+        build.diagnostics.push(Diagnostic::error(None, e.to_string()).in_file(project.manifest().output.clone()));
+        return false;
+    }
+    project.mark_built();
+    true
+}
+
+// Builds the project and, if that succeeds, writes the ROM to the
+// manifest's output path and returns a fresh emulator running it. Either way
+// the build carries the diagnostics.
+pub fn build_and_load(project: &mut Project, seed: u64) -> (Build, Option<Emulator>) {
+    let mut build = build(project);
+    if build.has_errors() {
+        return (build, None);
+    }
+    if !write_output(project, &mut build) {
+        return (build, None);
+    }
+    match build.emulator(project, seed) {
+        Ok(emulator) => (build, Some(emulator)),
+        Err(e) => {
+            build.diagnostics.push(Diagnostic::error(None, e.to_string()));
+            (build, None)
+        }
+    }
+}
+
+// Rebuilds the project and patches the new ROM into the running session
+// without resetting it. `previous` is the build the session is running; its
+// source map says which statement the PC, I and each return address were in
+// so they can follow that statement to its new address. Nothing is patched
+// if the build fails or the PC or a caller sits in code that was edited away.
+pub fn hot_reload(project: &mut Project, session: &mut Session, previous: &Build) -> (Build, Result<()>) {
+    let mut build = build(project);
+    if build.has_errors() {
+        return (build, Err(Box::new(ReloadError::BuildFailed)));
+    }
+    let emulator = session.emulator();
+    // PC and I go wherever the statement they point into went. A return
+    // address follows its CALL, two bytes before it, even when it's also the
+    // start of another statement that moved elsewhere.
+    let mut moved = BTreeMap::new();
+    let mut calls = BTreeMap::new();
+    let pc = emulator.pc();
+    match previous.source_map.remap(&build.source_map, pc) {
+        Some(new) => moved.insert(pc, new),
+        None => return (build, Err(Box::new(ReloadError::CodeRemoved(pc))))
+    };
+    for call in emulator.stack().into_iter().map(|return_address| return_address.wrapping_sub(2)) {
+        match previous.source_map.remap(&build.source_map, call) {
+            Some(new) => calls.insert(call, new),
+            None => return (build, Err(Box::new(ReloadError::CodeRemoved(call))))
+        };
+    }
+    let i = emulator.i();
+    if let Some(new) = previous.source_map.remap(&build.source_map, i) {
+        moved.insert(i, new);
+    }
+    let remap = |addr: MemoryAddress| moved.get(&addr).copied().unwrap_or(addr);
+    let remap_return = |addr: MemoryAddress| calls.get(&addr.wrapping_sub(2))
+        .map(|call| call.wrapping_add(2))
+        .unwrap_or(addr);
+    if let Err(e) = session.hot_reload(&build.rom, &remap, &remap_return) {
+        return (build, Err(e));
+    }
+    write_output(project, &mut build);
+    (build, Ok(()))
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    BuildFailed,
+    // The PC or a CALL still on the stack was in a statement that's gone.
+    CodeRemoved(MemoryAddress)
+}
+
+impl Display for ReloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReloadError::BuildFailed => write!(f, "The project failed to build"),
+            ReloadError::CodeRemoved(addr) => write!(f, "Can't reload: the code running at {:#05x} was changed or removed", addr)
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A project in a fresh directory with the given sources, in order.
+    fn project(name: &str, sources: &[(&str, &str)]) -> Project {
+        let dir = std::env::temp_dir().join(format!("lucid8-build-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut project = Project::new(&dir.join("lucid8.toml"), name);
+        for (file, source) in sources {
+            fs::write(dir.join(file), source).unwrap();
+            project.add_source(Path::new(file)).unwrap();
+        }
+        project
+    }
+
+    fn messages(build: &Build) -> Vec<(Option<PathBuf>, String)> {
+        build.diagnostics.iter().map(|diagnostic| (diagnostic.file.clone(), diagnostic.message.clone())).collect()
+    }
+
+    #[test]
+    fn sources_are_laid_out_in_manifest_order_with_shared_labels() {
+        let project = project("layout", &[
+            ("main.asm", "CALL draw\nJP end\n"),
+            ("gfx.asm", "draw:\nRET\nend:\nJP end\n")
+        ]);
+        let build = build(&project);
+        fs::remove_dir_all(project.root()).unwrap();
+        assert_eq!(messages(&build), vec![]);
+        assert_eq!(build.labels, BTreeMap::from([(String::from("draw"), 0x204), (String::from("end"), 0x206)]));
+        assert_eq!(build.rom, vec![0x22, 0x04, 0x12, 0x06, 0x00, 0xEE, 0x12, 0x06]);
+        let entry = build.source_map.lookup(0x205).unwrap();
+        assert_eq!((entry.addr, entry.file.as_path(), entry.line, entry.text.as_str()), (0x204, Path::new("gfx.asm"), 1, "RET"));
+        assert_eq!(build.source_map.address_of(Path::new("main.asm"), 1), Some(0x202));
+    }
+
+    #[test]
+    fn assets_are_converted_and_labelled_after_the_sources() {
+        let mut project = project("assets", &[("main.asm", "LD I, ship\nJP beep\n")]);
+        fs::write(project.resolve(Path::new("ship.db")), "ship:\n    db 0b00111100\n    db 0b11111111\n").unwrap();
+        fs::write(project.resolve(Path::new("beep.bin")), [0xF0; 16]).unwrap();
+        project.add_asset(Asset { name: String::from("ship"), kind: AssetKind::Sprite, path: PathBuf::from("ship.db"), width: None }).unwrap();
+        project.add_asset(Asset { name: String::from("beep"), kind: AssetKind::Audio, path: PathBuf::from("beep.bin"), width: None }).unwrap();
+        let built = build(&project);
+        assert_eq!(messages(&built), vec![]);
+        assert_eq!(built.labels[&String::from("ship")], 0x204);
+        assert_eq!(built.labels[&String::from("beep")], 0x206);
+        assert_eq!(built.rom[..6], [0xA2, 0x04, 0x12, 0x06, 0x3C, 0xFF]);
+        assert_eq!(built.rom[6..], [0xF0; 16]);
+        assert_eq!(built.source_map.lookup(0x210).unwrap().text, "beep");
+
+        fs::write(project.resolve(Path::new("beep.bin")), [0xF0; 3]).unwrap();
+        let built = build(&project);
+        fs::remove_dir_all(project.root()).unwrap();
+        assert_eq!(messages(&built), vec![
+            (Some(PathBuf::from("beep.bin")), String::from("Asset 'beep': An audio pattern is 16 bytes, got 3")),
+            (Some(PathBuf::from("main.asm")), String::from("Undefined label or constant 'beep'"))
+        ]);
+    }
+
+    #[test]
+    fn duplicate_definitions_name_their_kind_and_first_file() {
+        let mut project = project("duplicates", &[
+            ("a.asm", "start:\nspeed = 3\nCLS\n"),
+            ("b.asm", "start:\nspeed = 4\nRET\n")
+        ]);
+        fs::write(project.resolve(Path::new("start.db")), "db 0xFF\n").unwrap();
+        project.add_asset(Asset { name: String::from("start"), kind: AssetKind::Sprite, path: PathBuf::from("start.db"), width: None }).unwrap();
+        let build = build(&project);
+        fs::remove_dir_all(project.root()).unwrap();
+        assert_eq!(messages(&build), vec![
+            (Some(PathBuf::from("b.asm")), String::from("Label 'start' is already defined in a.asm")),
+            (Some(PathBuf::from("b.asm")), String::from("Constant 'speed' is already defined in a.asm")),
+            (None, String::from("Asset 'start' is already defined in a.asm"))
+        ]);
+        assert_eq!(build.diagnostics[1].span, Some(Span { line: 1, start: 0, end: 5 }));
+        // The first definitions stand.
+        assert_eq!(build.labels[&String::from("start")], 0x200);
+        assert_eq!(build.constants[&String::from("speed")], 3);
+    }
+
+    #[test]
+    fn images_larger_than_the_program_space_are_reported_against_the_output() {
+        // One word past the 0xE00 bytes CHIP-8 leaves for programs.
+        let source = "DW 0\n".repeat(0xE00 / 2 + 1);
+        let mut project = project("overflow", &[("main.asm", &source)]);
+        let (build, emulator) = build_and_load(&mut project, 0);
+        let written = project.resolve(&project.manifest().output).exists();
+        fs::remove_dir_all(project.root()).unwrap();
+        assert_eq!(messages(&build), vec![
+            (Some(PathBuf::from("build/game.ch8")), String::from("Program too large! Max Bytes: [3584], Given: [3586]"))
+        ]);
+        assert!(emulator.is_none());
+        assert!(!written);
+    }
+
+    #[test]
+    fn reloading_inside_a_subroutine_keeps_the_return_address_after_its_call() {
+        let dir = std::env::temp_dir().join(format!("lucid8-hot-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.asm");
+        // `sub` starts right after the CALL, so its first statement and the
+        // return address share 0x202.
+        fs::write(&source, "CALL sub\nsub:\nADD V0, 1\nRET\n").unwrap();
+        let mut project = Project::new(&dir.join("lucid8.toml"), "reload");
+        project.add_source(Path::new("main.asm")).unwrap();
+        let (previous, _) = build_and_load(&mut project, 0);
+        let mut session = Session::default();
+        session.load_rom(&previous.rom).unwrap();
+        session.step_instruction().unwrap();
+        assert_eq!(session.emulator().pc(), 0x202);
+        assert_eq!(session.emulator().stack(), vec![0x202]);
+
+        fs::write(&source, "CALL sub\nLD V1, 7\nsub:\nADD V0, 1\nRET\n").unwrap();
+        let (_, reloaded) = hot_reload(&mut project, &mut session, &previous);
+        fs::remove_dir_all(&dir).unwrap();
+        reloaded.unwrap();
+        assert_eq!(session.emulator().pc(), 0x204);
+        assert_eq!(session.emulator().stack(), vec![0x202]);
+
+        session.step_instruction().unwrap();
+        session.step_instruction().unwrap();
+        assert_eq!(session.emulator().pc(), 0x202);
+        session.step_instruction().unwrap();
+        assert_eq!(session.emulator().registers()[1], 7);
+    }
+}
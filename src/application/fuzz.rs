@@ -0,0 +1,333 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::emulator::emulator::{Emulator, MemoryAddress, Result};
+use crate::emulator::instructions::Instruction;
+use crate::emulator::platform::{Platform, PlatformProfile};
+use crate::emulator::quirks::{QuirkProfile, Quirks};
+use crate::emulator::reference::Reference;
+
+// Steps between timer ticks, as in a session.
+const CYCLES_PER_FRAME: usize = 10;
+const QUIRK_PROFILES: [QuirkProfile; 4] = [QuirkProfile::Vip, QuirkProfile::Chip48, QuirkProfile::Schip, QuirkProfile::XoChip];
+const PLATFORMS: [PlatformProfile; 2] = [PlatformProfile::Chip8, PlatformProfile::XoChip];
+
+// One generated program and the machine it runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub program: Vec<u8>,
+    pub seed: u64,
+    pub quirks: QuirkProfile,
+    pub platform: PlatformProfile
+}
+
+impl Case {
+    // A random program of `length` instructions. Most opcodes decode, jumps,
+    // calls and LD I aim into the program so runs stay in it, and some
+    // stores overwrite the program itself.
+    pub fn generate(seed: u64, length: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let platform = PLATFORMS[rng.gen_range(0..PLATFORMS.len())];
+        let load_address = Platform::from(platform).load_address;
+        let target = |rng: &mut StdRng| load_address + 2 * rng.gen_range(0..length.max(1)) as MemoryAddress;
+        let mut opcodes = Vec::with_capacity(length);
+        while opcodes.len() < length {
+            let opcode = match rng.gen_range(0..100) {
+                // Valid but rare in a random u16. RET mostly faults on an
+                // empty stack, ending the run, so it stays rare.
+                0 => 0x00EE,
+                1 => 0x00E0,
+                2..=4 => {
+                    opcodes.push(0xA000 | target(&mut rng));
+                    let x = rng.gen_range(0..16) << 8;
+                    if rng.gen() { 0xF055 | x } else { 0xF033 | x }
+                },
+                _ => loop {
+                    let opcode: u16 = rng.gen();
+                    if Instruction::try_from(opcode).is_ok() || rng.gen_ratio(1, 50) {
+                        break opcode;
+                    }
+                }
+            };
+            let opcode = match opcode >> 12 {
+                0x1 | 0x2 | 0xB => (opcode & 0xF000) | target(&mut rng),
+                0xA if rng.gen() => 0xA000 | target(&mut rng),
+                _ => opcode
+            };
+            opcodes.push(opcode);
+        }
+        opcodes.truncate(length);
+        Self {
+            program: opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect(),
+            seed,
+            quirks: QUIRK_PROFILES[rng.gen_range(0..QUIRK_PROFILES.len())],
+            platform
+        }
+    }
+
+    fn emulator(&self) -> Result<Emulator> {
+        let mut emulator = Emulator::new(self.seed, Quirks::from(self.quirks));
+        emulator.set_platform(self.platform.into())?;
+        emulator.load_program(&self.program)?;
+        Ok(emulator)
+    }
+
+    // The program as an assembly listing, undecodable words as `dw`.
+    pub fn disassemble(&self) -> String {
+        let load_address = Platform::from(self.platform).load_address;
+        self.program.chunks(2).enumerate().map(|(index, word)| {
+            let addr = load_address as usize + index * 2;
+            let text = Instruction::try_from(word).map_or_else(|_| format!("dw {:#06x}", u16::from_be_bytes([word[0], word[1]])), |instruction| instruction.to_string());
+            format!("{:#05x}  {:02X}{:02X}  {}\n", addr, word[0], word[1], text)
+        }).collect()
+    }
+}
+
+impl Display for Case {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "seed {}, {:?} quirks on {:?}, {} bytes", self.seed, self.quirks, self.platform, self.program.len())
+    }
+}
+
+// The first point where the emulator and the reference disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize,
+    // Where the instruction that caused it was.
+    pub pc: MemoryAddress,
+    pub instruction: String,
+    pub field: String,
+    pub emulator: String,
+    pub reference: String
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "step {}, {:#05x} {}: {} is {} in the emulator but {} in the reference",
+            self.step, self.pc, self.instruction, self.field, self.emulator, self.reference)
+    }
+}
+
+fn differ<T: PartialEq + std::fmt::Debug>(field: &str, emulator: T, reference: T) -> Option<(String, String, String)> {
+    if emulator == reference {
+        return None;
+    }
+    Some((field.to_string(), format!("{:x?}", emulator), format!("{:x?}", reference)))
+}
+
+// Everything observable, in a fixed order so the first difference found is
+// the one reported.
+fn compare(emulator: &Emulator, reference: &Reference) -> Option<(String, String, String)> {
+    let registers = emulator.registers();
+    let display = emulator.display();
+    differ("PC", emulator.pc(), reference.pc)
+        .or_else(|| differ("I", emulator.i(), reference.i))
+        .or_else(|| (0..16).find_map(|x| differ(&format!("V{:X}", x), registers[x], reference.v[x])))
+        .or_else(|| differ("the stack", emulator.stack(), reference.stack.clone()))
+        .or_else(|| differ("the delay timer", emulator.delay_timer(), reference.delay))
+        .or_else(|| differ("the sound timer", emulator.sound_timer(), reference.sound))
+        .or_else(|| differ("waiting for a key", emulator.is_waiting_for_key(), reference.waiting))
+        .or_else(|| differ("the audio pattern", emulator.audio_pattern(), reference.audio_pattern))
+        .or_else(|| differ("the pitch", emulator.pitch(), reference.pitch))
+        .or_else(|| {
+            let memory = emulator.memory().as_bytes();
+            if memory == reference.memory.as_slice() {
+                return None;
+            }
+            let addr = memory.iter().zip(&reference.memory).position(|(a, b)| a != b)?;
+            differ(&format!("memory at {:#06x}", addr), memory[addr], reference.memory[addr])
+        })
+        .or_else(|| {
+            let (x, y, lit) = display.pixels().find(|(x, y, lit)| *lit != reference.display[*y as usize][*x as usize])?;
+            differ(&format!("pixel ({}, {})", x, y), lit, !lit)
+        })
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("unknown panic"))
+}
+
+// Runs the case on both interpreters in lock step for up to `steps`
+// instructions. Faulting (or panicking) counts as state, so the run ends
+// quietly when both fault on the same step.
+pub fn run_case(case: &Case, steps: usize) -> Result<Option<Divergence>> {
+    run_with(case, steps, &|_, _| {})
+}
+
+// Like `run_case`, calling `after_step` with the PC the reference stepped
+// from after each of its instructions, so tests can plant bugs in it.
+fn run_with(case: &Case, steps: usize, after_step: &dyn Fn(MemoryAddress, &mut Reference)) -> Result<Option<Divergence>> {
+    let mut emulator = case.emulator()?;
+    let mut reference = Reference::new(&emulator);
+    for step in 0..steps {
+        let pc = emulator.pc();
+        let instruction = emulator.instruction_at(pc).map_or_else(|| String::from("(invalid)"), |instruction| instruction.to_string());
+        let diverged = |(field, emulator, reference): (String, String, String)| Some(Divergence { step, pc, instruction: instruction.clone(), field, emulator, reference });
+        let ran = panic::catch_unwind(AssertUnwindSafe(|| emulator.step()))
+            .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(payload)).into()));
+        let reference_pc = reference.pc;
+        let expected = reference.step();
+        if expected.is_ok() {
+            after_step(reference_pc, &mut reference);
+        }
+        match (ran, expected) {
+            (Ok(()), Ok(())) => {},
+            (Err(_), Err(_)) => return Ok(None),
+            (ran, expected) => {
+                let outcome = |result: Result<()>| result.map_or_else(|e| format!("a fault ({})", e), |_| String::from("fine"));
+                return Ok(diverged((String::from("the step"), outcome(ran), outcome(expected))));
+            }
+        }
+        if (step + 1) % CYCLES_PER_FRAME == 0 {
+            emulator.tick_timers();
+            reference.tick_timers();
+        }
+        if let Some(difference) = compare(&emulator, &reference) {
+            return Ok(diverged(difference));
+        }
+    }
+    Ok(None)
+}
+
+// `LD V0, V0`, which changes nothing.
+const FILLER: [u8; 2] = [0x80, 0x00];
+
+// Shrinks a diverging case while the same field still diverges. Runs of
+// instructions are dropped, halving the run length down to single
+// instructions; since that moves jump targets, what's left is then blanked
+// out one instruction at a time, and both repeat until neither helps.
+pub fn minimize(case: &Case, divergence: &Divergence, steps: usize) -> Result<(Case, Divergence)> {
+    shrink(case, divergence, &|candidate| run_case(candidate, steps))
+}
+
+// `minimize` with the runs done by `run`.
+fn shrink(case: &Case, divergence: &Divergence, run: &dyn Fn(&Case) -> Result<Option<Divergence>>) -> Result<(Case, Divergence)> {
+    let mut best = (case.clone(), divergence.clone());
+    let attempt = |best: &mut (Case, Divergence), candidate: Case| -> Result<bool> {
+        match run(&candidate)? {
+            Some(found) if found.field == divergence.field && !candidate.program.is_empty() => {
+                *best = (candidate, found);
+                Ok(true)
+            },
+            _ => Ok(false)
+        }
+    };
+    loop {
+        let before = best.0.program.clone();
+        // In instructions, two bytes each.
+        let mut chunk = (best.0.program.len() / 4).max(1);
+        loop {
+            let mut start = 0;
+            while start < best.0.program.len() {
+                let mut candidate = best.0.clone();
+                let end = (start + chunk * 2).min(candidate.program.len());
+                candidate.program.drain(start..end);
+                if !attempt(&mut best, candidate)? {
+                    start += chunk * 2;
+                }
+            }
+            if chunk == 1 {
+                break;
+            }
+            chunk /= 2;
+        }
+        for start in (0..best.0.program.len()).step_by(2) {
+            if best.0.program[start..start + 2] != FILLER {
+                let mut candidate = best.0.clone();
+                candidate.program[start..start + 2].copy_from_slice(&FILLER);
+                attempt(&mut best, candidate)?;
+            }
+        }
+        if best.0.program == before {
+            return Ok(best);
+        }
+    }
+}
+
+// Runs `runs` random cases, the nth from `seed + n`, and returns the first
+// that diverges, minimized.
+pub fn fuzz(seed: u64, runs: u64, length: usize, steps: usize) -> Result<Option<(Case, Divergence)>> {
+    for run in 0..runs {
+        let case = Case::generate(seed.wrapping_add(run), length);
+        if let Some(divergence) = run_case(&case, steps)? {
+            return minimize(&case, &divergence, steps).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_short_seeded_run_finds_no_divergence() {
+        assert_eq!(fuzz(1, 50, 32, 300).unwrap(), None);
+    }
+
+    // A reference whose SHR flips VF, as if it had the flag wrong.
+    fn flip_shr_flag(pc: MemoryAddress, reference: &mut Reference) {
+        let opcode = u16::from_be_bytes([reference.memory[pc as usize], reference.memory[pc as usize + 1]]);
+        if opcode & 0xF00F == 0x8006 {
+            reference.v[0xF] ^= 1;
+        }
+    }
+
+    fn case(program: &[u8]) -> Case {
+        Case { program: program.to_vec(), seed: 0, quirks: QuirkProfile::Chip48, platform: PlatformProfile::Chip8 }
+    }
+
+    #[test]
+    fn divergences_are_reported_by_field() {
+        let case = case(&[
+            0x60, 0x03, // LD V0, 3
+            0x71, 0x01, // ADD V1, 1
+            0x80, 0x06, // SHR V0
+            0x12, 0x02  // JP 0x202
+        ]);
+        assert_eq!(run_case(&case, 20).unwrap(), None);
+        let divergence = run_with(&case, 20, &flip_shr_flag).unwrap().unwrap();
+        assert_eq!(divergence, Divergence {
+            step: 2,
+            pc: 0x204,
+            instruction: String::from("SHR V0"),
+            field: String::from("VF"),
+            emulator: String::from("1"),
+            reference: String::from("0")
+        });
+        assert_eq!(divergence.to_string(), "step 2, 0x204 SHR V0: VF is 1 in the emulator but 0 in the reference");
+    }
+
+    #[test]
+    fn minimizing_keeps_the_diverging_field() {
+        let case = case(&[
+            0x60, 0x05, // LD V0, 5
+            0x61, 0x07, // LD V1, 7
+            0xA3, 0x00, // LD I, 0x300
+            0x82, 0x14, // ADD V2, V1
+            0x80, 0x06, // SHR V0
+            0xF1, 0x55, // LD [I], V1
+            0x70, 0x01, // ADD V0, 1
+            0x12, 0x00  // JP 0x200
+        ]);
+        let run = |candidate: &Case| run_with(candidate, 50, &flip_shr_flag);
+        let divergence = run(&case).unwrap().unwrap();
+        let (minimized, found) = shrink(&case, &divergence, &run).unwrap();
+        assert!(minimized.program.len() < case.program.len());
+        assert_eq!(minimized.program, vec![0x80, 0x06]);
+        assert_eq!(found.field, divergence.field);
+        assert_eq!(run(&minimized).unwrap(), Some(found));
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        assert_eq!(Case::generate(7, 16), Case::generate(7, 16));
+        assert_eq!(Case::generate(7, 16).program.len(), 32);
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::emulator::emulator::{Emulator, MemoryAddress, RegisterAddress, Result};
+use crate::emulator::platform::{Platform, PlatformProfile};
+use crate::emulator::quirks::{QuirkProfile, Quirks};
+use crate::input::keypad::Key;
+use super::app::Project;
+use super::assembler::parse_number;
+use super::build;
+
+fn default_max_cycles() -> usize {
+    10_000
+}
+
+// An address written as a number or as a label or constant name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Address {
+    Number(MemoryAddress),
+    Symbol(String)
+}
+
+impl From<&str> for Address {
+    fn from(symbol: &str) -> Self {
+        Address::Symbol(symbol.to_string())
+    }
+}
+
+impl From<MemoryAddress> for Address {
+    fn from(addr: MemoryAddress) -> Self {
+        Address::Number(addr)
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Number(addr) => write!(f, "{:#05x}", addr),
+            Address::Symbol(symbol) => write!(f, "{}", symbol)
+        }
+    }
+}
+
+// Machine state a test sets before the call or checks after it. Registers
+// are keyed `V0` to `VF`, memory by address or label (a TOML key is always a
+// string, so numbers are written `"0x300"`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct MachineState {
+    #[serde(default)]
+    pub registers: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub i: Option<Address>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Vec<u8>>
+}
+
+// What a test expects once the routine returns. `display` rows are compared
+// from the top left corner, `#` for a lit pixel and `.` for a dark one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Expectation {
+    #[serde(flatten)]
+    pub state: MachineState,
+    #[serde(default)]
+    pub display: Vec<String>
+}
+
+// One subroutine call: set up, CALL `call`, run until the matching RET.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RoutineTest {
+    pub name: String,
+    pub call: Address,
+    #[serde(default = "default_max_cycles")]
+    pub max_cycles: usize,
+    #[serde(flatten)]
+    pub setup: MachineState,
+    // Keys held down for the whole call.
+    #[serde(default)]
+    pub keys: Vec<Key>,
+    #[serde(default)]
+    pub expect: Expectation
+}
+
+impl RoutineTest {
+    pub fn new(name: &str, call: impl Into<Address>) -> Self {
+        Self {
+            name: name.to_string(),
+            call: call.into(),
+            max_cycles: default_max_cycles(),
+            setup: MachineState::default(),
+            keys: vec![],
+            expect: Expectation::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestOutcome {
+    pub name: String,
+    pub cycles: usize,
+    // Empty when the test passed.
+    pub failures: Vec<String>
+}
+
+impl TestOutcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for TestOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.passed() {
+            return write!(f, "ok {} ({} cycles)", self.name, self.cycles);
+        }
+        write!(f, "FAILED {}", self.name)?;
+        for failure in &self.failures {
+            write!(f, "\n    {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+fn register_index(name: &str) -> Option<RegisterAddress> {
+    let digit = name.strip_prefix('V').or_else(|| name.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// Runs routines of one ROM in isolation. Each test gets a freshly loaded
+// machine; timers don't tick, since no frames pass during a call.
+#[derive(Debug, Clone)]
+pub struct Harness {
+    rom: Vec<u8>,
+    platform: Platform,
+    quirks: Quirks,
+    // Label and constant values.
+    symbols: BTreeMap<String, u16>,
+    seed: u64
+}
+
+impl Harness {
+    pub fn new(rom: &[u8], platform: Platform, quirks: Quirks) -> Self {
+        Self { rom: rom.to_vec(), platform, quirks, symbols: BTreeMap::new(), seed: 0 }
+    }
+
+    // Builds the project so tests can call its labels by name.
+    pub fn for_project(project: &Project) -> Result<Self> {
+        let build = build::build(project);
+        if build.has_errors() {
+            let diagnostics: Vec<String> = build.diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+            return Err(format!("{} failed to build\n{}", project.manifest().name, diagnostics.join("\n")).into());
+        }
+        let mut harness = Harness::new(&build.rom, project.platform(), project.quirks());
+        harness.symbols.extend(build.constants);
+        harness.symbols.extend(build.labels);
+        Ok(harness)
+    }
+
+    // The RNG seed every test starts from.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn resolve(&self, addr: &Address) -> Result<MemoryAddress> {
+        match addr {
+            Address::Number(addr) => Ok(*addr),
+            Address::Symbol(symbol) => parse_number(symbol)
+                .or_else(|| self.symbols.get(symbol).copied())
+                .ok_or_else(|| format!("Undefined label or constant '{}'", symbol).into())
+        }
+    }
+
+    fn emulator(&self) -> Result<Emulator> {
+        let mut emulator = Emulator::new(self.seed, self.quirks);
+        emulator.set_platform(self.platform)?;
+        emulator.load_program(&self.rom)?;
+        Ok(emulator)
+    }
+
+    fn set_up(&self, emulator: &mut Emulator, test: &RoutineTest) -> Result<()> {
+        let setup = &test.setup;
+        for (name, value) in &setup.registers {
+            emulator.set_register(register_index(name).ok_or_else(|| format!("No register {}", name))?, *value)?;
+        }
+        if let Some(i) = &setup.i {
+            emulator.set_i(self.resolve(i)?);
+        }
+        for (addr, bytes) in &setup.memory {
+            let addr = self.resolve(&Address::Symbol(addr.clone()))?;
+            emulator.memory_mut().poke(addr, bytes)?;
+        }
+        for key in &test.keys {
+            emulator.keypad_mut().press(*key)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, emulator: &Emulator, expect: &Expectation) -> Result<Vec<String>> {
+        let mut failures = vec![];
+        for (name, expected) in &expect.state.registers {
+            let x = register_index(name).ok_or_else(|| format!("No register {}", name))?;
+            let actual = emulator.registers()[x as usize];
+            if actual != *expected {
+                failures.push(format!("{} is {:#04x}, expected {:#04x}", name, actual, expected));
+            }
+        }
+        if let Some(i) = &expect.state.i {
+            let expected = self.resolve(i)?;
+            if emulator.i() != expected {
+                failures.push(format!("I is {:#05x}, expected {} ({:#05x})", emulator.i(), i, expected));
+            }
+        }
+        for (name, expected) in &expect.state.memory {
+            let addr = self.resolve(&Address::Symbol(name.clone()))?;
+            let actual = emulator.memory().get_range(addr, expected.len())?;
+            if let Some(offset) = actual.iter().zip(expected).position(|(actual, expected)| actual != expected) {
+                failures.push(format!("{:#05x} is {:#04x}, expected {:#04x} ({} + {})",
+                    addr as usize + offset, actual[offset], expected[offset], name, offset));
+            }
+        }
+        let display = emulator.display();
+        for (y, row) in expect.display.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let lit = match c {
+                    '#' => true,
+                    '.' => false,
+                    _ => return Err(format!("Display rows use '#' and '.', got '{}'", c).into())
+                };
+                if x >= display.width as usize || y >= display.height as usize {
+                    return Err(format!("Pixel ({}, {}) is off the {}x{} display", x, y, display.width, display.height).into());
+                }
+                if display.pixel(x as u8, y as u8) != lit {
+                    failures.push(format!("Pixel ({}, {}) is {}, expected {}", x, y,
+                        if lit { "dark" } else { "lit" }, if lit { "lit" } else { "dark" }));
+                }
+            }
+        }
+        Ok(failures)
+    }
+
+    // Runs one test. A malformed test, a routine that faults or one that
+    // doesn't return in `max_cycles` all fail rather than erroring.
+    pub fn run(&self, test: &RoutineTest) -> TestOutcome {
+        let mut outcome = TestOutcome { name: test.name.clone(), cycles: 0, failures: vec![] };
+        let result = self.emulator().and_then(|mut emulator| {
+            self.set_up(&mut emulator, test)?;
+            emulator.call(self.resolve(&test.call)?)?;
+            let depth = emulator.stack_depth();
+            while emulator.stack_depth() >= depth {
+                if outcome.cycles == test.max_cycles {
+                    return Err(format!("{} didn't return within {} cycles (PC at {:#05x})", test.call, test.max_cycles, emulator.pc()).into());
+                }
+                let pc = emulator.pc();
+                emulator.step().map_err(|e| format!("{} at {:#05x}", e, pc))?;
+                outcome.cycles += 1;
+            }
+            self.check(&emulator, &test.expect)
+        });
+        match result {
+            Ok(failures) => outcome.failures = failures,
+            Err(e) => outcome.failures.push(e.to_string())
+        }
+        outcome
+    }
+
+    pub fn run_all(&self, tests: &[RoutineTest]) -> Vec<TestOutcome> {
+        tests.iter().map(|test| self.run(test)).collect()
+    }
+}
+
+// A TOML file of tests for a project or a plain ROM. Paths are relative to
+// the file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Suite {
+    #[serde(default)]
+    pub project: Option<PathBuf>,
+    #[serde(default)]
+    pub rom: Option<PathBuf>,
+    // For a plain ROM; a project uses its manifest's.
+    #[serde(default)]
+    pub platform: Option<PlatformProfile>,
+    #[serde(default)]
+    pub quirks: Option<QuirkProfile>,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default, rename = "test")]
+    pub tests: Vec<RoutineTest>
+}
+
+impl Suite {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut suite: Suite = toml::from_str(&fs::read_to_string(path)?)?;
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        suite.project = suite.project.map(|project| root.join(project));
+        suite.rom = suite.rom.map(|rom| root.join(rom));
+        Ok(suite)
+    }
+
+    pub fn harness(&self) -> Result<Harness> {
+        let mut harness = match (&self.project, &self.rom) {
+            (Some(project), None) => Harness::for_project(&Project::load(project)?)?,
+            (None, Some(rom)) => Harness::new(
+                &fs::read(rom)?,
+                self.platform.map(Platform::from).unwrap_or_default(),
+                self.quirks.map(Quirks::from).unwrap_or_default()
+            ),
+            _ => return Err("A test suite names either a project or a rom".into())
+        };
+        harness.set_seed(self.seed);
+        Ok(harness)
+    }
+
+    pub fn run(&self) -> Result<Vec<TestOutcome>> {
+        Ok(self.harness()?.run_all(&self.tests))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 16] = [
+        0x80, 0x04, // 0x200 double: ADD V0, V0
+        0xA3, 0x00, //       LD I, 0x300
+        0xF0, 0x55, //       LD [I], V0
+        0x00, 0xEE, //       RET
+        0x12, 0x08, // 0x208 hang: JP hang
+        0x60, 0x00, // 0x20A draw: LD V0, 0
+        0xD0, 0x01, //       DRW V0, V0, 1
+        0x00, 0xEE  //       RET
+    ];
+
+    const SUITE: &str = r###"
+rom = "routines.ch8"
+
+[[test]]
+name = "double"
+call = 0x200
+registers = { V0 = 3 }
+expect = { registers = { V0 = 6 }, memory = { "0x300" = [6] } }
+
+[[test]]
+name = "draw"
+call = "0x20a"
+i = "0x300"
+memory = { "0x300" = [0xC0] }
+expect = { display = ["##.", "..."] }
+"###;
+
+    fn harness() -> Harness {
+        Harness::new(&ROM, Platform::default(), Quirks::default())
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lucid8-harness-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_suite_loads_and_passes() {
+        let dir = temp_dir("pass");
+        fs::write(dir.join("routines.ch8"), ROM).unwrap();
+        fs::write(dir.join("tests.toml"), SUITE).unwrap();
+        let outcomes = Suite::load(&dir.join("tests.toml")).and_then(|suite| suite.run());
+        fs::remove_dir_all(&dir).unwrap();
+        let outcomes = outcomes.unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(TestOutcome::passed), "{:?}", outcomes);
+        assert_eq!(outcomes[0].cycles, 4);
+        assert_eq!(outcomes[0].to_string(), "ok double (4 cycles)");
+    }
+
+    #[test]
+    fn mismatches_are_all_reported() {
+        let mut test = RoutineTest::new("double", 0x200);
+        test.setup.registers.insert(String::from("V0"), 3);
+        test.expect.state.registers.insert(String::from("V0"), 7);
+        test.expect.state.memory.insert(String::from("0x2ff"), vec![0, 7]);
+        test.expect.display = vec![String::from("#")];
+        let outcome = harness().run(&test);
+        assert!(!outcome.passed());
+        assert_eq!(outcome.failures, vec![
+            String::from("V0 is 0x06, expected 0x07"),
+            String::from("0x300 is 0x06, expected 0x07 (0x2ff + 1)"),
+            String::from("Pixel (0, 0) is dark, expected lit")
+        ]);
+        assert!(outcome.to_string().starts_with("FAILED double\n    V0 is 0x06"));
+    }
+
+    #[test]
+    fn routines_that_never_return_time_out() {
+        let mut test = RoutineTest::new("hang", 0x208);
+        test.max_cycles = 50;
+        let outcome = harness().run(&test);
+        assert_eq!(outcome.cycles, 50);
+        assert_eq!(outcome.failures, vec![String::from("0x208 didn't return within 50 cycles (PC at 0x208)")]);
+    }
+
+    #[test]
+    fn bad_tests_fail_instead_of_erroring() {
+        let outcome = harness().run(&RoutineTest::new("missing", "nowhere"));
+        assert_eq!(outcome.failures, vec![String::from("Undefined label or constant 'nowhere'")]);
+
+        let mut test = RoutineTest::new("register", 0x200);
+        test.setup.registers.insert(String::from("VG"), 1);
+        assert_eq!(harness().run(&test).failures, vec![String::from("No register VG")]);
+    }
+
+    #[test]
+    fn malformed_suites_are_rejected() {
+        let dir = temp_dir("malformed");
+        let path = dir.join("tests.toml");
+        let load = |text: &str| {
+            fs::write(&path, text).unwrap();
+            Suite::load(&path)
+        };
+        // Not TOML, a test without `call`, a register value out of range.
+        let not_toml = load("rom = \"routines.ch8\"\n[[test]\nname = \"x\"\n");
+        let no_call = load("rom = \"routines.ch8\"\n[[test]]\nname = \"x\"\n");
+        let too_big = load("rom = \"routines.ch8\"\n[[test]]\nname = \"x\"\ncall = 0x200\nregisters = { V0 = 256 }\n");
+        let neither = load("seed = 1\n");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(not_toml.is_err());
+        assert!(no_call.unwrap_err().to_string().contains("missing field `call`"));
+        assert!(too_big.is_err());
+        assert_eq!(neither.unwrap().harness().unwrap_err().to_string(), "A test suite names either a project or a rom");
+    }
+}
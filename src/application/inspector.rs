@@ -0,0 +1,283 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use crate::emulator::emulator::{Emulator, MemoryAddress, Result};
+use crate::emulator::instructions::Instruction;
+use crate::emulator::memory::Memory;
+use crate::frontend::session::Session;
+
+pub const BYTES_PER_ROW: usize = 16;
+pub const PAGE_SIZE: usize = 256;
+
+// What a byte is pointed at by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Highlight {
+    pub pc: bool,
+    pub i: bool,
+    // A return address on the call stack.
+    pub stack: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub addr: MemoryAddress,
+    pub value: u8,
+    pub highlight: Highlight,
+    // Differs from the last `MemoryInspector::mark`.
+    pub changed: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectorRow {
+    pub addr: MemoryAddress,
+    pub cells: Vec<Cell>,
+    pub ascii: String,
+    // Instructions starting in this row, aligned with the PC.
+    pub disassembly: Vec<(MemoryAddress, String)>
+}
+
+// Hex editor state over the emulator's memory: the page being shown and a
+// snapshot to mark bytes changed since the last step.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryInspector {
+    page: usize,
+    baseline: Vec<u8>
+}
+
+impl MemoryInspector {
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn page_count(memory: &Memory) -> usize {
+        memory.as_bytes().len().div_ceil(PAGE_SIZE)
+    }
+
+    pub fn set_page(&mut self, memory: &Memory, page: usize) {
+        self.page = page.min(MemoryInspector::page_count(memory).saturating_sub(1));
+    }
+
+    pub fn next_page(&mut self, memory: &Memory) {
+        self.set_page(memory, self.page + 1);
+    }
+
+    pub fn previous_page(&mut self, memory: &Memory) {
+        self.set_page(memory, self.page.saturating_sub(1));
+    }
+
+    // Turns to the page holding `addr`.
+    pub fn show(&mut self, memory: &Memory, addr: MemoryAddress) {
+        self.set_page(memory, addr as usize / PAGE_SIZE);
+    }
+
+    // Remembers the current contents; later changes are flagged in `rows`.
+    pub fn mark(&mut self, memory: &Memory) {
+        self.baseline = memory.as_bytes().to_vec();
+    }
+
+    pub fn is_changed(&self, memory: &Memory, addr: MemoryAddress) -> bool {
+        let addr = addr as usize;
+        match (self.baseline.get(addr), memory.as_bytes().get(addr)) {
+            (Some(before), Some(now)) => before != now,
+            _ => false
+        }
+    }
+
+    pub fn rows(&self, emulator: &Emulator) -> Vec<InspectorRow> {
+        let memory = emulator.memory();
+        let bytes = memory.as_bytes();
+        let start = (self.page * PAGE_SIZE).min(bytes.len());
+        let end = (start + PAGE_SIZE).min(bytes.len());
+        let stack = emulator.stack();
+        (start..end).step_by(BYTES_PER_ROW).map(|row_start| {
+            let row_end = (row_start + BYTES_PER_ROW).min(end);
+            let cells = (row_start..row_end).map(|addr| {
+                let addr = addr as MemoryAddress;
+                Cell { addr, value: bytes[addr as usize], highlight: MemoryInspector::highlight(emulator, &stack, addr), changed: self.is_changed(memory, addr) }
+            }).collect();
+            let ascii = bytes[row_start..row_end].iter()
+                .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+                .collect();
+            let disassembly = (row_start..row_end)
+                .filter(|addr| (addr ^ emulator.pc() as usize) & 1 == 0)
+                .map(|addr| (addr as MemoryAddress, disassemble(memory, addr as MemoryAddress)))
+                .collect();
+            InspectorRow { addr: row_start as MemoryAddress, cells, ascii, disassembly }
+        }).collect()
+    }
+
+    fn highlight(emulator: &Emulator, stack: &[MemoryAddress], addr: MemoryAddress) -> Highlight {
+        let pc = emulator.pc();
+        Highlight {
+            pc: addr == pc || addr == pc.wrapping_add(1),
+            i: addr == emulator.i(),
+            stack: stack.contains(&addr)
+        }
+    }
+
+    // Next match at or after `from`, wrapping around the end of memory.
+    pub fn find(memory: &Memory, pattern: &[Option<u8>], from: MemoryAddress) -> Option<MemoryAddress> {
+        let bytes = memory.as_bytes();
+        if pattern.is_empty() || pattern.len() > bytes.len() {
+            return None;
+        }
+        let starts = bytes.len() - pattern.len() + 1;
+        (0..starts)
+            .map(|offset| (from as usize + offset) % starts)
+            .find(|start| pattern.iter().zip(&bytes[*start..]).all(|(expected, byte)| expected.is_none_or(|expected| expected == *byte)))
+            .map(|start| start as MemoryAddress)
+    }
+
+    // Steps one instruction, flagging whatever it changes.
+    pub fn step(&mut self, session: &mut Session) -> Result<()> {
+        self.mark(session.emulator().memory());
+        session.step_instruction()
+    }
+
+    pub fn edit(&mut self, session: &mut Session, addr: MemoryAddress, value: u8) -> Result<()> {
+        session.poke(addr, &[value])
+    }
+}
+
+fn disassemble(memory: &Memory, addr: MemoryAddress) -> String {
+    match memory.get_range(addr, 2) {
+        Ok(bytes) => Instruction::try_from(bytes).map(|instruction| instruction.to_string()).unwrap_or_else(|_| String::from("??")),
+        Err(_) => String::new()
+    }
+}
+
+// Space-separated hex bytes with `??` as a wildcard, e.g. "A2 ?? D0".
+pub fn parse_pattern(text: &str) -> Result<Vec<Option<u8>>> {
+    text.split_whitespace().map(|token| match token {
+        "??" => Ok(None),
+        _ => u8::from_str_radix(token, 16)
+            .map(Some)
+            .map_err(|_| InspectorError::BadPattern(token.to_string()).into())
+    }).collect()
+}
+
+#[derive(Debug)]
+pub enum InspectorError {
+    BadPattern(String)
+}
+
+impl Display for InspectorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InspectorError::BadPattern(token) => write!(f, "Expected a hex byte or ?? in the search pattern, got {}", token)
+        }
+    }
+}
+
+impl std::error::Error for InspectorError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::quirks::Quirks;
+    use crate::frontend::session::SessionError;
+
+    // LD V0, 'A'; LD I, 0x210; LD [I], V0; CALL 0x20A; ...
+    const PROGRAM: [u8; 8] = [0x60, 0x41, 0xA2, 0x10, 0xF0, 0x55, 0x22, 0x0A];
+
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(&PROGRAM).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn rows_show_bytes_text_and_disassembly() {
+        let mut emulator = emulator();
+        let mut inspector = MemoryInspector::default();
+        inspector.show(emulator.memory(), emulator.pc());
+        inspector.mark(emulator.memory());
+        emulator.run_cycles(4).unwrap();
+
+        let rows = inspector.rows(&emulator);
+        assert_eq!(rows.len(), PAGE_SIZE / BYTES_PER_ROW);
+        assert_eq!(rows[0].addr, 0x200);
+        assert_eq!(rows[0].cells.len(), BYTES_PER_ROW);
+        assert_eq!(&rows[0].ascii[..4], "`A..");
+        assert_eq!(rows[0].disassembly[..4], [
+            (0x200, String::from("LD V0, 0x41")),
+            (0x202, String::from("LD I, 0x210")),
+            (0x204, String::from("LD [I], V0")),
+            (0x206, String::from("CALL 0x20A"))
+        ]);
+
+        // The PC covers both bytes of its instruction; the stack marks the
+        // return address.
+        let highlighted = |addr: usize| rows[addr / BYTES_PER_ROW % BYTES_PER_ROW].cells[addr % BYTES_PER_ROW].highlight;
+        assert_eq!(highlighted(0x20A), Highlight { pc: true, ..Highlight::default() });
+        assert_eq!(highlighted(0x20B), Highlight { pc: true, ..Highlight::default() });
+        assert_eq!(highlighted(0x208), Highlight { stack: true, ..Highlight::default() });
+        assert_eq!(highlighted(0x200), Highlight::default());
+
+        // LD [I], V0 stored 'A' at 0x210 and moved I past it.
+        let stored = rows[1].cells[0];
+        assert_eq!((stored.value, stored.changed), (0x41, true));
+        assert!(!rows[0].cells[0].changed);
+        assert_eq!(rows[1].ascii.chars().next(), Some('A'));
+        assert!(highlighted(emulator.i() as usize).i);
+    }
+
+    #[test]
+    fn disassembly_follows_the_pc_alignment() {
+        let mut emulator = emulator();
+        emulator.set_pc(0x201);
+        let mut inspector = MemoryInspector::default();
+        inspector.show(emulator.memory(), 0x201);
+        let row = &inspector.rows(&emulator)[0];
+        assert_eq!(row.disassembly[..2], [
+            (0x201, String::from("SNE V1, 0xA2")),
+            (0x203, String::from("JP 0x0F0"))
+        ]);
+    }
+
+    #[test]
+    fn pages_stay_within_memory() {
+        let emulator = emulator();
+        let memory = emulator.memory();
+        let mut inspector = MemoryInspector::default();
+        assert_eq!(MemoryInspector::page_count(memory), 16);
+        inspector.previous_page(memory);
+        assert_eq!(inspector.page(), 0);
+        inspector.show(memory, 0x345);
+        assert_eq!(inspector.page(), 3);
+        inspector.set_page(memory, 99);
+        assert_eq!(inspector.page(), 15);
+        inspector.next_page(memory);
+        assert_eq!(inspector.page(), 15);
+    }
+
+    #[test]
+    fn find_matches_wildcards_and_wraps() {
+        let emulator = emulator();
+        let memory = emulator.memory();
+        let pattern = parse_pattern("A2 ?? F0").unwrap();
+        assert_eq!(pattern, vec![Some(0xA2), None, Some(0xF0)]);
+        assert_eq!(MemoryInspector::find(memory, &pattern, 0), Some(0x202));
+        assert_eq!(MemoryInspector::find(memory, &pattern, 0x203), Some(0x202));
+        assert_eq!(MemoryInspector::find(memory, &parse_pattern("A2 11").unwrap(), 0), None);
+        assert_eq!(MemoryInspector::find(memory, &[], 0), None);
+
+        let error = parse_pattern("A2 G0").unwrap_err();
+        assert_eq!(error.to_string(), "Expected a hex byte or ?? in the search pattern, got G0");
+    }
+
+    #[test]
+    fn edits_need_a_paused_session() {
+        let mut session = Session::default();
+        session.load_rom(&PROGRAM).unwrap();
+        let mut inspector = MemoryInspector::default();
+        let error = inspector.edit(&mut session, 0x201, 0x42).unwrap_err();
+        assert!(matches!(error.downcast_ref::<SessionError>(), Some(SessionError::NotPaused)));
+
+        session.toggle_pause();
+        inspector.edit(&mut session, 0x201, 0x42).unwrap();
+        inspector.step(&mut session).unwrap();
+        assert_eq!(session.emulator().registers()[0], 0x42);
+        assert!(!inspector.is_changed(session.emulator().memory(), 0x201));
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::instructions::Instruction;
+use super::app::{Project, MANIFEST_NAME};
+use super::assembler::{self, Diagnostic, ParsedSource, Span, Statement, StatementKind, MNEMONICS};
+
+pub const REGISTERS: [&str; 24] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "[I]", "DT", "ST", "K", "F", "HF", "B"
+];
+
+// The forms a mnemonic takes, for completion details.
+pub fn mnemonic_forms(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "CLS" => "CLS",
+        "RET" => "RET",
+        "JP" => "JP addr | JP V0, addr",
+        "CALL" => "CALL addr",
+        "SE" => "SE Vx, byte | SE Vx, Vy",
+        "SNE" => "SNE Vx, byte | SNE Vx, Vy",
+        "LD" => "LD Vx, byte|Vy|DT|K|[I] | LD I, addr | LD DT|ST|F|HF|B|[I], Vx",
+        "ADD" => "ADD Vx, byte | ADD Vx, Vy | ADD I, Vx",
+        "OR" => "OR Vx, Vy",
+        "AND" => "AND Vx, Vy",
+        "XOR" => "XOR Vx, Vy",
+        "SUB" => "SUB Vx, Vy",
+        "SHR" => "SHR Vx [, Vy]",
+        "SUBN" => "SUBN Vx, Vy",
+        "SHL" => "SHL Vx [, Vy]",
+        "RND" => "RND Vx, byte",
+        "DRW" => "DRW Vx, Vy, nibble",
+        "SKP" => "SKP Vx",
+        "SKNP" => "SKNP Vx",
+        "AUDIO" => "AUDIO",
+        "PITCH" => "PITCH Vx",
+        "DB" => "DB byte, ...",
+        "DW" => "DW word, ...",
+        _ => ""
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+    Asset
+}
+
+impl SymbolKind {
+    // As the build names it in diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Label => "Label",
+            SymbolKind::Constant => "Constant",
+            SymbolKind::Asset => "Asset"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub file: PathBuf,
+    // None for assets, which are defined by the manifest.
+    pub span: Option<Span>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Mnemonic,
+    Register,
+    Symbol(SymbolKind)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String
+}
+
+// What sits under the cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Mnemonic(&'a Statement),
+    Symbol(&'a str)
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    text: String,
+    parsed: ParsedSource
+}
+
+impl SourceFile {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), parsed: assembler::parse(text) }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn parsed(&self) -> &ParsedSource {
+        &self.parsed
+    }
+
+    fn token_at(&self, line: usize, column: usize) -> Option<(Token<'_>, Span)> {
+        let inside = |span: &Span| span.line == line && span.start <= column && column <= span.end;
+        for statement in self.parsed.statements.iter().filter(|statement| statement.span.line == line) {
+            match &statement.kind {
+                StatementKind::Label(name) | StatementKind::Constant { name, .. } if inside(&statement.span) => return Some((Token::Symbol(name), statement.span)),
+                StatementKind::Instruction { mnemonic, .. } => {
+                    let span = Span { line, start: statement.span.start, end: statement.span.start + mnemonic.len() };
+                    if inside(&span) {
+                        return Some((Token::Mnemonic(statement), span));
+                    }
+                },
+                StatementKind::Data { .. } => {
+                    let span = Span { line, start: statement.span.start, end: statement.span.start + 2 };
+                    if inside(&span) {
+                        return Some((Token::Mnemonic(statement), span));
+                    }
+                },
+                _ => {}
+            }
+            if let Some((name, span)) = statement.references().into_iter().find(|(_, span)| inside(span)) {
+                return Some((Token::Symbol(name), span));
+            }
+        }
+        None
+    }
+
+    // Whether the cursor is where a mnemonic goes rather than an operand.
+    fn expects_mnemonic(&self, line: usize, column: usize) -> bool {
+        let text = self.text.lines().nth(line).unwrap_or("");
+        let before = &text[..column.min(text.len())];
+        let before = before.rsplit(':').next().unwrap_or(before).trim_start();
+        !before.contains(char::is_whitespace)
+    }
+}
+
+// The sources of one project, plus its asset names, which any of them can
+// refer to. A file outside a project is a workspace of its own.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    files: Vec<(PathBuf, SourceFile)>,
+    assets: Vec<(String, PathBuf)>,
+    // The project directory, which diagnostics name files relative to.
+    root: Option<PathBuf>
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+impl Workspace {
+    pub fn add_file(&mut self, path: &Path, text: &str) {
+        self.files.retain(|(existing, _)| existing != path);
+        self.files.push((path.to_path_buf(), SourceFile::new(text)));
+    }
+
+    pub fn add_asset(&mut self, name: &str, path: &Path) {
+        self.assets.push((name.to_string(), path.to_path_buf()));
+    }
+
+    // The workspace `file` belongs to: every source of the nearest project
+    // that lists it, or just the file. `open` supplies unsaved editor text.
+    pub fn load(file: &Path, open: &dyn Fn(&Path) -> Option<String>) -> Self {
+        let read = |path: &Path| open(path).or_else(|| fs::read_to_string(path).ok());
+        let mut workspace = Workspace::default();
+        let project = file.ancestors().skip(1)
+            .map(|dir| dir.join(MANIFEST_NAME))
+            .filter(|manifest| manifest.is_file())
+            .filter_map(|manifest| Project::load(&manifest).ok())
+            .find(|project| project.manifest().sources.iter().any(|source| same_file(&project.resolve(source), file)));
+        if let Some(project) = project {
+            workspace.root = Some(project.root().to_path_buf());
+            for source in &project.manifest().sources {
+                let path = project.resolve(source);
+                let path = if same_file(&path, file) { file.to_path_buf() } else { path };
+                if let Some(text) = read(&path) {
+                    workspace.add_file(&path, &text);
+                }
+            }
+            for asset in &project.manifest().assets {
+                workspace.add_asset(&asset.name, &project.resolve(&asset.path));
+            }
+        }
+        if workspace.file(file).is_none() {
+            workspace.add_file(file, &read(file).unwrap_or_default());
+        }
+        workspace
+    }
+
+    pub fn file(&self, path: &Path) -> Option<&SourceFile> {
+        self.files.iter().find(|(existing, _)| existing == path).map(|(_, file)| file)
+    }
+
+    // Every definition in source order, assets last.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        for (path, file) in &self.files {
+            for statement in &file.parsed.statements {
+                let (name, kind) = match &statement.kind {
+                    StatementKind::Label(name) => (name, SymbolKind::Label),
+                    StatementKind::Constant { name, .. } => (name, SymbolKind::Constant),
+                    _ => continue
+                };
+                symbols.push(Symbol { name: name.clone(), kind, file: path.clone(), span: Some(statement.span) });
+            }
+        }
+        for (name, path) in &self.assets {
+            symbols.push(Symbol { name: name.clone(), kind: SymbolKind::Asset, file: path.clone(), span: None });
+        }
+        symbols
+    }
+
+    pub fn document_symbols(&self, path: &Path) -> Vec<Symbol> {
+        self.symbols().into_iter().filter(|symbol| symbol.file == path && symbol.span.is_some()).collect()
+    }
+
+    fn constant(&self, name: &str) -> Option<u16> {
+        self.files.iter()
+            .flat_map(|(_, file)| &file.parsed.statements)
+            .find_map(|statement| match &statement.kind {
+                StatementKind::Constant { name: constant, value } if constant == name => Some(*value),
+                _ => None
+            })
+    }
+
+    // Parse errors, duplicate definitions and operands that don't encode.
+    // Labels stand in as address 0, so ranges only `build` can know about
+    // (like a label past the end of memory) aren't reported here.
+    pub fn diagnostics(&self, path: &Path) -> Vec<Diagnostic> {
+        let file = match self.file(path) {
+            Some(file) => file,
+            None => return vec![]
+        };
+        let mut diagnostics = file.parsed.diagnostics.clone();
+        let symbols = self.symbols();
+        // Worded like `build`'s, which names the file of the first definition.
+        let mut first: BTreeMap<&str, &Symbol> = BTreeMap::new();
+        for symbol in &symbols {
+            match first.get(symbol.name.as_str()) {
+                Some(defined) if symbol.file == path => {
+                    let file = match defined.kind {
+                        SymbolKind::Asset => String::from("the assets"),
+                        _ => self.root.as_ref()
+                            .and_then(|root| defined.file.strip_prefix(root).ok())
+                            .unwrap_or(&defined.file)
+                            .display().to_string()
+                    };
+                    let message = format!("{} '{}' is already defined in {}", symbol.kind.name(), symbol.name, file);
+                    diagnostics.push(Diagnostic::error(symbol.span, message));
+                },
+                Some(_) => {},
+                None => {
+                    first.insert(&symbol.name, symbol);
+                }
+            }
+        }
+        let resolve = |name: &str| match symbols.iter().find(|symbol| symbol.name == name) {
+            Some(symbol) if symbol.kind == SymbolKind::Constant => self.constant(name),
+            Some(_) => Some(0),
+            None => None
+        };
+        for statement in &file.parsed.statements {
+            if let Err(diagnostic) = assembler::encode(statement, &resolve) {
+                diagnostics.push(diagnostic);
+            }
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span);
+        diagnostics
+    }
+
+    // Markdown describing the instruction or symbol under the cursor.
+    pub fn hover(&self, path: &Path, line: usize, column: usize) -> Option<(String, Span)> {
+        let (token, span) = self.file(path)?.token_at(line, column)?;
+        let text = match token {
+            Token::Mnemonic(statement) => match &statement.kind {
+                StatementKind::Data { width: 1, .. } => String::from("`DB` — emits each operand as a byte."),
+                StatementKind::Data { .. } => String::from("`DW` — emits each operand as a big-endian word."),
+                _ => {
+                    let bytes = assembler::encode(statement, &|name| self.constant(name).or(Some(0))).ok()?;
+                    let instruction = Instruction::try_from(bytes.as_slice()).ok()?;
+                    let mut text = format!("`{}` — {}", instruction.pattern(), instruction.summary());
+                    if let Some(quirk) = instruction.quirk() {
+                        text.push_str(&format!("\n\n**Quirk:** {}", quirk));
+                    }
+                    text
+                }
+            },
+            Token::Symbol(name) => {
+                let symbol = self.symbols().into_iter().find(|symbol| symbol.name == name)?;
+                let file = symbol.file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                match (symbol.kind, symbol.span) {
+                    (SymbolKind::Constant, _) => {
+                        let value = self.constant(name)?;
+                        format!("constant `{}` = {:#x} ({})", name, value, value)
+                    },
+                    (SymbolKind::Label, Some(span)) => format!("label `{}` ({}:{})", name, file, span.line + 1),
+                    _ => format!("asset `{}` ({})", name, file)
+                }
+            }
+        };
+        Some((text, span))
+    }
+
+    pub fn definition(&self, path: &Path, line: usize, column: usize) -> Option<Symbol> {
+        match self.file(path)?.token_at(line, column)? {
+            (Token::Symbol(name), _) => self.symbols().into_iter().find(|symbol| symbol.name == name),
+            _ => None
+        }
+    }
+
+    // Every use of the symbol under the cursor across the workspace.
+    pub fn references(&self, path: &Path, line: usize, column: usize, include_definition: bool) -> Vec<(PathBuf, Span)> {
+        let name = match self.file(path).and_then(|file| file.token_at(line, column)) {
+            Some((Token::Symbol(name), _)) => name.to_string(),
+            _ => return vec![]
+        };
+        let mut references = vec![];
+        if include_definition {
+            references.extend(self.symbols().into_iter()
+                .filter(|symbol| symbol.name == name)
+                .filter_map(|symbol| Some((symbol.file, symbol.span?))));
+        }
+        for (path, file) in &self.files {
+            for statement in &file.parsed.statements {
+                references.extend(statement.references().into_iter()
+                    .filter(|(reference, _)| *reference == name)
+                    .map(|(_, span)| (path.clone(), span)));
+            }
+        }
+        references
+    }
+
+    pub fn completions(&self, path: &Path, line: usize, column: usize) -> Vec<Completion> {
+        let file = match self.file(path) {
+            Some(file) => file,
+            None => return vec![]
+        };
+        if file.expects_mnemonic(line, column) {
+            return MNEMONICS.iter()
+                .map(|mnemonic| Completion { label: mnemonic.to_string(), kind: CompletionKind::Mnemonic, detail: mnemonic_forms(mnemonic).to_string() })
+                .collect();
+        }
+        let registers = REGISTERS.iter()
+            .map(|register| Completion { label: register.to_string(), kind: CompletionKind::Register, detail: String::new() });
+        let symbols = self.symbols().into_iter()
+            .map(|symbol| {
+                let detail = match symbol.kind {
+                    SymbolKind::Label => String::from("label"),
+                    SymbolKind::Constant => self.constant(&symbol.name).map(|value| format!("= {:#x}", value)).unwrap_or_default(),
+                    SymbolKind::Asset => String::from("asset")
+                };
+                Completion { label: symbol.name, kind: CompletionKind::Symbol(symbol.kind), detail }
+            });
+        registers.chain(symbols).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "start:\n  CALL draw\n  LD V0, speed\n  JP start\n";
+    const DRAW: &str = "draw:\n  LD I, ball\n  DRW V0, V0, 8\n  RET\nspeed = 3\n";
+
+    fn workspace() -> Workspace {
+        let mut workspace = Workspace::default();
+        workspace.add_file(Path::new("main.asm"), MAIN);
+        workspace.add_file(Path::new("draw.asm"), DRAW);
+        workspace.add_asset("ball", Path::new("ball.db"));
+        workspace
+    }
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span { line, start, end }
+    }
+
+    #[test]
+    fn definitions_are_found_in_any_file() {
+        let workspace = workspace();
+        let main = Path::new("main.asm");
+        let draw = workspace.definition(main, 1, 8).unwrap();
+        assert_eq!(draw, Symbol { name: String::from("draw"), kind: SymbolKind::Label, file: PathBuf::from("draw.asm"), span: Some(span(0, 0, 4)) });
+        assert_eq!(workspace.definition(Path::new("draw.asm"), 1, 9).unwrap().kind, SymbolKind::Asset);
+        assert_eq!(workspace.definition(main, 2, 10).unwrap().kind, SymbolKind::Constant);
+        // Mnemonics aren't symbols.
+        assert_eq!(workspace.definition(main, 1, 3), None);
+
+        let names: Vec<String> = workspace.document_symbols(Path::new("draw.asm")).into_iter().map(|symbol| symbol.name).collect();
+        assert_eq!(names, vec!["draw", "speed"]);
+    }
+
+    #[test]
+    fn references_span_the_workspace() {
+        let workspace = workspace();
+        assert_eq!(workspace.references(Path::new("draw.asm"), 0, 2, true), vec![
+            (PathBuf::from("draw.asm"), span(0, 0, 4)),
+            (PathBuf::from("main.asm"), span(1, 7, 11))
+        ]);
+        assert_eq!(workspace.references(Path::new("main.asm"), 3, 6, false), vec![(PathBuf::from("main.asm"), span(3, 5, 10))]);
+        assert!(workspace.references(Path::new("main.asm"), 3, 2, true).is_empty());
+    }
+
+    #[test]
+    fn completions_depend_on_the_position() {
+        let workspace = workspace();
+        let main = Path::new("main.asm");
+        let mnemonics = workspace.completions(main, 1, 2);
+        assert_eq!(mnemonics.len(), MNEMONICS.len());
+        assert!(mnemonics.contains(&Completion { label: String::from("CALL"), kind: CompletionKind::Mnemonic, detail: String::from("CALL addr") }));
+
+        let operands = workspace.completions(main, 2, 9);
+        assert_eq!(operands.iter().filter(|completion| completion.kind == CompletionKind::Register).count(), REGISTERS.len());
+        let symbols: Vec<(&str, &str)> = operands.iter()
+            .filter(|completion| matches!(completion.kind, CompletionKind::Symbol(_)))
+            .map(|completion| (completion.label.as_str(), completion.detail.as_str()))
+            .collect();
+        assert_eq!(symbols, vec![("start", "label"), ("draw", "label"), ("speed", "= 0x3"), ("ball", "asset")]);
+    }
+
+    #[test]
+    fn hover_describes_instructions_and_symbols() {
+        let workspace = workspace();
+        let (text, hovered) = workspace.hover(Path::new("main.asm"), 2, 10).unwrap();
+        assert_eq!((text.as_str(), hovered), ("constant `speed` = 0x3 (3)", span(2, 9, 14)));
+        let (text, _) = workspace.hover(Path::new("main.asm"), 1, 3).unwrap();
+        assert!(text.starts_with("`2NNN` — "), "{}", text);
+        let (text, _) = workspace.hover(Path::new("main.asm"), 1, 9).unwrap();
+        assert_eq!(text, "label `draw` (draw.asm:1)");
+    }
+
+    #[test]
+    fn diagnostics_cover_duplicates_and_undefined_names() {
+        let mut workspace = workspace();
+        workspace.add_file(Path::new("main.asm"), "draw:\n  JP nowhere\nspeed = 4\n");
+        let messages: Vec<String> = workspace.diagnostics(Path::new("main.asm")).into_iter().map(|diagnostic| diagnostic.message).collect();
+        assert_eq!(messages, vec![
+            "Label 'draw' is already defined in draw.asm",
+            "Undefined label or constant 'nowhere'",
+            "Constant 'speed' is already defined in draw.asm"
+        ]);
+        assert!(workspace.diagnostics(Path::new("draw.asm")).is_empty());
+    }
+
+    #[test]
+    fn load_gathers_the_project_around_a_file() {
+        let dir = std::env::temp_dir().join(format!("lucid8-workspace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.asm"), MAIN).unwrap();
+        fs::write(dir.join("draw.asm"), DRAW).unwrap();
+        fs::write(dir.join("scratch.asm"), "CLS\n").unwrap();
+        let mut project = Project::new(&dir.join(MANIFEST_NAME), "pong");
+        project.add_source(Path::new("main.asm")).unwrap();
+        project.add_source(Path::new("draw.asm")).unwrap();
+        project.save().unwrap();
+
+        // Unsaved text from the editor wins over the file on disk.
+        let main = dir.join("main.asm");
+        let edited = |path: &Path| if path == main { Some(String::from("CALL draw\n")) } else { None };
+        let workspace = Workspace::load(&main, &edited);
+        let redefined = |path: &Path| if path == main { Some(String::from("draw:\n")) } else { None };
+        // main.asm comes first in the manifest, so draw.asm's is the duplicate.
+        let duplicate = Workspace::load(&main, &redefined).diagnostics(&dir.join("draw.asm"));
+        let scratch = Workspace::load(&dir.join("scratch.asm"), &|_| None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(workspace.file(&main).unwrap().text(), "CALL draw\n");
+        assert!(workspace.file(&dir.join("draw.asm")).is_some());
+        assert_eq!(workspace.definition(&main, 0, 6).unwrap().file, dir.join("draw.asm"));
+        assert_eq!(duplicate[0].message, "Label 'draw' is already defined in main.asm");
+        assert!(scratch.file(&dir.join("main.asm")).is_none());
+        assert_eq!(scratch.symbols(), vec![]);
+    }
+}
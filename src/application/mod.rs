@@ -1 +1,9 @@
-pub mod app;
\ No newline at end of file
+pub mod app;
+pub mod assembler;
+pub mod build;
+pub mod fuzz;
+pub mod harness;
+pub mod inspector;
+pub mod language;
+pub mod script;
+pub mod sprite;
@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, NativeCallContext, AST, INT};
+
+use crate::emulator::emulator::Result;
+use crate::frontend::session::{Session, SessionEvent};
+use crate::input::program::Program;
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+// Names accepted by `on(event, handler)`.
+pub const EVENTS: [&str; 4] = ["frame", "breakpoint", "draw", "sound"];
+
+fn fail(e: impl Display) -> Box<EvalAltResult> {
+    e.to_string().into()
+}
+
+fn narrow<T: TryFrom<INT>>(value: INT, what: &str) -> ScriptResult<T> {
+    T::try_from(value).map_err(|_| fail(format!("{} out of range: {}", what, value)))
+}
+
+fn event_args(event: SessionEvent) -> (&'static str, Vec<Dynamic>) {
+    match event {
+        SessionEvent::FrameEnd(frame) => ("frame", vec![Dynamic::from(frame as INT)]),
+        SessionEvent::Breakpoint(addr) => ("breakpoint", vec![Dynamic::from(addr as INT)]),
+        SessionEvent::Draw { x, y, height, collision } =>
+            ("draw", vec![Dynamic::from(x as INT), Dynamic::from(y as INT), Dynamic::from(height as INT), Dynamic::from(collision)]),
+        SessionEvent::SoundStart => ("sound", vec![])
+    }
+}
+
+// What the bindings reach: the session being scripted, which is swapped in
+// for the duration of each call, and the registered handlers.
+#[derive(Default)]
+struct State {
+    session: Session,
+    handlers: BTreeMap<String, Vec<FnPtr>>,
+    overlay: Vec<String>
+}
+
+// Calls the handlers for each event; `call` runs one handler with its
+// arguments, from inside a script or from the host.
+fn dispatch(state: &Rc<RefCell<State>>, events: Vec<SessionEvent>, call: &dyn Fn(&FnPtr, Vec<Dynamic>) -> ScriptResult<()>) -> ScriptResult<()> {
+    for event in events {
+        let (name, args) = event_args(event);
+        let handlers = state.borrow().handlers.get(name).cloned().unwrap_or_default();
+        for handler in handlers {
+            call(&handler, args.clone())?;
+        }
+    }
+    Ok(())
+}
+
+// A Rhai script automating a session. The top level runs once, and can
+// drive the game itself with `frames(n)`; handlers registered with `on` run
+// whenever the session produces a matching event, whether the script or
+// the host is advancing frames.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>
+}
+
+impl Script {
+    pub fn compile(source: &str) -> Result<Self> {
+        let state = Rc::new(RefCell::new(State::default()));
+        let mut engine = Engine::new();
+        Script::register(&mut engine, &state);
+        let ast = engine.compile(source)?;
+        Ok(Self { engine, ast, state })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Script::compile(&fs::read_to_string(path)?)
+    }
+
+    // The script attached to a program, if it has one.
+    pub fn for_program(program: &Program) -> Option<Result<Self>> {
+        program.script().map(Script::compile)
+    }
+
+    fn register(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+        // Registers
+        let s = state.clone();
+        engine.register_fn("reg", move |x: INT| -> ScriptResult<INT> {
+            let x: usize = narrow(x, "register")?;
+            s.borrow().session.emulator().registers().get(x).map(|v| *v as INT).ok_or_else(|| fail(format!("No register V{}", x)))
+        });
+        let s = state.clone();
+        engine.register_fn("set_reg", move |x: INT, value: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.set_register(narrow(x, "register")?, narrow(value, "value")?).map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("i", move || s.borrow().session.emulator().i() as INT);
+        let s = state.clone();
+        engine.register_fn("set_i", move |addr: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.set_i(narrow(addr, "address")?).map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("pc", move || s.borrow().session.emulator().pc() as INT);
+        let s = state.clone();
+        engine.register_fn("delay", move || s.borrow().session.emulator().delay_timer() as INT);
+        let s = state.clone();
+        engine.register_fn("sound_timer", move || s.borrow().session.emulator().sound_timer() as INT);
+
+        // Memory and display
+        let s = state.clone();
+        engine.register_fn("peek", move |addr: INT| -> ScriptResult<INT> {
+            let addr = narrow(addr, "address")?;
+            s.borrow().session.emulator().memory().get_range(addr, 1).map(|bytes| bytes[0] as INT).map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("poke", move |addr: INT, value: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.write_memory(narrow(addr, "address")?, &[narrow(value, "value")?]).map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("pixel", move |x: INT, y: INT| -> ScriptResult<bool> {
+            Ok(s.borrow().session.emulator().display().pixel(narrow(x, "x")?, narrow(y, "y")?))
+        });
+
+        // Input
+        let s = state.clone();
+        engine.register_fn("press", move |key: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.set_key(narrow(key, "key")?, true).map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("release", move |key: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.set_key(narrow(key, "key")?, false).map_err(fail)
+        });
+
+        // Running
+        let s = state.clone();
+        engine.register_fn("step", move || -> ScriptResult<()> {
+            s.borrow_mut().session.step_instruction().map_err(fail)
+        });
+        let s = state.clone();
+        engine.register_fn("frames", move |context: NativeCallContext, count: INT| -> ScriptResult<()> {
+            for _ in 0..count {
+                let (ran, events) = {
+                    let mut state = s.borrow_mut();
+                    (state.session.run_frame(), state.session.take_events())
+                };
+                dispatch(&s, events, &|handler, args| handler.call_within_context::<Dynamic>(&context, args).map(|_| ()))?;
+                ran.map_err(fail)?;
+            }
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("frame_count", move || s.borrow().session.frame() as INT);
+        let s = state.clone();
+        engine.register_fn("pause", move || if !s.borrow().session.is_paused() { s.borrow_mut().session.toggle_pause() });
+        let s = state.clone();
+        engine.register_fn("resume", move || if s.borrow().session.is_paused() { s.borrow_mut().session.toggle_pause() });
+        let s = state.clone();
+        engine.register_fn("paused", move || s.borrow().session.is_paused());
+        let s = state.clone();
+        engine.register_fn("break_at", move |addr: INT| -> ScriptResult<()> {
+            s.borrow_mut().session.add_breakpoint(narrow(addr, "address")?);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("clear_break", move |addr: INT| -> ScriptResult<bool> {
+            Ok(s.borrow_mut().session.remove_breakpoint(narrow(addr, "address")?))
+        });
+
+        // Events and overlay
+        let s = state.clone();
+        engine.register_fn("on", move |event: &str, handler: FnPtr| -> ScriptResult<()> {
+            if !EVENTS.contains(&event) {
+                return Err(fail(format!("Unknown event '{}', expected one of {}", event, EVENTS.join(", "))));
+            }
+            s.borrow_mut().handlers.entry(event.to_string()).or_default().push(handler);
+            Ok(())
+        });
+        let s = state.clone();
+        engine.register_fn("overlay", move |text: &str| s.borrow_mut().overlay.push(text.to_string()));
+        let s = state.clone();
+        engine.register_fn("clear_overlay", move || s.borrow_mut().overlay.clear());
+    }
+
+    // Lends `session` to the bindings while `f` runs.
+    fn attach<T>(&mut self, session: &mut Session, f: impl FnOnce(&mut Self) -> T) -> T {
+        std::mem::swap(session, &mut self.state.borrow_mut().session);
+        let result = f(self);
+        std::mem::swap(session, &mut self.state.borrow_mut().session);
+        result
+    }
+
+    // Runs the script's top level against `session`, turning on the event
+    // tracking its handlers need.
+    pub fn run(&mut self, session: &mut Session) -> Result<()> {
+        session.set_event_tracking(true);
+        self.attach(session, |script| script.engine.run_ast(&script.ast))?;
+        Ok(())
+    }
+
+    // Hands the events of frames the host ran to the script's handlers.
+    pub fn handle_events(&mut self, session: &mut Session) -> Result<()> {
+        let events = session.take_events();
+        self.attach(session, |script| {
+            dispatch(&script.state, events, &|handler, args| handler.call::<Dynamic>(&script.engine, &script.ast, args).map(|_| ()))
+        })?;
+        Ok(())
+    }
+
+    // Lines the script asked to show over the game.
+    pub fn overlay(&self) -> Vec<String> {
+        self.state.borrow().overlay.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 5; LD V1, 3; DRW V0, V1, 5; JP 0x206
+    const ROM: [u8; 8] = [0x60, 0x05, 0x61, 0x03, 0xD0, 0x15, 0x12, 0x06];
+
+    fn session() -> Session {
+        let mut session = Session::default();
+        session.load_rom(&ROM).unwrap();
+        session
+    }
+
+    #[test]
+    fn top_level_drives_the_session_and_calls_handlers() {
+        let mut session = session();
+        let mut script = Script::compile(r#"
+            on("draw", |x, y, height, collision| overlay(`draw ${x},${y} ${height} ${collision}`));
+            on("frame", |frame| overlay(`frame ${frame}`));
+            frames(2);
+            overlay(`V0=${reg(0)} V1=${reg(1)} pc=${pc()} frames=${frame_count()}`);
+        "#).unwrap();
+        script.run(&mut session).unwrap();
+        assert_eq!(script.overlay(), vec![
+            "draw 5,3 5 false",
+            "frame 0",
+            "frame 1",
+            "V0=5 V1=3 pc=518 frames=2"
+        ]);
+        assert!(session.emulator().display().pixel(5, 3));
+    }
+
+    #[test]
+    fn handlers_see_frames_the_host_runs() {
+        let mut session = session();
+        let mut script = Script::compile(r#"
+            break_at(0x204);
+            on("breakpoint", |addr| { overlay(`break ${addr}`); set_reg(2, 9); });
+        "#).unwrap();
+        script.run(&mut session).unwrap();
+        session.run_frame().unwrap();
+        assert!(session.is_paused());
+        script.handle_events(&mut session).unwrap();
+        assert_eq!(script.overlay(), vec!["break 516"]);
+        assert_eq!(session.emulator().registers()[2], 9);
+    }
+
+    #[test]
+    fn rejects_bad_events_and_arguments() {
+        let mut session = session();
+        for source in [r#"on("tick", || 0)"#, "reg(16)", "set_reg(0, 256)", "peek(-1)"] {
+            let mut script = Script::compile(source).unwrap();
+            assert!(script.run(&mut session).is_err(), "{} ran", source);
+        }
+        assert!(Script::compile("frames(").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt::{Display, Formatter};
+
+use crate::emulator::display::{self, Collision};
+use crate::emulator::emulator::Result;
+
+pub const MAX_HEIGHT: u8 = 15;
+
+// One row per sprite line with column 0 in the most significant bit, so an
+// 8 pixel wide sprite only uses the high byte.
+type SpriteRow = u16;
+
+// Either a CHIP-8 sprite 8 pixels wide and 1 to 15 rows tall, or a SCHIP
+// 16x16 sprite stored as two bytes per row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    width: u8,
+    rows: Vec<SpriteRow>
+}
+
+impl Sprite {
+    pub fn new(width: u8, height: u8) -> Result<Self> {
+        match (width, height) {
+            (8, 1..=MAX_HEIGHT) | (16, 16) => Ok(Self { width, rows: vec![0; height as usize] }),
+            _ => Err(Box::new(SpriteError::InvalidSize { width, height: height as usize }))
+        }
+    }
+
+    pub fn from_bytes(width: u8, bytes: &[u8]) -> Result<Self> {
+        let bytes_per_row = width as usize / 8;
+        let height = bytes.len() / bytes_per_row.max(1);
+        // Checked before narrowing, so 257 rows can't pass as 1.
+        if bytes_per_row == 0 || !bytes.len().is_multiple_of(bytes_per_row) || height > 16 {
+            return Err(Box::new(SpriteError::InvalidSize { width, height }));
+        }
+        let mut sprite = Sprite::new(width, height as u8)?;
+        for (row, chunk) in sprite.rows.iter_mut().zip(bytes.chunks(bytes_per_row)) {
+            *row = match chunk {
+                [high, low] => SpriteRow::from_be_bytes([*high, *low]),
+                [high] => (*high as SpriteRow) << 8,
+                _ => unreachable!()
+            };
+        }
+        Ok(sprite)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.rows.iter().flat_map(|row| {
+            let [high, low] = row.to_be_bytes();
+            if self.width == 16 { vec![high, low] } else { vec![high] }
+        }).collect()
+    }
+
+    // Parses the `db` lines written by `to_db_lines`. Values may be binary
+    // (0b or %), hex (0x, # or $) or decimal; labels, comments after `;` and
+    // blank lines are skipped.
+    pub fn from_db_lines(width: u8, source: &str) -> Result<Self> {
+        let mut bytes = vec![];
+        for line in source.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let operands = match line.split_once(char::is_whitespace) {
+                Some((directive, operands)) if directive.eq_ignore_ascii_case("db") => operands,
+                _ if line.is_empty() || line.ends_with(':') => continue,
+                _ => return Err(Box::new(SpriteError::Parse(line.to_string())))
+            };
+            for operand in operands.split(',') {
+                bytes.push(parse_byte(operand.trim()).ok_or_else(|| SpriteError::Parse(operand.trim().to_string()))?);
+            }
+        }
+        Sprite::from_bytes(width, &bytes)
+    }
+
+    // One `db` line per row, in binary so the shape is visible in the source.
+    pub fn to_db_lines(&self, label: Option<&str>) -> String {
+        let mut source = label.map(|label| format!("{}:\n", label)).unwrap_or_default();
+        for bytes in self.to_bytes().chunks(self.width as usize / 8) {
+            let operands: Vec<_> = bytes.iter().map(|byte| format!("0b{:08b}", byte)).collect();
+            source.push_str(&format!("    db {}\n", operands.join(", ")));
+        }
+        source
+    }
+
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn height(&self) -> u8 {
+        self.rows.len() as u8
+    }
+
+    // 8 pixel wide sprites can be made taller or shorter; new rows are blank.
+    pub fn resize(&mut self, height: u8) -> Result<()> {
+        Sprite::new(self.width, height)?;
+        self.rows.resize(height as usize, 0);
+        Ok(())
+    }
+
+    fn bit(&self, x: u8) -> SpriteRow {
+        1 << (15 - x)
+    }
+
+    fn row_mask(&self) -> SpriteRow {
+        !0 << (16 - self.width)
+    }
+
+    fn check(&self, x: u8, y: u8) -> Result<()> {
+        if x >= self.width || y >= self.height() {
+            return Err(Box::new(SpriteError::OutOfBounds { x, y }));
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, x: u8, y: u8) -> Result<bool> {
+        self.check(x, y)?;
+        Ok(self.rows[y as usize] & self.bit(x) != 0)
+    }
+
+    pub fn set(&mut self, x: u8, y: u8, lit: bool) -> Result<()> {
+        self.check(x, y)?;
+        let bit = self.bit(x);
+        let row = &mut self.rows[y as usize];
+        if lit { *row |= bit } else { *row &= !bit }
+        Ok(())
+    }
+
+    pub fn toggle(&mut self, x: u8, y: u8) -> Result<()> {
+        self.check(x, y)?;
+        self.rows[y as usize] ^= self.bit(x);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.iter_mut().for_each(|row| *row = 0);
+    }
+
+    pub fn invert(&mut self) {
+        let mask = self.row_mask();
+        self.rows.iter_mut().for_each(|row| *row = !*row & mask);
+    }
+
+    // Flood fills the area of same-coloured pixels around (x, y), 4-connected,
+    // with `lit`.
+    pub fn fill(&mut self, x: u8, y: u8, lit: bool) -> Result<()> {
+        let target = self.get(x, y)?;
+        if target == lit {
+            return Ok(());
+        }
+        let mut pending = vec![(x, y)];
+        while let Some((x, y)) = pending.pop() {
+            if self.get(x, y)? != target {
+                continue;
+            }
+            self.set(x, y, lit)?;
+            if x > 0 { pending.push((x - 1, y)) }
+            if y > 0 { pending.push((x, y - 1)) }
+            if x + 1 < self.width { pending.push((x + 1, y)) }
+            if y + 1 < self.height() { pending.push((x, y + 1)) }
+        }
+        Ok(())
+    }
+
+    // Moves the image by (dx, dy), wrapping pixels around the edges.
+    pub fn shift(&mut self, dx: i8, dy: i8) {
+        let (width, height) = (self.width as i32, self.height() as i32);
+        let dx = (dx as i32).rem_euclid(width) as u32;
+        let mask = self.row_mask();
+        for row in &mut self.rows {
+            let bits = (*row >> (16 - width)) as u32;
+            let rotated = ((bits >> dx) | (bits << (width as u32 - dx))) & ((1 << width) - 1);
+            *row = ((rotated as SpriteRow) << (16 - width)) & mask;
+        }
+        if height > 0 {
+            self.rows.rotate_right((dy as i32).rem_euclid(height) as usize);
+        }
+    }
+
+    pub fn flip_horizontal(&mut self) {
+        let width = self.width;
+        self.rows.iter_mut().for_each(|row| *row = row.reverse_bits() << (16 - width));
+    }
+
+    pub fn flip_vertical(&mut self) {
+        self.rows.reverse();
+    }
+
+    // Quarter turn clockwise. Only square sprites (8x8 and 16x16) can rotate.
+    pub fn rotate(&mut self) -> Result<()> {
+        if self.width != self.height() {
+            return Err(Box::new(SpriteError::NotSquare { width: self.width, height: self.height() }));
+        }
+        let size = self.width;
+        let mut rotated = Sprite::new(self.width, size)?;
+        for y in 0..size {
+            for x in 0..size {
+                rotated.set(size - 1 - y, x, self.get(x, y)?)?;
+            }
+        }
+        *self = rotated;
+        Ok(())
+    }
+
+    // Draws the sprite the way DRW would, 16 pixel wide sprites as two 8
+    // pixel columns, so the preview shows the same XOR and collision result.
+    pub fn preview(&self, display: &mut display::Display, x: u8, y: u8) -> Result<Collision> {
+        let bytes = self.to_bytes();
+        if self.width == 8 {
+            return display.draw_bytes_at(x, y, &bytes);
+        }
+        let left: Vec<u8> = bytes.iter().step_by(2).copied().collect();
+        let right: Vec<u8> = bytes.iter().skip(1).step_by(2).copied().collect();
+        let collision = display.draw_bytes_at(x, y, &left)?;
+        Ok(display.draw_bytes_at(x.wrapping_add(8), y, &right)? || collision)
+    }
+}
+
+fn parse_byte(operand: &str) -> Option<u8> {
+    let (digits, radix) = if let Some(digits) = operand.strip_prefix("0b").or_else(|| operand.strip_prefix('%')) {
+        (digits, 2)
+    } else if let Some(digits) = operand.strip_prefix("0x").or_else(|| operand.strip_prefix('#')).or_else(|| operand.strip_prefix('$')) {
+        (digits, 16)
+    } else {
+        (operand, 10)
+    };
+    u8::from_str_radix(digits, radix).ok()
+}
+
+#[derive(Debug)]
+pub enum SpriteError {
+    InvalidSize { width: u8, height: usize },
+    OutOfBounds { x: u8, y: u8 },
+    NotSquare { width: u8, height: u8 },
+    Parse(String)
+}
+
+impl Display for SpriteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpriteError::InvalidSize { width, height } => write!(f, "Sprites are 8x1 to 8x{} or 16x16, not {}x{}", MAX_HEIGHT, width, height),
+            SpriteError::OutOfBounds { x, y } => write!(f, "Pixel ({}, {}) is outside the sprite", x, y),
+            SpriteError::NotSquare { width, height } => write!(f, "Only square sprites can be rotated, this one is {}x{}", width, height),
+            SpriteError::Parse(text) => write!(f, "Expected a db line of byte values, got: {}", text)
+        }
+    }
+}
+
+impl std::error::Error for SpriteError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(rows: &[&str]) -> Sprite {
+        let mut sprite = Sprite::new(rows[0].len() as u8, rows.len() as u8).unwrap();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                sprite.set(x as u8, y as u8, pixel == '#').unwrap();
+            }
+        }
+        sprite
+    }
+
+    #[test]
+    fn only_chip8_and_schip_sizes_exist() {
+        assert!(Sprite::new(8, 1).is_ok());
+        assert!(Sprite::new(8, 0).is_err());
+        assert!(Sprite::new(16, 8).is_err());
+        assert!(Sprite::new(4, 4).is_err());
+        let mut sprite = Sprite::new(8, 3).unwrap();
+        assert!(sprite.get(8, 0).is_err());
+        assert!(sprite.resize(16).is_err());
+        sprite.resize(5).unwrap();
+        assert_eq!(sprite.height(), 5);
+    }
+
+    #[test]
+    fn from_bytes_rejects_too_many_rows() {
+        assert!(Sprite::from_bytes(8, &[0xFF; 15]).is_ok());
+        assert!(Sprite::from_bytes(8, &[0xFF; 16]).is_err());
+        // 257 rows would wrap to 1 as a u8.
+        assert!(Sprite::from_bytes(8, &[0xFF; 257]).is_err());
+        assert!(Sprite::from_bytes(16, &[0xFF; 32]).is_ok());
+        assert!(Sprite::from_bytes(16, &[0xFF; 2 * 272]).is_err());
+    }
+
+    #[test]
+    fn shift_wraps_around_the_edges() {
+        let mut glyph = sprite(&["#.......", "........", "......##"]);
+        glyph.shift(1, 1);
+        assert_eq!(glyph, sprite(&["#......#", ".#......", "........"]));
+        glyph.shift(-1, -1);
+        assert_eq!(glyph, sprite(&["#.......", "........", "......##"]));
+
+        let mut wide = Sprite::new(16, 16).unwrap();
+        wide.set(15, 0, true).unwrap();
+        wide.shift(2, 0);
+        assert!(wide.get(1, 0).unwrap());
+        assert_eq!(wide.to_bytes()[..2], [0x40, 0x00]);
+    }
+
+    #[test]
+    fn flips_mirror_rows_and_columns() {
+        let mut glyph = sprite(&["##......", "#......."]);
+        glyph.flip_horizontal();
+        assert_eq!(glyph, sprite(&["......##", ".......#"]));
+        glyph.flip_vertical();
+        assert_eq!(glyph, sprite(&[".......#", "......##"]));
+
+        let mut wide = Sprite::new(16, 16).unwrap();
+        wide.set(0, 3, true).unwrap();
+        wide.flip_horizontal();
+        assert!(wide.get(15, 3).unwrap());
+    }
+
+    #[test]
+    fn rotate_turns_square_sprites_clockwise() {
+        let mut glyph = Sprite::new(8, 8).unwrap();
+        glyph.set(0, 0, true).unwrap();
+        glyph.set(1, 0, true).unwrap();
+        glyph.rotate().unwrap();
+        assert!(glyph.get(7, 0).unwrap() && glyph.get(7, 1).unwrap());
+        assert_eq!(glyph.to_bytes().iter().map(|byte| byte.count_ones()).sum::<u32>(), 2);
+        for _ in 0..3 {
+            glyph.rotate().unwrap();
+        }
+        assert!(glyph.get(0, 0).unwrap() && glyph.get(1, 0).unwrap());
+    }
+
+    #[test]
+    fn rotate_refuses_non_square_sprites() {
+        let mut glyph = sprite(&["#.......", "........", "........"]);
+        let error = glyph.rotate().unwrap_err();
+        assert!(matches!(error.downcast_ref::<SpriteError>(), Some(SpriteError::NotSquare { width: 8, height: 3 })));
+        assert_eq!(glyph, sprite(&["#.......", "........", "........"]));
+    }
+
+    #[test]
+    fn fill_stops_at_other_colours() {
+        let mut glyph = sprite(&["...#....", "...#....", "####...."]);
+        glyph.fill(0, 0, true).unwrap();
+        assert_eq!(glyph, sprite(&["####....", "####....", "####...."]));
+        glyph.fill(7, 2, true).unwrap();
+        assert_eq!(glyph.to_bytes(), vec![0xFF; 3]);
+        // Filling with the colour already there changes nothing.
+        glyph.fill(0, 0, true).unwrap();
+        assert_eq!(glyph.to_bytes(), vec![0xFF; 3]);
+        glyph.invert();
+        assert_eq!(glyph.to_bytes(), vec![0; 3]);
+    }
+
+    #[test]
+    fn db_lines_round_trip() {
+        let glyph = sprite(&["#..##..#", ".######."]);
+        let source = glyph.to_db_lines(Some("smile"));
+        assert_eq!(source, "smile:\n    db 0b10011001\n    db 0b01111110\n");
+        assert_eq!(Sprite::from_db_lines(8, &source).unwrap(), glyph);
+
+        let mut wide = Sprite::new(16, 16).unwrap();
+        wide.set(3, 5, true).unwrap();
+        wide.set(12, 15, true).unwrap();
+        assert_eq!(Sprite::from_db_lines(16, &wide.to_db_lines(None)).unwrap(), wide);
+    }
+
+    #[test]
+    fn db_lines_accept_any_radix_and_skip_comments() {
+        let source = "ball: ; the ball\n\n  DB %11000000, $C0 ; two rows\n  db 0xff, #0f, 3\n";
+        assert_eq!(Sprite::from_db_lines(8, source).unwrap().to_bytes(), vec![0xC0, 0xC0, 0xFF, 0x0F, 3]);
+        assert!(Sprite::from_db_lines(8, "dw 0x1234").is_err());
+        assert!(Sprite::from_db_lines(8, "db 256").is_err());
+    }
+
+    #[test]
+    fn preview_draws_like_drw() {
+        let mut display = display::Display::new(64, 32);
+        let glyph = sprite(&["##......"]);
+        assert!(!glyph.preview(&mut display, 63, 0).unwrap());
+        assert!(display.pixel(63, 0) && display.pixel(0, 0));
+        assert!(glyph.preview(&mut display, 63, 0).unwrap());
+
+        let mut wide = Sprite::new(16, 16).unwrap();
+        wide.set(15, 0, true).unwrap();
+        wide.preview(&mut display, 0, 0).unwrap();
+        assert!(display.pixel(15, 0));
+    }
+}
@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+pub const FRAME_RATE: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    // Seconds taken to ramp between silence and full volume.
+    pub fade: f32
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+            fade: 0.005
+        }
+    }
+}
+
+// What plays while the sound timer runs: the configured tone, or an XO-CHIP
+// 128-bit pattern stepped through at a rate set by the pitch register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Voice {
+    Tone,
+    Pattern { bits: [u8; 16], pitch: u8 }
+}
+
+// Playback rate of XO-CHIP patterns in bits per second.
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+// Turns the on/off state of the sound timer into samples, one 60Hz frame at a
+// time. Phase and envelope carry over between frames so there are no clicks.
+#[derive(Debug)]
+pub struct Buzzer {
+    tone: Tone,
+    voice: Voice,
+    sample_rate: u32,
+    phase: f32,
+    pattern_position: f32,
+    envelope: f32,
+    remainder: u32
+}
+
+impl Buzzer {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Self {
+            tone,
+            voice: Voice::Tone,
+            sample_rate,
+            phase: 0.0,
+            pattern_position: 0.0,
+            envelope: 0.0,
+            remainder: 0
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    pub fn voice(&self) -> Voice {
+        self.voice
+    }
+
+    pub fn set_voice(&mut self, voice: Voice) {
+        self.voice = voice;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Sample count for the next frame; rates that aren't a multiple of 60
+    // spread the remainder over successive frames.
+    pub fn frame_len(&mut self) -> usize {
+        self.remainder += self.sample_rate;
+        let len = self.remainder / FRAME_RATE;
+        self.remainder %= FRAME_RATE;
+        len as usize
+    }
+
+    pub fn render_frame(&mut self, active: bool) -> Vec<f32> {
+        let len = self.frame_len();
+        let mut samples = Vec::with_capacity(len);
+        self.render(active, len, &mut samples);
+        samples
+    }
+
+    pub fn render(&mut self, active: bool, len: usize, out: &mut Vec<f32>) {
+        let step = self.tone.frequency / self.sample_rate as f32;
+        let fade_step = if self.tone.fade > 0.0 { 1.0 / (self.tone.fade * self.sample_rate as f32) } else { 1.0 };
+        for _ in 0..len {
+            self.envelope = if active {
+                (self.envelope + fade_step).min(1.0)
+            } else {
+                (self.envelope - fade_step).max(0.0)
+            };
+            let wave = match self.voice {
+                Voice::Tone => {
+                    let wave = match self.tone.waveform {
+                        Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                        Waveform::Sine => (self.phase * 2.0 * PI).sin()
+                    };
+                    self.phase = (self.phase + step).fract();
+                    wave
+                },
+                Voice::Pattern { bits, pitch } => {
+                    let bit = self.pattern_position as usize;
+                    let wave = if bits[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 };
+                    self.pattern_position = (self.pattern_position + pattern_rate(pitch) / self.sample_rate as f32) % 128.0;
+                    wave
+                }
+            };
+            out.push(wave * self.tone.volume * self.envelope);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f32, fade: f32) -> Tone {
+        Tone { waveform: Waveform::Square, frequency, volume: 0.5, fade }
+    }
+
+    fn render(buzzer: &mut Buzzer, active: bool, len: usize) -> Vec<f32> {
+        let mut samples = vec![];
+        buzzer.render(active, len, &mut samples);
+        samples
+    }
+
+    #[test]
+    fn square_wave_flips_halfway_through_each_period() {
+        // 1kHz at 8kHz: eight samples a period.
+        let mut buzzer = Buzzer::new(tone(1000.0, 0.0), 8000);
+        assert_eq!(render(&mut buzzer, true, 10), vec![0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn sine_wave_starts_at_zero_and_peaks_a_quarter_in() {
+        let mut buzzer = Buzzer::new(Tone { waveform: Waveform::Sine, ..tone(1000.0, 0.0) }, 8000);
+        let samples = render(&mut buzzer, true, 8);
+        assert!(samples[0].abs() < 1e-6);
+        assert!((samples[2] - 0.5).abs() < 1e-6);
+        assert!((samples[6] + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fade_ramps_in_and_out_over_the_configured_time() {
+        // A 1Hz square wave stays high; a 4ms fade at 1kHz is four samples.
+        let mut buzzer = Buzzer::new(tone(1.0, 0.004), 1000);
+        assert_eq!(render(&mut buzzer, true, 5), vec![0.125, 0.25, 0.375, 0.5, 0.5]);
+        assert_eq!(render(&mut buzzer, false, 5), vec![0.375, 0.25, 0.125, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn a_stopped_sound_timer_is_silent() {
+        let mut buzzer = Buzzer::new(Tone::default(), 44100);
+        let samples = buzzer.render_frame(false);
+        assert_eq!(samples.len(), 735);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn frames_spread_the_remainder_of_odd_rates() {
+        let mut buzzer = Buzzer::new(Tone::default(), 8000);
+        let lens: Vec<usize> = (0..3).map(|_| buzzer.frame_len()).collect();
+        assert_eq!(lens, vec![133, 133, 134]);
+    }
+
+    #[test]
+    fn patterns_play_one_bit_per_sample_at_the_default_pitch() {
+        let mut buzzer = Buzzer::new(tone(440.0, 0.0), 4000);
+        let mut bits = [0; 16];
+        bits[0] = 0b1100_1010;
+        buzzer.set_voice(Voice::Pattern { bits, pitch: 64 });
+        assert_eq!(render(&mut buzzer, true, 8), vec![0.5, 0.5, -0.5, -0.5, 0.5, -0.5, 0.5, -0.5]);
+    }
+}
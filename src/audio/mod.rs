@@ -0,0 +1,16 @@
+pub mod buzzer;
+pub mod wav;
+
+use std::fmt::Debug;
+
+use crate::emulator::emulator::Result;
+
+// Sink for mono samples in [-1.0, 1.0]. Real-time backends queue them to a
+// device; offline ones such as `WavWriter` store them.
+pub trait AudioBackend: Debug {
+    fn sample_rate(&self) -> u32;
+    fn queue(&mut self, samples: &[f32]) -> Result<()>;
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::emulator::emulator::Result;
+use super::AudioBackend;
+
+const HEADER_LEN: u32 = 44;
+
+// 16-bit mono PCM. The header is written up front with empty sizes and
+// patched by `finish`.
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    samples: u32
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> Result<Self> {
+        write_header(&mut writer, sample_rate, 0)?;
+        Ok(Self {
+            writer,
+            sample_rate,
+            samples: 0
+        })
+    }
+
+    pub fn samples_written(&self) -> u32 {
+        self.samples
+    }
+
+    // Rewrites the header for the samples written so far.
+    pub fn update_header(&mut self) -> Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, self.sample_rate, self.samples)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.update_header()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, sample_rate: u32, samples: u32) -> Result<()> {
+    let data_len = samples * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    Ok(())
+}
+
+impl<W: Write + Seek + std::fmt::Debug> AudioBackend for WavWriter<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue(&mut self, samples: &[f32]) -> Result<()> {
+        for sample in samples {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.update_header()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn header_describes_16_bit_mono() {
+        let bytes = WavWriter::new(Cursor::new(vec![]), 22050).unwrap().into_inner().unwrap().into_inner();
+        assert_eq!(bytes.len(), HEADER_LEN as usize);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(&bytes, 20), 1);
+        assert_eq!(u16_at(&bytes, 22), 1);
+        assert_eq!(u32_at(&bytes, 24), 22050);
+        assert_eq!(u32_at(&bytes, 28), 44100);
+        assert_eq!(u16_at(&bytes, 32), 2);
+        assert_eq!(u16_at(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+    }
+
+    #[test]
+    fn finish_patches_the_sizes() {
+        let mut writer = WavWriter::new(Cursor::new(vec![]), 8000).unwrap();
+        writer.queue(&[0.0, 1.0, -2.0]).unwrap();
+        assert_eq!(u32_at(writer.writer.get_ref(), 40), 0);
+        writer.finish().unwrap();
+        assert_eq!(writer.samples_written(), 3);
+        let bytes = writer.writer.into_inner();
+        assert_eq!(bytes.len(), 50);
+        assert_eq!(u32_at(&bytes, 4), 42);
+        assert_eq!(u32_at(&bytes, 40), 6);
+        // Out of range samples are clamped.
+        assert_eq!(&bytes[44..], &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
+use std::process;
+
+use rand::Rng;
+
+use lucid8::application::fuzz;
+use lucid8::emulator::emulator::Result;
+
+const USAGE: &str = "usage: lucid8-fuzz [--runs N] [--seed N] [--length INSTRUCTIONS] [--steps N] [--out DIR]";
+
+struct Options {
+    runs: u64,
+    seed: u64,
+    length: usize,
+    steps: usize,
+    out: PathBuf
+}
+
+fn parse_args() -> std::result::Result<Options, String> {
+    let mut options = Options {
+        runs: 1000,
+        seed: rand::thread_rng().gen(),
+        length: 64,
+        steps: 1000,
+        out: PathBuf::from("fuzz")
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => {
+                options.runs = args.next().and_then(|n| n.parse().ok()).ok_or("--runs expects a count")?;
+            },
+            "--seed" => {
+                options.seed = args.next().and_then(|n| n.parse().ok()).ok_or("--seed expects a number")?;
+            },
+            "--length" => {
+                options.length = args.next().and_then(|n| n.parse().ok()).filter(|n| *n > 0).ok_or("--length expects a positive instruction count")?;
+            },
+            "--steps" => {
+                options.steps = args.next().and_then(|n| n.parse().ok()).ok_or("--steps expects a count")?;
+            },
+            "--out" => {
+                options.out = args.next().map(PathBuf::from).ok_or("--out expects a directory")?;
+            },
+            "-h" | "--help" => return Err(String::new()),
+            _ => return Err(format!("unexpected argument: {}", arg))
+        }
+    }
+    Ok(options)
+}
+
+// Writes the minimized program of the first divergence to the output
+// directory, named after the seed that reproduces it.
+fn run(options: Options) -> Result<bool> {
+    // Panics are reported as divergences, not printed.
+    panic::set_hook(Box::new(|_| {}));
+    let found = fuzz::fuzz(options.seed, options.runs, options.length, options.steps)?;
+    let _ = panic::take_hook();
+    let (case, divergence) = match found {
+        Some(found) => found,
+        None => {
+            println!("no divergences in {} runs from seed {}", options.runs, options.seed);
+            return Ok(true);
+        }
+    };
+    fs::create_dir_all(&options.out)?;
+    let rom = options.out.join(format!("divergence-{}.ch8", case.seed));
+    fs::write(&rom, &case.program)?;
+    println!("{}", divergence);
+    println!("{}", case);
+    print!("{}", case.disassemble());
+    // The program depends on the length and the divergence on the steps, so
+    // the rerun needs both.
+    println!("reproducer written to {} (rerun with --seed {} --runs 1 --length {} --steps {})",
+        rom.display(), case.seed, options.length, options.steps);
+    Ok(false)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    match run(options) {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, LogMessage, Notification as _, PublishDiagnostics};
+use lsp_types::request::{Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams, GotoDefinitionParams,
+    Hover, HoverContents, HoverParams, HoverProviderCapability, Location, LogMessageParams, MarkupContent, MarkupKind, MessageType,
+    OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams, ServerCapabilities, SymbolKind as LspSymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url
+};
+
+use lucid8::application::assembler::{Diagnostic, Severity, Span};
+use lucid8::application::language::{CompletionKind, SymbolKind, Workspace};
+use lucid8::emulator::emulator::Result;
+
+// Open documents by path, holding the editor's text rather than what's on
+// disk. Every request rebuilds the workspace from these, which is cheap at
+// CHIP-8 program sizes.
+#[derive(Default)]
+struct Server {
+    documents: BTreeMap<PathBuf, String>
+}
+
+// LSP columns count UTF-16 code units; spans count bytes.
+fn to_position(text: &str, line: usize, byte: usize) -> Position {
+    let line_text = text.lines().nth(line).unwrap_or("");
+    let column = line_text.get(..byte.min(line_text.len())).map_or(0, |before| before.encode_utf16().count());
+    Position::new(line as u32, column as u32)
+}
+
+fn to_byte(text: &str, position: Position) -> (usize, usize) {
+    let line = position.line as usize;
+    let line_text = text.lines().nth(line).unwrap_or("");
+    let mut units = 0;
+    let byte = line_text.char_indices()
+        .find(|(_, c)| {
+            units += c.len_utf16();
+            units > position.character as usize
+        })
+        .map_or(line_text.len(), |(index, _)| index);
+    (line, byte)
+}
+
+fn to_range(text: &str, span: Option<Span>) -> Range {
+    match span {
+        Some(span) => Range::new(to_position(text, span.line, span.start), to_position(text, span.line, span.end)),
+        None => Range::default()
+    }
+}
+
+fn to_path(uri: &Url) -> Option<PathBuf> {
+    uri.to_file_path().ok()
+}
+
+fn to_uri(path: &Path) -> Option<Url> {
+    Url::from_file_path(path).ok()
+}
+
+fn to_diagnostic(text: &str, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING
+    };
+    lsp_types::Diagnostic {
+        range: to_range(text, diagnostic.span),
+        severity: Some(severity),
+        source: Some(String::from("lucid8")),
+        message: diagnostic.message.clone(),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+// An error for the editor's log, for notifications, which have no reply to
+// carry one.
+fn log_error(message: String) -> Notification {
+    Notification::new(LogMessage::METHOD.to_string(), LogMessageParams { typ: MessageType::ERROR, message })
+}
+
+impl Server {
+    fn workspace(&self, path: &Path) -> Workspace {
+        Workspace::load(path, &|path| self.documents.get(path).cloned())
+    }
+
+    fn location(workspace: &Workspace, path: &Path, span: Option<Span>) -> Option<Location> {
+        let text = workspace.file(path).map(|file| file.text()).unwrap_or("");
+        Some(Location::new(to_uri(path)?, to_range(text, span)))
+    }
+
+    // Diagnostics for every open document, since an edit to one file can
+    // define or remove a label another one uses.
+    fn diagnostics(&self) -> Vec<Notification> {
+        self.documents.keys().filter_map(|path| {
+            let workspace = self.workspace(path);
+            let text = workspace.file(path)?.text();
+            let diagnostics = workspace.diagnostics(path).iter().map(|diagnostic| to_diagnostic(text, diagnostic)).collect();
+            let params = PublishDiagnosticsParams::new(to_uri(path)?, diagnostics, None);
+            Some(Notification::new(PublishDiagnostics::METHOD.to_string(), params))
+        }).collect()
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<Vec<Notification>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                if let Some(path) = to_path(&params.text_document.uri) {
+                    self.documents.insert(path, params.text_document.text);
+                }
+            },
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                // Full sync: the last change holds the whole document.
+                if let (Some(path), Some(change)) = (to_path(&params.text_document.uri), params.content_changes.into_iter().last()) {
+                    self.documents.insert(path, change.text);
+                }
+            },
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                if let Some(path) = to_path(&params.text_document.uri) {
+                    self.documents.remove(&path);
+                    let params = PublishDiagnosticsParams::new(params.text_document.uri, vec![], None);
+                    let mut notifications = vec![Notification::new(PublishDiagnostics::METHOD.to_string(), params)];
+                    notifications.extend(self.diagnostics());
+                    return Ok(notifications);
+                }
+            },
+            _ => return Ok(vec![])
+        }
+        Ok(self.diagnostics())
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        match self.respond(request) {
+            Ok(Some(response)) => response,
+            Ok(None) => Response::new_err(id, ErrorCode::MethodNotFound as i32, String::from("Unsupported request")),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string())
+        }
+    }
+
+    fn respond(&self, request: Request) -> Result<Option<Response>> {
+        let id = request.id;
+        let response = match request.method.as_str() {
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                self.hover(id, params)
+            },
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                self.definition(id, params)
+            },
+            References::METHOD => {
+                let params: ReferenceParams = serde_json::from_value(request.params)?;
+                self.references(id, params)
+            },
+            DocumentSymbolRequest::METHOD => {
+                let params: DocumentSymbolParams = serde_json::from_value(request.params)?;
+                self.document_symbols(id, params)
+            },
+            Completion::METHOD => {
+                let params: CompletionParams = serde_json::from_value(request.params)?;
+                self.completion(id, params)
+            },
+            _ => return Ok(None)
+        };
+        Ok(Some(response))
+    }
+
+    // The workspace for a document and the cursor in byte columns.
+    fn locate(&self, uri: &Url, position: Position) -> Option<(Workspace, PathBuf, usize, usize)> {
+        let path = to_path(uri)?;
+        let workspace = self.workspace(&path);
+        let (line, column) = to_byte(workspace.file(&path)?.text(), position);
+        Some((workspace, path, line, column))
+    }
+
+    fn hover(&self, id: RequestId, params: HoverParams) -> Response {
+        let position = params.text_document_position_params;
+        let hover = self.locate(&position.text_document.uri, position.position).and_then(|(workspace, path, line, column)| {
+            let (value, span) = workspace.hover(&path, line, column)?;
+            let range = to_range(workspace.file(&path)?.text(), Some(span));
+            Some(Hover { contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }), range: Some(range) })
+        });
+        Response::new_ok(id, hover)
+    }
+
+    fn definition(&self, id: RequestId, params: GotoDefinitionParams) -> Response {
+        let position = params.text_document_position_params;
+        let location = self.locate(&position.text_document.uri, position.position).and_then(|(workspace, path, line, column)| {
+            let symbol = workspace.definition(&path, line, column)?;
+            Server::location(&workspace, &symbol.file, symbol.span)
+        });
+        Response::new_ok(id, location)
+    }
+
+    fn references(&self, id: RequestId, params: ReferenceParams) -> Response {
+        let position = params.text_document_position;
+        let include_declaration = params.context.include_declaration;
+        let locations: Vec<Location> = self.locate(&position.text_document.uri, position.position)
+            .map(|(workspace, path, line, column)| {
+                workspace.references(&path, line, column, include_declaration).iter()
+                    .filter_map(|(file, span)| Server::location(&workspace, file, Some(*span)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Response::new_ok(id, locations)
+    }
+
+    #[allow(deprecated)]
+    fn document_symbols(&self, id: RequestId, params: DocumentSymbolParams) -> Response {
+        let symbols: Vec<DocumentSymbol> = to_path(&params.text_document.uri)
+            .map(|path| {
+                let workspace = self.workspace(&path);
+                let text = workspace.file(&path).map(|file| file.text()).unwrap_or("");
+                workspace.document_symbols(&path).into_iter().map(|symbol| {
+                    let range = to_range(text, symbol.span);
+                    let kind = match symbol.kind {
+                        SymbolKind::Constant => LspSymbolKind::CONSTANT,
+                        _ => LspSymbolKind::FUNCTION
+                    };
+                    DocumentSymbol { name: symbol.name, detail: None, kind, tags: None, deprecated: None, range, selection_range: range, children: None }
+                }).collect()
+            })
+            .unwrap_or_default();
+        Response::new_ok(id, symbols)
+    }
+
+    fn completion(&self, id: RequestId, params: CompletionParams) -> Response {
+        let position = params.text_document_position;
+        let items: Vec<CompletionItem> = self.locate(&position.text_document.uri, position.position)
+            .map(|(workspace, path, line, column)| {
+                workspace.completions(&path, line, column).into_iter().map(|completion| {
+                    let kind = match completion.kind {
+                        CompletionKind::Mnemonic => CompletionItemKind::KEYWORD,
+                        CompletionKind::Register => CompletionItemKind::VARIABLE,
+                        CompletionKind::Symbol(SymbolKind::Label) => CompletionItemKind::FUNCTION,
+                        CompletionKind::Symbol(SymbolKind::Constant) => CompletionItemKind::CONSTANT,
+                        CompletionKind::Symbol(SymbolKind::Asset) => CompletionItemKind::FILE
+                    };
+                    let detail = Some(completion.detail).filter(|detail| !detail.is_empty());
+                    CompletionItem { label: completion.label, kind: Some(kind), detail, ..CompletionItem::default() }
+                }).collect()
+            })
+            .unwrap_or_default();
+        Response::new_ok(id, items)
+    }
+}
+
+fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    let mut server = Server::default();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                connection.sender.send(server.handle_request(request).into())?;
+            },
+            Message::Notification(notification) => {
+                let method = notification.method.clone();
+                let notifications = server.handle_notification(notification)
+                    .unwrap_or_else(|e| vec![log_error(format!("Ignored {}: {}", method, e))]);
+                for notification in notifications {
+                    connection.sender.send(notification.into())?;
+                }
+            },
+            Message::Response(_) => {}
+        }
+    }
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "é" is two bytes and one UTF-16 unit, "𝄞" four bytes and two units.
+    const TEXT: &str = "LD V0, 1\nfoo: ; é𝄞 bar\n";
+
+    #[test]
+    fn positions_count_utf16_units() {
+        assert_eq!(to_position(TEXT, 0, 3), Position::new(0, 3));
+        // "𝄞" starts at byte 9 and unit 8, "bar" at byte 14 and unit 11.
+        assert_eq!(to_position(TEXT, 1, 9), Position::new(1, 8));
+        assert_eq!(to_position(TEXT, 1, 14), Position::new(1, 11));
+        // Past the end of the line or the text.
+        assert_eq!(to_position(TEXT, 1, 100), Position::new(1, 14));
+        assert_eq!(to_position(TEXT, 5, 2), Position::new(5, 0));
+    }
+
+    #[test]
+    fn bytes_count_utf8_and_round_trip() {
+        assert_eq!(to_byte(TEXT, Position::new(1, 8)), (1, 9));
+        assert_eq!(to_byte(TEXT, Position::new(1, 11)), (1, 14));
+        // Inside the surrogate pair: the start of the character.
+        assert_eq!(to_byte(TEXT, Position::new(1, 9)), (1, 9));
+        assert_eq!(to_byte(TEXT, Position::new(1, 50)), (1, 17));
+        for byte in [0, 5, 7, 9, 13, 14, 17] {
+            assert_eq!(to_byte(TEXT, to_position(TEXT, 1, byte)), (1, byte));
+        }
+    }
+
+    #[test]
+    fn bad_notifications_are_errors_not_panics() {
+        let mut server = Server::default();
+        let notification = Notification::new(DidOpenTextDocument::METHOD.to_string(), serde_json::json!({ "textDocument": 3 }));
+        assert!(server.handle_notification(notification).is_err());
+        let log = log_error(String::from("oops"));
+        assert_eq!(log.method, "window/logMessage");
+        assert_eq!(log.params, serde_json::json!({ "type": 1, "message": "oops" }));
+    }
+}
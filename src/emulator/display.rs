@@ -1,8 +1,83 @@
 use super::emulator::Result;
 
-#[derive(Debug, Default)]
+pub type Row = u128;
+
+pub const MAX_WIDTH: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8
+}
+
+// Pixels that changed since the last `Display::take_dirty`, one mask per row
+// in the same layout as the display rows.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DirtyRegion {
+    rows: Vec<Row>
+}
+
+impl DirtyRegion {
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| *row == 0)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = (u8, Row)> + '_ {
+        self.rows.iter().enumerate().filter(|(_, row)| **row != 0).map(|(y, row)| (y as u8, *row))
+    }
+
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        x < MAX_WIDTH && self.rows.get(y as usize).is_some_and(|row| row & (1 << (MAX_WIDTH - 1 - x)) != 0)
+    }
+
+    pub fn bounds(&self) -> Option<Rect> {
+        Display::bounding_rect(self.rows())
+    }
+
+    // Rectangles covering each run of consecutive changed rows. Columns more
+    // than a sprite's width apart get separate rectangles, so a sprite that
+    // wraps around the right edge doesn't mark the whole row.
+    pub fn rects(&self) -> Vec<Rect> {
+        let mut rects = vec![];
+        let mut run: Option<(u8, u8, Row)> = None;
+        for (y, row) in self.rows() {
+            run = match run {
+                Some((top, bottom, columns)) if bottom + 1 == y => Some((top, y, columns | row)),
+                Some(done) => {
+                    rects.extend(DirtyRegion::span_rects(done));
+                    Some((y, y, row))
+                },
+                None => Some((y, y, row))
+            };
+        }
+        rects.extend(run.into_iter().flat_map(DirtyRegion::span_rects));
+        rects
+    }
+
+    fn span_rects((top, bottom, columns): (u8, u8, Row)) -> Vec<Rect> {
+        const GAP: u8 = 8;
+        let mut spans: Vec<(u8, u8)> = vec![];
+        for x in (0..MAX_WIDTH).filter(|x| columns & (1 << (MAX_WIDTH - 1 - x)) != 0) {
+            match spans.last_mut() {
+                Some((_, right)) if x - *right < GAP => *right = x + 1,
+                _ => spans.push((x, x + 1))
+            }
+        }
+        spans.into_iter()
+            .map(|(left, right)| Rect { x: left, y: top, width: right - left, height: bottom - top + 1 })
+            .collect()
+    }
+}
+
+// Each row is packed into a u128 with column 0 in the most significant bit,
+// so a sprite row is drawn with a shift and one XOR and collision is an AND.
+#[derive(Debug, Default, Clone)]
 pub struct Display {
-    pub pixel_map: Vec<Vec<bool>>,
+    rows: Vec<Row>,
+    dirty: Vec<Row>,
+    frame: u64,
     pub height: u8,
     pub width: u8
 }
@@ -10,80 +85,256 @@ pub struct Display {
 pub type Collision = bool;
 
 impl Display {
+    pub fn new(width: u8, height: u8) -> Self {
+        let mut display = Display { rows: vec![], dirty: vec![], frame: 0, height: 0, width: 0 };
+        display.resize(width, height);
+        display
+    }
+
+    // Keeps the pixels that are still on screen after the change.
     pub fn resize(&mut self, width: u8, height: u8) {
-        let cur_height = self.pixel_map.len();
-        let mut cur_width = 0;
-        if cur_height > 0 {
-            cur_width = self.pixel_map[0].len();
+        let width = width.min(MAX_WIDTH);
+        let mask = Display::row_mask(width);
+        self.rows.resize(height as usize, 0);
+        for row in &mut self.rows {
+            *row &= mask;
         }
+        self.width = width;
+        self.height = height;
+        self.dirty = vec![mask; height as usize];
+    }
 
-        let ydiff = height as i16 - cur_height as i16;
-        let xdiff = width as i16 - cur_width as i16;
+    fn bounding_rect<I: Iterator<Item = (u8, Row)>>(rows: I) -> Option<Rect> {
+        let mut bounds: Option<(u8, u8, Row)> = None;
+        for (y, row) in rows {
+            bounds = Some(match bounds {
+                None => (y, y, row),
+                Some((top, _, columns)) => (top, y, columns | row)
+            });
+        }
+        bounds.map(|(top, bottom, columns)| {
+            let left = columns.leading_zeros() as u8;
+            let right = MAX_WIDTH - columns.trailing_zeros() as u8;
+            Rect { x: left, y: top, width: right - left, height: bottom - top + 1 }
+        })
+    }
 
-        if (xdiff, ydiff) == (0, 0) { return; }
+    pub fn take_dirty(&mut self) -> DirtyRegion {
+        let rows = std::mem::replace(&mut self.dirty, vec![0; self.height as usize]);
+        DirtyRegion { rows }
+    }
 
-        if ydiff > 0 {
-            for _i in 0..ydiff {
-                self.pixel_map.push(vec![false; width as usize]);
-            }
-        }
-        if ydiff < 0 {
-            for _i in 0..(-1 * ydiff) as usize {
-                self.pixel_map.pop();
-            }
-        }
-        for row in &mut self.pixel_map {
-            if row.len() as u8 == width { continue; }
-            if xdiff > 0 {
-                row.append(&mut vec![false; xdiff as usize]);
-            }
-            if xdiff < 0 {
-                for _i in 0..(-1 * xdiff) as usize {
-                    row.push(false);
-                }
-            }
+    // Called once per 60Hz frame by the emulator.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    fn row_mask(width: u8) -> Row {
+        match width {
+            0 => 0,
+            _ => !0 << (MAX_WIDTH - width)
         }
     }
 
-    fn byte_to_bits(byte: u8) -> [bool; 8] {
-        [
-            byte & 0b10000000 != 0,
-            byte & 0b01000000 != 0,
-            byte & 0b00100000 != 0,
-            byte & 0b00010000 != 0,
-            byte & 0b00001000 != 0,
-            byte & 0b00000100 != 0,
-            byte & 0b00000010 != 0,
-            byte & 0b00000001 != 0
-        ]
-    }
-
-    fn draw_bit_at(&mut self, x: u8, y: u8, bit: bool) -> Result<Collision> {
-        let px = self.pixel_map[(y % self.height) as usize][(x % self.width) as usize];
-        let new = px ^ bit;
-        let collision = if px == true && new == false { true } else { false };
-        self.pixel_map[(y % self.height) as usize][(x % self.width) as usize] = new;
-        Ok(collision)
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
     }
 
-    fn draw_byte_at(&mut self, x: u8, y: u8, byte: u8) -> Result<Collision> {
-        let bits = Display::byte_to_bits(byte);
-        let mut collision = false;
-        for i in 0..8 {
-            collision |= self.draw_bit_at(x + i, y, bits[i as usize])?;
+    pub fn row(&self, y: u8) -> Row {
+        self.rows.get(y as usize).copied().unwrap_or(0)
+    }
+
+    pub fn pixel(&self, x: u8, y: u8) -> bool {
+        x < self.width && self.row(y) & (1 << (MAX_WIDTH - 1 - x)) != 0
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (u8, u8, bool)> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| (x, y, self.pixel(x, y))))
+    }
+
+    // Bits a sprite byte covers when drawn at column `x` of this display.
+    fn sprite_row(&self, x: u8, byte: u8, clip: bool) -> Row {
+        let sprite = (byte as Row) << (MAX_WIDTH - 8);
+        let mut row = sprite >> x;
+        if !clip {
+            row |= sprite.checked_shl((self.width - x) as u32).unwrap_or(0);
         }
-        Ok(collision)
+        row & Display::row_mask(self.width)
     }
 
     pub fn draw_bytes_at(&mut self, x: u8, y: u8, bytes: &[u8]) -> Result<Collision> {
+        self.draw_sprite(x, y, bytes, false)
+    }
+
+    // The sprite origin always wraps; with `clip` the parts hanging off the
+    // right and bottom edges are dropped instead of wrapping around.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, bytes: &[u8], clip: bool) -> Result<Collision> {
+        if self.width == 0 || self.height == 0 {
+            return Ok(false);
+        }
+        let x = x % self.width;
+        let y = (y % self.height) as usize;
         let mut collision = false;
-        for i in 0..bytes.len() {
-            collision |= self.draw_byte_at(x, y + i as u8, bytes[i])?;
+        for (i, byte) in bytes.iter().enumerate() {
+            if clip && y + i >= self.height as usize {
+                break;
+            }
+            let sprite = self.sprite_row(x, *byte, clip);
+            let index = (y + i) % self.height as usize;
+            let row = &mut self.rows[index];
+            collision |= *row & sprite != 0;
+            *row ^= sprite;
+            self.dirty[index] |= sprite;
         }
         Ok(collision)
     }
 
     pub fn clear(&mut self) {
-        self.pixel_map = vec![vec![false; self.width.into()]; self.height.into()];
+        for (row, dirty) in self.rows.iter_mut().zip(self.dirty.iter_mut()) {
+            *dirty |= *row;
+            *row = 0;
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(display: &Display) -> Vec<(u8, u8)> {
+        display.pixels().filter(|(_, _, lit)| *lit).map(|(x, y, _)| (x, y)).collect()
+    }
+
+    #[test]
+    fn sprites_wrap_around_both_edges() {
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(62, 31, &[0xC3, 0x81], false).unwrap();
+        assert_eq!(lit(&display), vec![(5, 0), (62, 0), (4, 31), (5, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn clipped_sprites_drop_what_hangs_off() {
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(62, 31, &[0xC3, 0x81], true).unwrap();
+        assert_eq!(lit(&display), vec![(62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn the_origin_wraps_even_when_clipping() {
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(64 + 2, 32 + 1, &[0x80], true).unwrap();
+        assert_eq!(lit(&display), vec![(2, 1)]);
+    }
+
+    #[test]
+    fn collisions_count_only_pixels_that_were_drawn() {
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        // The wrapped-around part turns (0, 0) off.
+        assert!(display.draw_sprite(63, 0, &[0xC0], false).unwrap());
+        assert_eq!(lit(&display), vec![(63, 0)]);
+
+        // Clipped, the part that would have wrapped can't collide.
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        assert!(!display.draw_sprite(63, 31, &[0xC0, 0xC0], true).unwrap());
+        assert!(display.pixel(0, 0));
+    }
+
+    #[test]
+    fn drawing_twice_erases_and_collides() {
+        let mut display = Display::new(128, 64);
+        assert!(!display.draw_sprite(120, 10, &[0xFF; 4], false).unwrap());
+        assert!(display.draw_sprite(120, 10, &[0xFF; 4], false).unwrap());
+        assert!(lit(&display).is_empty());
+    }
+
+    #[test]
+    fn resizing_keeps_what_is_still_on_screen() {
+        let mut display = Display::new(128, 64);
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        display.draw_sprite(100, 40, &[0x80], false).unwrap();
+        display.resize(64, 32);
+        assert_eq!((display.width, display.height), (64, 32));
+        assert_eq!(lit(&display), vec![(0, 0)]);
+        // Nothing that was cut off comes back.
+        display.resize(128, 64);
+        assert_eq!(lit(&display), vec![(0, 0)]);
+        // A hi-res row wraps at 128 columns.
+        display.draw_sprite(124, 0, &[0xFF], false).unwrap();
+        assert!(display.pixel(127, 0) && display.pixel(3, 0) && !display.pixel(4, 0));
+    }
+
+    #[test]
+    fn rows_pack_column_zero_into_the_top_bit() {
+        let mut display = Display::new(64, 32);
+        display.draw_sprite(0, 3, &[0x81], false).unwrap();
+        assert_eq!(display.row(3), (1 << 127) | (1 << 120));
+        display.clear();
+        assert_eq!(display.row(3), 0);
+    }
+
+    #[test]
+    fn take_dirty_reports_changes_once() {
+        let mut display = Display::new(64, 32);
+        // A new display is dirty all over.
+        assert_eq!(display.take_dirty().bounds(), Some(Rect { x: 0, y: 0, width: 64, height: 32 }));
+        assert!(display.take_dirty().is_empty());
+
+        display.draw_sprite(10, 4, &[0x3C, 0x42], false).unwrap();
+        let dirty = display.take_dirty();
+        assert!(dirty.contains(11, 5) && !dirty.contains(10, 4));
+        assert_eq!(dirty.rects(), vec![Rect { x: 11, y: 4, width: 6, height: 2 }]);
+        assert!(display.take_dirty().is_empty());
+
+        // Clearing dirties only what was lit.
+        display.clear();
+        assert_eq!(display.take_dirty().bounds(), Some(Rect { x: 11, y: 4, width: 6, height: 2 }));
+    }
+
+    #[test]
+    fn separate_runs_of_rows_get_separate_rects() {
+        let mut display = Display::new(64, 32);
+        display.take_dirty();
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        display.draw_sprite(4, 1, &[0x80], false).unwrap();
+        display.draw_sprite(2, 10, &[0x80], false).unwrap();
+        let dirty = display.take_dirty();
+        assert_eq!(dirty.rects(), vec![
+            Rect { x: 0, y: 0, width: 5, height: 2 },
+            Rect { x: 2, y: 10, width: 1, height: 1 }
+        ]);
+        assert_eq!(dirty.bounds(), Some(Rect { x: 0, y: 0, width: 5, height: 11 }));
+    }
+
+    #[test]
+    fn wrapped_sprites_dirty_two_rects() {
+        let mut display = Display::new(64, 32);
+        display.take_dirty();
+        display.draw_sprite(10, 31, &[0xFF, 0xFF], false).unwrap();
+        assert_eq!(display.take_dirty().rects(), vec![
+            Rect { x: 10, y: 0, width: 8, height: 1 },
+            Rect { x: 10, y: 31, width: 8, height: 1 }
+        ]);
+
+        display.draw_sprite(60, 20, &[0xFF], false).unwrap();
+        assert_eq!(display.take_dirty().rects(), vec![
+            Rect { x: 0, y: 20, width: 4, height: 1 },
+            Rect { x: 60, y: 20, width: 4, height: 1 }
+        ]);
+    }
+
+    #[test]
+    fn clipped_sprites_only_dirty_what_was_drawn() {
+        let mut display = Display::new(64, 32);
+        display.take_dirty();
+        display.draw_sprite(60, 31, &[0xFF, 0xFF], true).unwrap();
+        let dirty = display.take_dirty();
+        assert_eq!(dirty.rects(), vec![Rect { x: 60, y: 31, width: 4, height: 1 }]);
+        assert!(!dirty.contains(0, 0));
+    }
+}
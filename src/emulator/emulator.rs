@@ -1,99 +1,379 @@
 extern crate rand;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 pub type MemoryAddress = u16;
 pub type RegisterAddress = u8;
 pub type StackPointer = usize;
 
-use super::display::Display;
+// XO-CHIP pitch register value for the 4000Hz base sample rate.
+pub const DEFAULT_PITCH: u8 = 64;
+
+use super::display::{DirtyRegion, Display};
 use super::instructions::Instruction;
 use super::registers::Registers;
+use super::font::Font;
 use super::memory::Memory;
+use super::platform::Platform;
+use super::stack::{CallStack, StackFrame};
 use super::timed::TimedRegister;
+use super::quirks::Quirks;
+use crate::input::keypad::{Key, Keypad};
+use crate::util::ContentHasher;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuState {
+    Running,
+    // LDK blocks until a key is pressed and then released, as on the COSMAC VIP.
+    // Keys already down when it ran are in `held` and only count once they
+    // have been let go and pressed again.
+    WaitingForKey { register: RegisterAddress, pressed: Option<Key>, held: u16 }
+}
 
 #[derive(Debug)]
 pub struct Emulator {
     display: Display,
     pc: MemoryAddress,
     i: MemoryAddress,
-    sp: StackPointer,
-    stack: [MemoryAddress; 16],
+    stack: CallStack,
     registers: Registers,
     delay_register: TimedRegister,
     sound_register: TimedRegister,
-    memory: Memory
+    memory: Memory,
+    keypad: Keypad,
+    state: CpuState,
+    quirks: Quirks,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    seed: u64,
+    rng: StdRng
 }
 
 impl Default for Emulator {
     fn default() -> Self {
+        Emulator::new(rand::thread_rng().gen(), Quirks::default())
+    }
+}
+
+impl Emulator {
+    // The seed drives RND, so two emulators with the same seed, quirks and
+    // inputs run identically.
+    pub fn new(seed: u64, quirks: Quirks) -> Self {
         Self {
-            display: Display::default(),
-            pc: 0,
+            display: Display::new(64, 32),
+            pc: Platform::default().load_address,
             i: 0,
-            sp: 0,
-            stack: [0; 16],
+            stack: CallStack::default(),
             registers: Registers::default(),
             delay_register: TimedRegister::default(),
             sound_register: TimedRegister::default(),
-            memory: Memory::default()
+            memory: Memory::default(),
+            keypad: Keypad::default(),
+            state: CpuState::Running,
+            quirks,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            seed,
+            rng: StdRng::seed_from_u64(seed)
         }
     }
-}
-
-impl Emulator {
 
-    fn reset(&mut self) {
+    pub fn reset(&mut self) {
         self.display.clear();
-        self.pc = 0x200;
+        self.pc = self.memory.platform().load_address;
         self.i = 0;
-        self.sp = 0;
-        self.stack = [0; 16];
+        self.stack.clear();
         self.registers = Registers::default();
         self.delay_register.set(0);
         self.sound_register.set(0);
         self.memory.clear();
+        self.state = CpuState::Running;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) -> Result<()> {
+        self.reset();
+        self.memory.load_program(program)?;
+        Ok(())
+    }
+
+    // Swaps in a new version of the program without a reset: registers,
+    // timers and the display are kept, while the PC and I are passed through
+    // `remap` and return addresses through `remap_return` to follow code
+    // that moved.
+    pub fn patch_program(&mut self, program: &[u8], remap: &dyn Fn(MemoryAddress) -> MemoryAddress, remap_return: &dyn Fn(MemoryAddress) -> MemoryAddress) -> Result<()> {
+        self.memory.load_program(program)?;
+        self.pc = remap(self.pc);
+        self.i = remap(self.i);
+        self.stack.remap(&mut self.memory, remap_return)
+    }
+
+    // The font survives resets and program loads.
+    pub fn set_font(&mut self, font: &Font, base: MemoryAddress) -> Result<()> {
+        self.memory.load_font(font, base)
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.memory.platform()
     }
 
-    fn load_program(&mut self, program: &[u8]) -> Result<()> {
+    // Resizes memory and moves the entry point; the program has to be loaded
+    // again afterwards.
+    pub fn set_platform(&mut self, platform: Platform) -> Result<()> {
+        self.memory.set_platform(platform)?;
+        self.stack = CallStack::new(&platform);
         self.reset();
-        self.memory.set_range(0x200, program)?;
         Ok(())
     }
 
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn font(&self) -> &Font {
+        self.memory.font()
+    }
+
+    pub fn font_base(&self) -> MemoryAddress {
+        self.memory.font_base()
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn pc(&self) -> MemoryAddress {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, addr: MemoryAddress) {
+        self.pc = addr;
+    }
+
+    // V0 to VF.
+    pub fn registers(&self) -> &[u8] {
+        self.registers.as_bytes()
+    }
+
+    pub fn set_register(&mut self, register: RegisterAddress, value: u8) -> Result<()> {
+        self.registers.set(register, value)
+    }
+
+    pub fn set_i(&mut self, addr: MemoryAddress) {
+        self.i = addr;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_register.get()
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_register.get()
+    }
+
+    // The instruction at `addr`, read without notifying memory observers.
+    pub fn instruction_at(&self, addr: MemoryAddress) -> Option<Instruction> {
+        self.memory.get_range(addr, 2).ok().and_then(|bytes| Instruction::try_from(bytes).ok())
+    }
+
+    pub fn i(&self) -> MemoryAddress {
+        self.i
+    }
+
+    // Return addresses, innermost call last.
+    pub fn stack(&self) -> Vec<MemoryAddress> {
+        self.stack.entries(&self.memory)
+    }
+
+    pub fn stack_depth(&self) -> StackPointer {
+        self.stack.len()
+    }
+
+    // Does what a CALL at the PC would: pushes the PC and jumps to `addr`.
+    pub fn call(&mut self, addr: MemoryAddress) -> Result<()> {
+        self.stack.push(&mut self.memory, self.pc)?;
+        self.pc = addr;
+        Ok(())
+    }
+
+    // The call stack with subroutine names looked up in `symbols`.
+    pub fn call_stack(&self, symbols: &BTreeMap<MemoryAddress, String>) -> Vec<StackFrame> {
+        self.stack.frames(&self.memory, symbols)
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut Keypad {
+        &mut self.keypad
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Takes effect from the next reset.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn state(&self) -> CpuState {
+        self.state
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        matches!(self.state, CpuState::WaitingForKey { .. })
+    }
+
+    pub fn step(&mut self) -> Result<()> {
+        if let CpuState::WaitingForKey { register, pressed, held } = self.state {
+            return self.poll_key_wait(register, pressed, held);
+        }
+        let instruction = self.memory.fetch_instruction(self.pc)?;
+        self.skip();
+        self.interpret(&instruction)
+    }
+
+    fn poll_key_wait(&mut self, register: RegisterAddress, pressed: Option<Key>, held: u16) -> Result<()> {
+        match pressed {
+            None => {
+                let down = self.keypad.as_bits();
+                let fresh = down & !held;
+                let pressed = match fresh {
+                    0 => None,
+                    _ => Some(fresh.trailing_zeros() as Key)
+                };
+                self.state = CpuState::WaitingForKey { register, pressed, held: held & down };
+            },
+            Some(key) => {
+                if !self.keypad.is_pressed(key)? {
+                    self.registers.set(register, key)?;
+                    self.state = CpuState::Running;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.delay_register.tick();
+        self.sound_register.tick();
+        self.display.end_frame();
+    }
+
+    pub fn take_dirty(&mut self) -> DirtyRegion {
+        self.display.take_dirty()
+    }
+
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<()> {
+        for _ in 0..cycles {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn run_frame(&mut self, cycles: usize) -> Result<()> {
+        self.run_cycles(cycles)?;
+        self.tick_timers();
+        Ok(())
+    }
+
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_register.get() > 0
+    }
+
+    // XO-CHIP 1-bit sample pattern loaded by F002, if any.
+    pub fn audio_pattern(&self) -> Option<[u8; 16]> {
+        self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // A content hash of everything that affects execution, stable across
+    // builds so it can be stored alongside recordings.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = ContentHasher::default();
+        let mut feed = |bytes: &[u8]| hasher.write(bytes);
+        feed(&self.pc.to_be_bytes());
+        feed(&self.i.to_be_bytes());
+        feed(&(self.stack.len() as u16).to_be_bytes());
+        for addr in self.stack.slots() {
+            feed(&addr.to_be_bytes());
+        }
+        feed(self.registers.as_bytes());
+        feed(&[self.delay_register.get(), self.sound_register.get()]);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                feed(&[1]);
+                feed(pattern);
+            },
+            None => feed(&[0])
+        }
+        feed(&[self.pitch]);
+        feed(self.memory.as_bytes());
+        for y in 0..self.display.height {
+            feed(&(0..self.display.width).map(|x| self.display.pixel(x, y) as u8).collect::<Vec<u8>>());
+        }
+        hasher.finish()
+    }
+
+    // Moves past one instruction. The PC wraps at 64K like the 16-bit
+    // register it is; fetching past the end of memory is what fails.
+    fn skip(&mut self) {
+        self.pc = self.pc.wrapping_add(2);
+    }
+
     fn interpret(&mut self, instruction: &Instruction) -> Result<()> {
         match instruction {
             Instruction::CLS => self.display.clear(),
             Instruction::RET => {
-                self.pc = self.stack[self.sp];
-                self.sp -= 1;
+                self.pc = self.stack.pop(&self.memory)?;
             },
             Instruction::JP(addr) => {
                 self.pc = *addr;
             },
             Instruction::CALL(addr) => {
-                self.sp += 1;
-                self.stack[self.sp] = self.pc;
+                self.stack.push(&mut self.memory, self.pc)?;
                 self.pc = *addr;
             },
             Instruction::SE(vx, y) => {
                 let x = self.registers.get(*vx)?;
-                if x == *y { self.pc += 2; }
+                if x == *y { self.skip(); }
             },
             Instruction::SNE(vx, y) => {
                 let x = self.registers.get(*vx)?;
-                if x != *y { self.pc += 2; }
+                if x != *y { self.skip(); }
             },
             Instruction::SEV(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                if x == y { self.pc += 2; }
+                if x == y { self.skip(); }
             },
             Instruction::LD(vx, y) => {
                 self.registers.set(*vx, *y)?;
             },
             Instruction::ADD(vx, y) => {
                 let x = self.registers.get(*vx)?;
-                self.registers.set(*vx, x + *y)?;
+                self.registers.set(*vx, x.wrapping_add(*y))?;
             },
             Instruction::LDV(vx, vy) => {
                 let y = self.registers.get(*vy)?;
@@ -102,66 +382,82 @@ impl Emulator {
             Instruction::OR(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
                 self.registers.set(*vx, x | y)?;
+                if self.quirks.logic_resets_vf { self.registers.set(0x0f, 0)?; }
             },
             Instruction::AND(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
                 self.registers.set(*vx, x & y)?;
+                if self.quirks.logic_resets_vf { self.registers.set(0x0f, 0)?; }
             },
             Instruction::XOR(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
                 self.registers.set(*vx, x ^ y)?;
+                if self.quirks.logic_resets_vf { self.registers.set(0x0f, 0)?; }
             },
             Instruction::ADDV(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                self.registers.set(*vx, x + y)?;
+                let (sum, carry) = x.overflowing_add(y);
+                self.registers.set(*vx, sum)?;
+                self.registers.set(0x0f, if carry { 1 } else { 0 })?;
             },
             Instruction::SUB(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                let pos = if x > y { 1 } else { 0 };
-                self.registers.set(*vx, x - y)?;
+                let pos = if x >= y { 1 } else { 0 };
+                self.registers.set(*vx, x.wrapping_sub(y))?;
                 self.registers.set(0x0f, pos)?;
             },
             Instruction::SHR(vx) => {
                 let x = self.registers.get(*vx)?;
                 self.registers.set(*vx, x >> 1)?;
+                self.registers.set(0x0f, x & 1)?;
             },
             Instruction::SUBN(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                let pos = if y > 1 { 1 } else { 0 };
-                self.registers.set(*vx, y - x)?;
+                let pos = if y >= x { 1 } else { 0 };
+                self.registers.set(*vx, y.wrapping_sub(x))?;
                 self.registers.set(0x0f, pos)?;
             },
             Instruction::SHL(vx) => {
                 let x = self.registers.get(*vx)?;
                 self.registers.set(*vx, x << 1)?;
+                self.registers.set(0x0f, x >> 7)?;
             },
             Instruction::SNEV(vx, vy) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                if x != y { self.pc += 2; }
+                if x != y { self.skip(); }
             },
             Instruction::LDI(addr) => {
                 self.i = *addr;
             },
             Instruction::JPV(addr) => {
-                let offset = self.registers.get(0)?;
+                let offset_register = if self.quirks.jump_uses_vx { (*addr >> 8) as u8 } else { 0 };
+                let offset = self.registers.get(offset_register)?;
                 self.pc = offset as u16 + *addr;
             },
             Instruction::RND(vx, y) => {
-                let x: u8 = rand::thread_rng().gen();
+                let x: u8 = self.rng.gen();
                 self.registers.set(*vx, x & *y)?;
             },
             Instruction::DRW(vx, vy, num_bytes) => {
                 let (x, y) = self.registers.get_pair(*vx, *vy)?;
-                let sprite = self.memory.get_range(self.i, *num_bytes as usize)?;
-                let collision = self.display.draw_bytes_at(x, y, sprite)?;
+                let sprite = self.memory.read(self.i, *num_bytes as usize)?;
+                let collision = self.display.draw_sprite(x, y, sprite, self.quirks.clip_sprites)?;
                 self.registers.set(0xf, if collision {1} else {0})?;
             },
-            Instruction::SKP(_) => todo!(),
-            Instruction::SKNP(_) => todo!(),
+            Instruction::SKP(vx) => {
+                let x = self.registers.get(*vx)?;
+                if self.keypad.is_pressed(x & 0xF)? { self.skip(); }
+            },
+            Instruction::SKNP(vx) => {
+                let x = self.registers.get(*vx)?;
+                if !self.keypad.is_pressed(x & 0xF)? { self.skip(); }
+            },
             Instruction::LDD(vx) => {
                 self.registers.set(*vx, self.delay_register.get())?;
             },
-            Instruction::LDK(_) => todo!(),
+            Instruction::LDK(vx) => {
+                self.state = CpuState::WaitingForKey { register: *vx, pressed: None, held: self.keypad.as_bits() };
+            },
             Instruction::LDDV(vx) => {
                 let x = self.registers.get(*vx)?;
                 self.delay_register.set(x);
@@ -172,11 +468,15 @@ impl Emulator {
             },
             Instruction::ADDI(vx) => {
                 let x = self.registers.get(*vx)?;
-                self.i += x as u16;
+                self.i = self.i.wrapping_add(x as u16);
             },
             Instruction::LDF(vx) => {
                 let x = self.registers.get(*vx)?;
-                self.i = (x as u16 & 0xF) * 5;
+                self.i = self.memory.font_address(x);
+            },
+            Instruction::LDHF(vx) => {
+                let x = self.registers.get(*vx)?;
+                self.i = self.memory.big_font_address(x);
             },
             Instruction::LDB(vx) => {
                 let x = self.registers.get(*vx)?;
@@ -184,16 +484,225 @@ impl Emulator {
                 let tens = ((x % 100) - ones) / 10;
                 let hundreds = ((x as u16 % 1000) as u8 - tens - ones) / 100;
                 self.memory.set_byte(self.i, hundreds)?;
-                self.memory.set_byte(self.i + 1, tens)?;
-                self.memory.set_byte(self.i + 2, ones)?;
+                self.memory.set_byte(self.i.wrapping_add(1), tens)?;
+                self.memory.set_byte(self.i.wrapping_add(2), ones)?;
             },
-            Instruction::LDIV => {
-                self.memory.set_range(self.i, self.registers.as_bytes())?;
+            Instruction::LDIV(vx) => {
+                let count = *vx as usize + 1;
+                self.memory.set_range(self.i, &self.registers.as_bytes()[..count])?;
             },
-            Instruction::LDVI => {
-                self.registers.set_bytes(&self.memory.get_range(self.i, 16)?)?;
+            Instruction::LDVI(vx) => {
+                self.registers.set_bytes(self.memory.read(self.i, *vx as usize + 1)?)?;
+            },
+            Instruction::AUDIO => {
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(self.memory.read(self.i, 16)?);
+                self.audio_pattern = Some(pattern);
+            },
+            Instruction::PITCH(vx) => {
+                self.pitch = self.registers.get(*vx)?;
             },
         };
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::platform::PlatformProfile;
+
+    // Runs `program` one instruction per opcode, starting from zeroed registers.
+    fn run(program: &[u16]) -> Emulator {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        let bytes: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        emulator.load_program(&bytes).unwrap();
+        emulator.run_cycles(program.len()).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn add_sets_vf_on_carry() {
+        let emulator = run(&[0x60FF, 0x6101, 0x8014]);
+        assert_eq!(emulator.registers()[0], 0);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(&[0x60FE, 0x6101, 0x6F07, 0x8014]);
+        assert_eq!(emulator.registers()[0], 0xFF);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn add_byte_wraps_and_leaves_vf_alone() {
+        let emulator = run(&[0x60FF, 0x6F07, 0x7002]);
+        assert_eq!(emulator.registers()[0], 1);
+        assert_eq!(emulator.registers()[0xF], 7);
+    }
+
+    #[test]
+    fn timers_only_count_down_when_ticked() {
+        // V0 = 2; delay and sound timers = V0.
+        let mut emulator = run(&[0x6002, 0xF015, 0xF018]);
+        assert_eq!((emulator.delay_timer(), emulator.sound_timer()), (2, 2));
+        emulator.tick_timers();
+        assert_eq!((emulator.delay_timer(), emulator.sound_timer()), (1, 1));
+        emulator.tick_timers();
+        emulator.tick_timers();
+        assert_eq!((emulator.delay_timer(), emulator.sound_timer()), (0, 0));
+    }
+
+    #[test]
+    fn subn_subtracts_vx_from_vy() {
+        let emulator = run(&[0x6003, 0x6105, 0x8017]);
+        assert_eq!(emulator.registers()[0], 2);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(&[0x6005, 0x6103, 0x8017]);
+        assert_eq!(emulator.registers()[0], 0xFE);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn sub_sets_vf_when_nothing_is_borrowed() {
+        // V0 = V1 = 5: SUB and SUBN both give 0 with no borrow.
+        let emulator = run(&[0x6005, 0x6105, 0x8015]);
+        assert_eq!(emulator.registers()[0], 0);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(&[0x6005, 0x6105, 0x8017]);
+        assert_eq!(emulator.registers()[0], 0);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(&[0x6004, 0x6105, 0x8015]);
+        assert_eq!(emulator.registers()[0], 0xFF);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_bit_shifted_out() {
+        let emulator = run(&[0x6081, 0x8006]);
+        assert_eq!(emulator.registers()[0], 0x40);
+        assert_eq!(emulator.registers()[0xF], 1);
+        let emulator = run(&[0x6081, 0x800E]);
+        assert_eq!(emulator.registers()[0], 0x02);
+        assert_eq!(emulator.registers()[0xF], 1);
+        // The flag wins when VF itself is shifted.
+        let emulator = run(&[0x6F02, 0x8F06]);
+        assert_eq!(emulator.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn register_stores_and_loads_stop_at_x() {
+        // V0..V2 = 1, 2, 3 and V3 = 4; FX55 with X = 2 leaves 0x303 alone.
+        let emulator = run(&[0x6001, 0x6102, 0x6203, 0x6304, 0xA300, 0xF255]);
+        assert_eq!(emulator.memory().get_range(0x300, 4).unwrap(), &[1, 2, 3, 0]);
+        // Reloading V0..V1 from 0x301 keeps V2 and V3.
+        let emulator = run(&[0x6001, 0x6102, 0x6203, 0x6304, 0xA300, 0xF355, 0xA301, 0xF165]);
+        assert_eq!(&emulator.registers()[..4], &[2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn ldk_ignores_a_key_held_before_it_ran() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(&[0xF3, 0x0A]).unwrap();
+        emulator.keypad_mut().press(0x5).unwrap();
+        emulator.run_cycles(4).unwrap();
+        emulator.keypad_mut().release(0x5).unwrap();
+        emulator.run_cycles(4).unwrap();
+        assert!(emulator.is_waiting_for_key());
+
+        // Pressed again after the release, it counts.
+        emulator.keypad_mut().press(0x5).unwrap();
+        emulator.step().unwrap();
+        emulator.keypad_mut().release(0x5).unwrap();
+        emulator.step().unwrap();
+        assert!(!emulator.is_waiting_for_key());
+        assert_eq!(emulator.registers()[3], 0x5);
+    }
+
+    #[test]
+    fn ldk_completes_on_release_with_the_pressed_key() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(&[0xF3, 0x0A, 0x60, 0x01]).unwrap();
+        emulator.step().unwrap();
+        assert!(emulator.is_waiting_for_key());
+
+        emulator.keypad_mut().press(0xA).unwrap();
+        emulator.run_cycles(3).unwrap();
+        assert!(emulator.is_waiting_for_key());
+        assert_eq!(emulator.pc(), 0x202);
+
+        emulator.keypad_mut().release(0xA).unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.registers()[3], 0xA);
+        emulator.step().unwrap();
+        assert_eq!(emulator.registers()[0], 1);
+    }
+
+    #[test]
+    fn timers_run_while_waiting_for_a_key() {
+        // V0 = 3; delay and sound timers = V0; LDK V1.
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(&[0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18, 0xF1, 0x0A]).unwrap();
+        emulator.run_frame(4).unwrap();
+        assert!(emulator.is_waiting_for_key());
+        assert_eq!((emulator.delay_timer(), emulator.sound_timer()), (2, 2));
+        emulator.run_frame(10).unwrap();
+        emulator.run_frame(10).unwrap();
+        assert_eq!((emulator.delay_timer(), emulator.sound_timer()), (0, 0));
+        assert!(emulator.is_waiting_for_key());
+    }
+
+    #[test]
+    fn audio_instructions_only_run_on_xo_chip() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(&[0xF0, 0x02]).unwrap();
+        assert!(emulator.step().is_err());
+
+        emulator.set_platform(PlatformProfile::XoChip.into()).unwrap();
+        emulator.load_program(&[0x60, 0x70, 0xF0, 0x3A]).unwrap();
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.pitch(), 0x70);
+    }
+
+    #[test]
+    fn state_hash_covers_audio() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        let before = emulator.state_hash();
+        emulator.pitch = 0x70;
+        assert_ne!(emulator.state_hash(), before);
+        emulator.pitch = DEFAULT_PITCH;
+        emulator.audio_pattern = Some([0; 16]);
+        assert_ne!(emulator.state_hash(), before);
+    }
+
+    #[test]
+    fn pc_and_i_wrap_at_64k() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.set_platform(PlatformProfile::XoChip.into()).unwrap();
+        emulator.memory_mut().poke(0xFFFE, &[0x60, 0x02]).unwrap();
+        emulator.set_pc(0xFFFE);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0);
+
+        // ADD I, V0 with I at 0xFFFF.
+        emulator.memory_mut().poke(0, &[0xF0, 0x1E]).unwrap();
+        emulator.set_i(0xFFFF);
+        emulator.step().unwrap();
+        assert_eq!(emulator.i(), 1);
+    }
+
+    #[test]
+    fn code_that_writes_over_its_next_instruction_runs_the_new_one() {
+        for cached in [true, false] {
+            let mut emulator = Emulator::new(0, Quirks::default());
+            emulator.memory_mut().set_decode_cache(cached);
+            // Stores LD V3, 9 over the LD V2, 1 that follows.
+            let program: Vec<u8> = [0xA208u16, 0x6063, 0x6109, 0xF155, 0x6201].iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+            emulator.load_program(&program).unwrap();
+            emulator.set_pc(0x208);
+            emulator.step().unwrap();
+            assert_eq!(emulator.registers()[2], 1);
+
+            emulator.set_pc(0x200);
+            emulator.run_cycles(5).unwrap();
+            assert_eq!(emulator.registers()[3], 9, "decode cache {}", cached);
+        }
+    }
+}
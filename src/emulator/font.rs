@@ -0,0 +1,302 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::emulator::Result;
+
+pub const SMALL_GLYPH_HEIGHT: usize = 5;
+pub const BIG_GLYPH_HEIGHT: usize = 10;
+const SMALL_FONT_LEN: usize = 16 * SMALL_GLYPH_HEIGHT;
+
+// The font most interpreters since CHIP-48 ship with.
+const CHIP48_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,
+    0x20, 0x60, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0x90, 0x90, 0xF0, 0x10, 0x10,
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,
+    0xF0, 0x10, 0x20, 0x40, 0x40,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90,
+    0xE0, 0x90, 0xE0, 0x90, 0xE0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xE0, 0x90, 0x90, 0x90, 0xE0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0x80
+];
+
+// The COSMAC VIP ROM's digits, with its square 4, 7, B and D.
+const VIP_SMALL: [u8; SMALL_FONT_LEN] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,
+    0x60, 0x20, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0xA0, 0xA0, 0xF0, 0x20, 0x20,
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,
+    0xF0, 0x10, 0x10, 0x10, 0x10,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90,
+    0xF0, 0x50, 0x70, 0x50, 0xF0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xF0, 0x50, 0x50, 0x50, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0x80
+];
+
+// SCHIP 1.1 only has big glyphs for 0-9.
+const SCHIP_BIG: [u8; 10 * BIG_GLYPH_HEIGHT] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x07, 0x3E, 0x7C
+];
+
+const OCTO_BIG: [u8; 16 * BIG_GLYPH_HEIGHT] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0
+];
+
+// The 4x5 hex digits used by LDF and, optionally, the 8x10 digits used by
+// LDHF. In memory the big glyphs directly follow the small ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Font {
+    small: Vec<u8>,
+    big: Vec<u8>
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        FontSet::Chip48.font()
+    }
+}
+
+impl Font {
+    // 80 bytes of small glyphs, optionally followed by 100 (0-9) or 160
+    // (0-F) bytes of big ones.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.len().checked_sub(SMALL_FONT_LEN) {
+            Some(0) | Some(100) | Some(160) => Ok(Self {
+                small: bytes[..SMALL_FONT_LEN].to_vec(),
+                big: bytes[SMALL_FONT_LEN..].to_vec()
+            }),
+            _ => Err(Box::new(FontError::InvalidLength(bytes.len())))
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Font::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.small[..], &self.big[..]].concat()
+    }
+
+    pub fn len(&self) -> usize {
+        self.small.len() + self.big.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn big_glyphs(&self) -> usize {
+        self.big.len() / BIG_GLYPH_HEIGHT
+    }
+
+    pub fn glyph(&self, digit: u8) -> &[u8] {
+        let start = (digit as usize & 0xF) * SMALL_GLYPH_HEIGHT;
+        &self.small[start..start + SMALL_GLYPH_HEIGHT]
+    }
+
+    pub fn big_glyph(&self, digit: u8) -> Option<&[u8]> {
+        let start = digit as usize * BIG_GLYPH_HEIGHT;
+        self.big.get(start..start + BIG_GLYPH_HEIGHT)
+    }
+
+    pub fn set_glyph(&mut self, digit: u8, rows: [u8; SMALL_GLYPH_HEIGHT]) {
+        let start = (digit as usize & 0xF) * SMALL_GLYPH_HEIGHT;
+        self.small[start..start + SMALL_GLYPH_HEIGHT].copy_from_slice(&rows);
+    }
+
+    pub fn set_big_glyph(&mut self, digit: u8, rows: [u8; BIG_GLYPH_HEIGHT]) -> Result<()> {
+        let start = digit as usize * BIG_GLYPH_HEIGHT;
+        match self.big.get_mut(start..start + BIG_GLYPH_HEIGHT) {
+            Some(glyph) => glyph.copy_from_slice(&rows),
+            None => return Err(Box::new(FontError::NoBigGlyph(digit)))
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontSet {
+    Vip,
+    Chip48,
+    // CHIP-48 digits plus the big 0-9.
+    Schip,
+    // CHIP-48 digits plus big 0-F.
+    Octo
+}
+
+impl FontSet {
+    pub const ALL: [FontSet; 4] = [FontSet::Vip, FontSet::Chip48, FontSet::Schip, FontSet::Octo];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FontSet::Vip => "vip",
+            FontSet::Chip48 => "chip48",
+            FontSet::Schip => "schip",
+            FontSet::Octo => "octo"
+        }
+    }
+
+    pub fn font(&self) -> Font {
+        let (small, big): (&[u8], &[u8]) = match self {
+            FontSet::Vip => (&VIP_SMALL, &[]),
+            FontSet::Chip48 => (&CHIP48_SMALL, &[]),
+            FontSet::Schip => (&CHIP48_SMALL, &SCHIP_BIG),
+            FontSet::Octo => (&CHIP48_SMALL, &OCTO_BIG)
+        };
+        Font { small: small.to_vec(), big: big.to_vec() }
+    }
+}
+
+// `--font` accepts a built-in font name or a font file.
+impl FromStr for Font {
+    type Err = Box<dyn std::error::Error>;
+    fn from_str(s: &str) -> Result<Self> {
+        match FontSet::ALL.iter().find(|set| set.name() == s) {
+            Some(set) => Ok(set.font()),
+            None if Path::new(s).exists() => Font::load(Path::new(s)),
+            None => Err(Box::new(FontError::UnknownFont(s.to_string())))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    InvalidLength(usize),
+    UnknownFont(String),
+    NoBigGlyph(u8)
+}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontError::InvalidLength(len) => write!(f, "A font is 80 bytes of small digits, optionally followed by 100 or 160 bytes of big ones; got {} bytes", len),
+            FontError::UnknownFont(name) => {
+                let names: Vec<_> = FontSet::ALL.iter().map(FontSet::name).collect();
+                write!(f, "Unknown font {} (expected one of {} or a font file)", name, names.join(", "))
+            },
+            FontError::NoBigGlyph(digit) => write!(f, "This font has no big glyph for {:X}", digit)
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::emulator::Emulator;
+    use crate::emulator::memory::MemoryError;
+    use crate::emulator::platform::PlatformProfile;
+    use crate::emulator::quirks::Quirks;
+
+    // V0 = 0xA, then LD F, V0 and LD HF, V0 with I read back after each.
+    fn font_addresses(profile: PlatformProfile, font: &Font, base: u16) -> (u16, Result<u16>) {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.set_platform(profile.into()).unwrap();
+        emulator.set_font(font, base).unwrap();
+        emulator.load_program(&[0x60, 0x0A, 0xF0, 0x29, 0xF0, 0x30]).unwrap();
+        emulator.run_cycles(2).unwrap();
+        let small = emulator.i();
+        let big = emulator.step().map(|_| emulator.i());
+        (small, big)
+    }
+
+    #[test]
+    fn ldf_and_ldhf_point_into_the_font_at_its_base() {
+        let (small, big) = font_addresses(PlatformProfile::Schip, &FontSet::Octo.font(), 0x50);
+        assert_eq!(small, 0x50 + 0xA * 5);
+        assert_eq!(big.unwrap(), 0x50 + 80 + 0xA * 10);
+
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.set_font(&FontSet::Octo.font(), 0x50).unwrap();
+        let glyph = emulator.memory().get_range(0x50 + 80 + 0xA * 10, BIG_GLYPH_HEIGHT).unwrap();
+        assert_eq!(glyph, FontSet::Octo.font().big_glyph(0xA).unwrap());
+    }
+
+    #[test]
+    fn ldhf_is_only_decoded_where_the_platform_has_big_fonts() {
+        assert!(font_addresses(PlatformProfile::Chip8, &FontSet::Octo.font(), 0).1.is_err());
+        assert!(font_addresses(PlatformProfile::Vip, &FontSet::Octo.font(), 0).1.is_err());
+        assert!(font_addresses(PlatformProfile::XoChip, &FontSet::Octo.font(), 0).1.is_ok());
+    }
+
+    #[test]
+    fn font_files_load_by_path() {
+        let path = std::env::temp_dir().join(format!("lucid8-font-{}.bin", std::process::id()));
+        FontSet::Schip.font().save(&path).unwrap();
+        let loaded: Result<Font> = path.to_str().unwrap().parse();
+        fs::write(&path, [0; 81]).unwrap();
+        let short = Font::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), FontSet::Schip.font());
+        assert!(matches!(short.unwrap_err().downcast_ref::<FontError>(), Some(FontError::InvalidLength(81))));
+        assert!("no-such-font".parse::<Font>().is_err());
+    }
+
+    #[test]
+    fn fonts_have_to_end_below_the_load_address() {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        let font = FontSet::Octo.font();
+        assert!(emulator.set_font(&font, 0x200 - font.len() as u16).is_ok());
+        let error = emulator.set_font(&font, 0x200 - font.len() as u16 + 1).unwrap_err();
+        assert!(matches!(error.downcast_ref::<MemoryError>(), Some(MemoryError::FontOutOfRange(..))));
+        // The font that was there is kept.
+        assert_eq!(emulator.font_base(), 0x200 - font.len() as u16);
+    }
+
+    #[test]
+    fn glyphs_can_be_edited() {
+        let mut font = FontSet::Schip.font();
+        assert_eq!(font.big_glyphs(), 10);
+        font.set_glyph(0x1F, [0xFF; SMALL_GLYPH_HEIGHT]);
+        assert_eq!(font.glyph(0xF), &[0xFF; SMALL_GLYPH_HEIGHT]);
+        assert!(font.set_big_glyph(9, [0; BIG_GLYPH_HEIGHT]).is_ok());
+        assert!(font.set_big_glyph(0xA, [0; BIG_GLYPH_HEIGHT]).is_err());
+        assert_eq!(Font::from_bytes(&font.to_bytes()).unwrap(), font);
+    }
+}
@@ -1,8 +1,9 @@
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 use super::emulator::{MemoryAddress, RegisterAddress};
+use super::platform::Platform;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     CLS,
     RET,
@@ -35,26 +36,194 @@ pub enum Instruction {
     LDS(RegisterAddress),
     ADDI(RegisterAddress),
     LDF(RegisterAddress),
+    LDHF(RegisterAddress),
     LDB(RegisterAddress),
-    LDIV,
-    LDVI
+    LDIV(RegisterAddress),
+    LDVI(RegisterAddress),
+    AUDIO,
+    PITCH(RegisterAddress)
 }
 
 #[derive(Debug)]
 pub enum InstructionError {
-    BadCode
+    BadCode,
+    Unsupported(Instruction)
 }
 
 impl Display for InstructionError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            InstructionError::BadCode => write!(f, "Invalid instruction code!")
+            InstructionError::BadCode => write!(f, "Invalid instruction code!"),
+            InstructionError::Unsupported(instruction) => write!(f, "{} ({}) isn't available on this platform", instruction, instruction.pattern())
         }
     }
 }
 
 impl std::error::Error for InstructionError {}
 
+impl Instruction {
+    // Whether the instruction runs on `platform`.
+    pub fn is_supported(&self, platform: &Platform) -> bool {
+        match self {
+            Instruction::LDHF(_) => platform.big_font,
+            Instruction::AUDIO | Instruction::PITCH(_) => platform.xo_chip_audio,
+            _ => true
+        }
+    }
+
+    // The opcode with its operand nibbles as letters, e.g. `6XNN`.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::CLS => "00E0",
+            Instruction::RET => "00EE",
+            Instruction::JP(_) => "1NNN",
+            Instruction::CALL(_) => "2NNN",
+            Instruction::SE(..) => "3XNN",
+            Instruction::SNE(..) => "4XNN",
+            Instruction::SEV(..) => "5XY0",
+            Instruction::LD(..) => "6XNN",
+            Instruction::ADD(..) => "7XNN",
+            Instruction::LDV(..) => "8XY0",
+            Instruction::OR(..) => "8XY1",
+            Instruction::AND(..) => "8XY2",
+            Instruction::XOR(..) => "8XY3",
+            Instruction::ADDV(..) => "8XY4",
+            Instruction::SUB(..) => "8XY5",
+            Instruction::SHR(_) => "8XY6",
+            Instruction::SUBN(..) => "8XY7",
+            Instruction::SHL(_) => "8XYE",
+            Instruction::SNEV(..) => "9XY0",
+            Instruction::LDI(_) => "ANNN",
+            Instruction::JPV(_) => "BNNN",
+            Instruction::RND(..) => "CXNN",
+            Instruction::DRW(..) => "DXYN",
+            Instruction::SKP(_) => "EX9E",
+            Instruction::SKNP(_) => "EXA1",
+            Instruction::LDD(_) => "FX07",
+            Instruction::LDK(_) => "FX0A",
+            Instruction::LDDV(_) => "FX15",
+            Instruction::LDS(_) => "FX18",
+            Instruction::ADDI(_) => "FX1E",
+            Instruction::LDF(_) => "FX29",
+            Instruction::LDHF(_) => "FX30",
+            Instruction::LDB(_) => "FX33",
+            Instruction::LDIV(_) => "FX55",
+            Instruction::LDVI(_) => "FX65",
+            Instruction::AUDIO => "F002",
+            Instruction::PITCH(_) => "FX3A"
+        }
+    }
+
+    // What the instruction does, in a sentence.
+    pub fn summary(&self) -> &'static str {
+        match self {
+            Instruction::CLS => "Clears the display.",
+            Instruction::RET => "Returns from a subroutine.",
+            Instruction::JP(_) => "Jumps to NNN.",
+            Instruction::CALL(_) => "Calls the subroutine at NNN.",
+            Instruction::SE(..) => "Skips the next instruction if VX equals NN.",
+            Instruction::SNE(..) => "Skips the next instruction if VX doesn't equal NN.",
+            Instruction::SEV(..) => "Skips the next instruction if VX equals VY.",
+            Instruction::LD(..) => "Sets VX to NN.",
+            Instruction::ADD(..) => "Adds NN to VX without touching the carry flag.",
+            Instruction::LDV(..) => "Sets VX to VY.",
+            Instruction::OR(..) => "Sets VX to VX OR VY.",
+            Instruction::AND(..) => "Sets VX to VX AND VY.",
+            Instruction::XOR(..) => "Sets VX to VX XOR VY.",
+            Instruction::ADDV(..) => "Adds VY to VX; VF is 1 on carry, 0 otherwise.",
+            Instruction::SUB(..) => "Sets VX to VX - VY; VF is 1 if there was no borrow.",
+            Instruction::SHR(_) => "Shifts VX right by one; VF gets the bit shifted out.",
+            Instruction::SUBN(..) => "Sets VX to VY - VX; VF is 1 if there was no borrow.",
+            Instruction::SHL(_) => "Shifts VX left by one; VF gets the bit shifted out.",
+            Instruction::SNEV(..) => "Skips the next instruction if VX doesn't equal VY.",
+            Instruction::LDI(_) => "Sets I to NNN.",
+            Instruction::JPV(_) => "Jumps to NNN + V0.",
+            Instruction::RND(..) => "Sets VX to a random byte AND NN.",
+            Instruction::DRW(..) => "XORs the N-byte sprite at I onto the display at (VX, VY); VF is 1 if any pixel was erased.",
+            Instruction::SKP(_) => "Skips the next instruction if the key in VX is held.",
+            Instruction::SKNP(_) => "Skips the next instruction if the key in VX isn't held.",
+            Instruction::LDD(_) => "Sets VX to the delay timer.",
+            Instruction::LDK(_) => "Waits for a key press and release and stores the key in VX.",
+            Instruction::LDDV(_) => "Sets the delay timer to VX.",
+            Instruction::LDS(_) => "Sets the sound timer to VX.",
+            Instruction::ADDI(_) => "Adds VX to I.",
+            Instruction::LDF(_) => "Points I at the small font glyph for the digit in VX.",
+            Instruction::LDHF(_) => "Points I at the big font glyph for the digit in VX.",
+            Instruction::LDB(_) => "Stores the hundreds, tens and ones of VX at I, I+1 and I+2.",
+            Instruction::LDIV(_) => "Stores V0 to VX in memory starting at I.",
+            Instruction::LDVI(_) => "Loads V0 to VX from memory starting at I.",
+            Instruction::AUDIO => "Loads the 16-byte audio pattern at I.",
+            Instruction::PITCH(_) => "Sets the audio pattern's playback pitch to VX."
+        }
+    }
+
+    // How interpreters disagree about this instruction, if they do.
+    pub fn quirk(&self) -> Option<&'static str> {
+        match self {
+            Instruction::OR(..) | Instruction::AND(..) | Instruction::XOR(..) =>
+                Some("The COSMAC VIP also clears VF (`logic_resets_vf`)."),
+            Instruction::SHR(_) | Instruction::SHL(_) =>
+                Some("The COSMAC VIP shifts VY into VX; CHIP-48 and later, and this interpreter, shift VX in place."),
+            Instruction::JPV(_) =>
+                Some("CHIP-48 and SUPER-CHIP jump to XNN + VX instead (`jump_uses_vx`)."),
+            Instruction::DRW(..) =>
+                Some("Sprites wrap around the screen edge unless `clip_sprites` is set, as on the VIP and SUPER-CHIP."),
+            Instruction::LDIV(_) | Instruction::LDVI(_) =>
+                Some("The COSMAC VIP leaves I pointing past the last register; CHIP-48 and later, and this interpreter, leave it unchanged."),
+            Instruction::LDHF(_) =>
+                Some("SUPER-CHIP and XO-CHIP only; other platforms treat it as an invalid instruction."),
+            Instruction::AUDIO | Instruction::PITCH(_) =>
+                Some("XO-CHIP only; other platforms treat it as an invalid instruction."),
+            _ => None
+        }
+    }
+}
+
+// Disassembly in the usual mnemonic syntax, e.g. `LD V0, 0x0B`.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::CLS => write!(f, "CLS"),
+            Instruction::RET => write!(f, "RET"),
+            Instruction::JP(addr) => write!(f, "JP 0x{:03X}", addr),
+            Instruction::CALL(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SE(x, byte) => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SNE(x, byte) => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            Instruction::SEV(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LD(x, byte) => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            Instruction::ADD(x, byte) => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            Instruction::LDV(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::ADDV(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SUB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::SHR(x) => write!(f, "SHR V{:X}", x),
+            Instruction::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::SHL(x) => write!(f, "SHL V{:X}", x),
+            Instruction::SNEV(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LDI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JPV(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::RND(x, byte) => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Instruction::DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SKP(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SKNP(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LDD(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LDK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LDDV(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LDS(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::ADDI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LDF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LDHF(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LDB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LDIV(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LDVI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::AUDIO => write!(f, "AUDIO"),
+            Instruction::PITCH(x) => write!(f, "PITCH V{:X}", x)
+        }
+    }
+}
+
 impl TryFrom<u16> for Instruction {
     type Error = Box<InstructionError>;
     fn try_from(value: u16) -> Result<Self, Self::Error> {
@@ -91,9 +260,12 @@ impl TryFrom<u16> for Instruction {
             [0xF, a, 1, 8] => Ok(Instruction::LDS(a)),
             [0xF, a, 1, 0xE] => Ok(Instruction::ADDI(a)),
             [0xF, a, 2, 9] => Ok(Instruction::LDF(a)),
+            [0xF, a, 3, 0] => Ok(Instruction::LDHF(a)),
             [0xF, a, 3, 3] => Ok(Instruction::LDB(a)),
-            [0xF, _, 5, 5] => Ok(Instruction::LDIV),
-            [0xF, _, 6, 5] => Ok(Instruction::LDVI),
+            [0xF, a, 5, 5] => Ok(Instruction::LDIV(a)),
+            [0xF, a, 6, 5] => Ok(Instruction::LDVI(a)),
+            [0xF, 0, 0, 2] => Ok(Instruction::AUDIO),
+            [0xF, a, 3, 0xA] => Ok(Instruction::PITCH(a)),
             _ => Err(Box::new(InstructionError::BadCode))
         }
     }
@@ -1,83 +1,547 @@
 use super::emulator::{MemoryAddress, Result};
+use super::font::{Font, BIG_GLYPH_HEIGHT, SMALL_GLYPH_HEIGHT};
+use super::instructions::{Instruction, InstructionError};
+use super::platform::Platform;
+use crate::input::program::ProgramError;
+use std::cell::RefCell;
+use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::str::FromStr;
 
-const HEX_SPRITES: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0,
-    0x20, 0x60, 0x20, 0x20, 0x70,
-    0xF0, 0x10, 0xF0, 0x80, 0xF0,
-    0xF0, 0x10, 0xF0, 0x10, 0xF0,
-    0x90, 0x90, 0xF0, 0x10, 0x10,
-    0xF0, 0x80, 0xF0, 0x10, 0xF0,
-    0xF0, 0x80, 0xF0, 0x90, 0xF0,
-    0xF0, 0x10, 0x20, 0x40, 0x40,
-    0xF0, 0x90, 0xF0, 0x90, 0xF0,
-    0xF0, 0x90, 0xF0, 0x10, 0xF0,
-    0xF0, 0x90, 0xF0, 0x90, 0x90,
-    0xE0, 0x90, 0xE0, 0x90, 0xE0,
-    0xF0, 0x80, 0x80, 0x80, 0xF0,
-    0xE0, 0x90, 0x90, 0x90, 0xE0,
-    0xF0, 0x80, 0xF0, 0x80, 0xF0,
-    0xF0, 0x80, 0xF0, 0x80, 0x80
-];
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegionKind {
+    Interpreter,
+    Font,
+    Program,
+    Free,
+    Stack,
+    // The VIP keeps its display buffer in the top 256 bytes.
+    Display
+}
+
+impl FromStr for RegionKind {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(RegionKind::Interpreter),
+            "font" => Ok(RegionKind::Font),
+            "program" => Ok(RegionKind::Program),
+            "free" => Ok(RegionKind::Free),
+            "stack" => Ok(RegionKind::Stack),
+            "display" => Ok(RegionKind::Display),
+            _ => Err(format!("unknown memory region: {} (expected interpreter, font, program, free, stack or display)", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub start: MemoryAddress,
+    pub len: usize
+}
+
+impl Region {
+    pub fn contains(&self, addr: MemoryAddress) -> bool {
+        addr >= self.start && (addr as usize) < self.start as usize + self.len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryLayout {
+    #[default]
+    Standard,
+    // COSMAC VIP: the stack, interpreter variables and display buffer are
+    // reserved at the top of memory (0xEA0, 0xED0 and 0xF00 on a 4K machine).
+    Vip
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Fetch,
+    Read,
+    Write
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: MemoryAddress,
+    pub len: usize
+}
+
+// Told about every fetch, read and write the CPU makes. Loading programs and
+// fonts and inspecting memory through `get_range` or `as_bytes` are not
+// reported.
+pub trait MemoryObserver: std::fmt::Debug {
+    fn access(&mut self, access: Access);
+}
+
+// Per-address access counts, e.g. for a heatmap.
+#[derive(Debug, Default, Clone)]
+pub struct AccessCounter {
+    pub fetches: Vec<u64>,
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>
+}
+
+impl MemoryObserver for AccessCounter {
+    fn access(&mut self, access: Access) {
+        let counts = match access.kind {
+            AccessKind::Fetch => &mut self.fetches,
+            AccessKind::Read => &mut self.reads,
+            AccessKind::Write => &mut self.writes
+        };
+        let (start, end) = (access.addr as usize, access.addr as usize + access.len);
+        if counts.len() < end {
+            counts.resize(end, 0);
+        }
+        counts[start..end].iter_mut().for_each(|count| *count += 1);
+    }
+}
 
 #[derive(Debug)]
 pub struct Memory {
-    buffer: [u8; 4096]
+    buffer: Vec<u8>,
+    platform: Platform,
+    font: Font,
+    font_base: MemoryAddress,
+    program_len: usize,
+    protected: Vec<RegionKind>,
+    observers: Vec<Rc<RefCell<dyn MemoryObserver>>>,
+    // The instruction decoded at each address, kept until one of its two
+    // bytes changes.
+    decoded: Vec<Option<Instruction>>,
+    cache_decodes: bool
 }
 
 impl Default for Memory {
     fn default() -> Self {
-        let mut memory: [u8; 4096] = [0; 4096];
-        memory[..80].copy_from_slice(&HEX_SPRITES[..]);
-        Self {
-            buffer: memory
-        }
+        Memory::new(Platform::default()).expect("the default font fits below the program")
     }
 }
 
 impl Memory {
+    pub fn new(platform: Platform) -> Result<Self> {
+        let mut memory = Self {
+            buffer: vec![0; platform.memory_size],
+            platform,
+            font: Font::default(),
+            font_base: 0,
+            program_len: 0,
+            protected: vec![],
+            observers: vec![],
+            decoded: vec![None; platform.memory_size],
+            cache_decodes: true
+        };
+        memory.load_font(&Font::default(), 0)?;
+        Ok(memory)
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    // Resizes memory for another platform, keeping the font and clearing the
+    // program.
+    pub fn set_platform(&mut self, platform: Platform) -> Result<()> {
+        if self.font_base as usize + self.font.len() > platform.load_address as usize {
+            return Err(Box::new(MemoryError::FontOutOfRange(self.font_base, self.font.len(), platform.load_address)));
+        }
+        self.platform = platform;
+        self.buffer.resize(platform.memory_size, 0);
+        self.decoded = vec![None; platform.memory_size];
+        self.clear();
+        Ok(())
+    }
+
+    // Replaces the current font. It has to fit in the interpreter area below
+    // the load address so loading a program doesn't overwrite it.
+    pub fn load_font(&mut self, font: &Font, base: MemoryAddress) -> Result<()> {
+        if base as usize + font.len() > self.platform.load_address as usize {
+            return Err(Box::new(MemoryError::FontOutOfRange(base, font.len(), self.platform.load_address)));
+        }
+        let old = self.font_base as usize;
+        self.buffer[old..old + self.font.len()].iter_mut().for_each(|byte| *byte = 0);
+        self.invalidate(old, self.font.len());
+        self.buffer[base as usize..base as usize + font.len()].copy_from_slice(&font.to_bytes());
+        self.invalidate(base as usize, font.len());
+        self.font = font.clone();
+        self.font_base = base;
+        Ok(())
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    pub fn font_base(&self) -> MemoryAddress {
+        self.font_base
+    }
+
+    // Where LDF points I for a hex digit.
+    pub fn font_address(&self, digit: u8) -> MemoryAddress {
+        self.font_base + (digit & 0xF) as MemoryAddress * SMALL_GLYPH_HEIGHT as MemoryAddress
+    }
+
+    // Where LDHF points I; the big glyphs follow the 16 small ones.
+    pub fn big_font_address(&self, digit: u8) -> MemoryAddress {
+        self.font_address(0) + 16 * SMALL_GLYPH_HEIGHT as MemoryAddress + (digit & 0xF) as MemoryAddress * BIG_GLYPH_HEIGHT as MemoryAddress
+    }
+
     pub fn clear(&mut self) {
-        self.buffer[0x200..].clone_from_slice(&[0; 3584]);
+        let start = self.platform.load_address as usize;
+        self.buffer[start..].iter_mut().for_each(|byte| *byte = 0);
+        self.invalidate(start, self.buffer.len() - start);
+        self.program_len = 0;
+    }
+
+    // Copies a program to the load address regardless of write protection,
+    // zeroing whatever is left of a longer previous one.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<()> {
+        if program.len() > self.platform.program_capacity() {
+            return Err(Box::new(ProgramError::SpaceExceeded { size: program.len(), capacity: self.platform.program_capacity() }));
+        }
+        let start = self.platform.load_address as usize;
+        self.buffer[start..start + program.len()].copy_from_slice(program);
+        if self.program_len > program.len() {
+            self.buffer[start + program.len()..start + self.program_len].iter_mut().for_each(|byte| *byte = 0);
+        }
+        self.invalidate(start, program.len().max(self.program_len));
+        self.program_len = program.len();
+        Ok(())
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn layout(&self) -> MemoryLayout {
+        self.platform.layout
+    }
+
+    pub fn set_layout(&mut self, layout: MemoryLayout) {
+        self.platform.layout = layout;
+    }
+
+    // Where the VIP keeps its call stack, 0x160 bytes below the top.
+    pub fn stack_base(&self) -> MemoryAddress {
+        (self.buffer.len() - 0x160) as MemoryAddress
+    }
+
+    // The memory map in address order. Gaps in the interpreter area around
+    // the font are reported as separate interpreter regions.
+    pub fn regions(&self) -> Vec<Region> {
+        let font_end = self.font_base as usize + self.font.len();
+        let load_address = self.platform.load_address as usize;
+        let program_end = load_address + self.program_len;
+        let top = self.buffer.len();
+        let reserved = match self.platform.layout {
+            MemoryLayout::Standard => vec![],
+            MemoryLayout::Vip => vec![(RegionKind::Stack, self.stack_base() as usize, top - 0x130), (RegionKind::Interpreter, top - 0x130, top - 0x100), (RegionKind::Display, top - 0x100, top)]
+        };
+        let free_end = reserved.first().map_or(self.buffer.len(), |(_, start, _)| *start).max(program_end);
+        let mut bounds = vec![
+            (RegionKind::Interpreter, 0, self.font_base as usize),
+            (RegionKind::Font, self.font_base as usize, font_end),
+            (RegionKind::Interpreter, font_end, load_address),
+            (RegionKind::Program, load_address, program_end),
+            (RegionKind::Free, program_end, free_end)
+        ];
+        bounds.extend(reserved.into_iter().map(|(kind, start, end)| (kind, start.max(program_end), end)));
+        bounds.into_iter()
+            .filter(|(_, start, end)| start < end)
+            .map(|(kind, start, end)| Region { kind, start: start as MemoryAddress, len: end - start })
+            .collect()
+    }
+
+    pub fn region_at(&self, addr: MemoryAddress) -> Option<Region> {
+        self.regions().into_iter().find(|region| region.contains(addr))
+    }
+
+    // CPU writes into a protected region fail with `MemoryError::WriteProtected`.
+    pub fn set_protected(&mut self, kind: RegionKind, protected: bool) {
+        self.protected.retain(|protected_kind| *protected_kind != kind);
+        if protected {
+            self.protected.push(kind);
+        }
+    }
+
+    pub fn is_protected(&self, kind: RegionKind) -> bool {
+        self.protected.contains(&kind)
+    }
+
+    pub fn add_observer(&mut self, observer: Rc<RefCell<dyn MemoryObserver>>) {
+        self.observers.push(observer);
+    }
+
+    pub fn clear_observers(&mut self) {
+        self.observers.clear();
+    }
+
+    fn notify(&self, kind: AccessKind, addr: MemoryAddress, len: usize) {
+        for observer in &self.observers {
+            observer.borrow_mut().access(Access { kind, addr, len });
+        }
+    }
+
+    // Inspects memory without notifying observers.
     pub fn get_range(&self, start_addr: MemoryAddress, length: usize) -> Result<&[u8]> {
-        if start_addr as usize + length >= self.buffer.len() {
+        if start_addr as usize + length > self.buffer.len() {
             return Err(Box::new(MemoryError::OutOfBounds(start_addr, length)))
         }
         Ok(&self.buffer[start_addr as usize .. start_addr as usize + length])
     }
 
-    pub fn set_range(&mut self, start_addr: MemoryAddress, data: &[u8]) -> Result<()> {
-        let data_len = data.len();
-        if start_addr as usize + data_len > self.buffer.len() {
-            return Err(Box::new(MemoryError::OutOfMemory(start_addr, start_addr as usize + data_len - self.buffer.len())));
+    // Edits memory from outside the CPU, e.g. a debugger. Ignores write
+    // protection and isn't reported to observers.
+    pub fn poke(&mut self, start_addr: MemoryAddress, data: &[u8]) -> Result<()> {
+        if start_addr as usize + data.len() > self.buffer.len() {
+            return Err(Box::new(MemoryError::OutOfBounds(start_addr, data.len())));
         }
         self.buffer[start_addr as usize..start_addr as usize + data.len()].copy_from_slice(data);
+        self.invalidate(start_addr as usize, data.len());
         Ok(())
     }
 
-    pub fn set_byte(&mut self, addr: MemoryAddress, byte: u8) -> Result<()> {
-        if addr as usize >= self.buffer.len() || addr < 0 {
-            return Err(Box::new(MemoryError::OutOfBounds(addr, 0)));
+    // An instruction fetch by the CPU.
+    pub fn fetch(&self, addr: MemoryAddress) -> Result<&[u8]> {
+        let bytes = self.get_range(addr, 2)?;
+        self.notify(AccessKind::Fetch, addr, 2);
+        Ok(bytes)
+    }
+
+    // An instruction fetch by the CPU, decoded. Each address is decoded once
+    // and reused until something writes over it. Instructions the platform
+    // doesn't have fail to decode.
+    pub fn fetch_instruction(&mut self, addr: MemoryAddress) -> Result<Instruction> {
+        if let Some(Some(instruction)) = self.decoded.get(addr as usize) {
+            self.notify(AccessKind::Fetch, addr, 2);
+            return Ok(*instruction);
+        }
+        let instruction = Instruction::try_from(self.fetch(addr)?)?;
+        if !instruction.is_supported(&self.platform) {
+            return Err(Box::new(InstructionError::Unsupported(instruction)));
+        }
+        if self.cache_decodes {
+            self.decoded[addr as usize] = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    // Turning the decode cache off makes every fetch decode again, which is
+    // only useful for measuring what it saves.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache_decodes = enabled;
+        if !enabled {
+            self.decoded.iter_mut().for_each(|decoded| *decoded = None);
         }
-        self.buffer[addr as usize] = byte;
+    }
+
+    // Forgets decodes overlapping `len` bytes from `start`, including one
+    // starting the byte before, whose second byte is `start`.
+    fn invalidate(&mut self, start: usize, len: usize) {
+        let end = (start + len).min(self.decoded.len());
+        let start = start.saturating_sub(1).min(end);
+        self.decoded[start..end].iter_mut().for_each(|decoded| *decoded = None);
+    }
+
+    // A data read by the CPU.
+    pub fn read(&self, start_addr: MemoryAddress, length: usize) -> Result<&[u8]> {
+        let bytes = self.get_range(start_addr, length)?;
+        self.notify(AccessKind::Read, start_addr, length);
+        Ok(bytes)
+    }
+
+    fn check_write(&self, start_addr: MemoryAddress, length: usize) -> Result<()> {
+        if self.protected.is_empty() {
+            return Ok(());
+        }
+        let end = start_addr as usize + length;
+        for region in self.regions().iter().filter(|region| self.is_protected(region.kind)) {
+            let overlap = (start_addr as usize).max(region.start as usize);
+            if overlap < end.min(region.start as usize + region.len) {
+                return Err(Box::new(MemoryError::WriteProtected(overlap as MemoryAddress, region.kind)));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_bounds(&self, start_addr: MemoryAddress, length: usize) -> Result<()> {
+        if start_addr as usize + length > self.buffer.len() {
+            return Err(Box::new(MemoryError::OutOfMemory(start_addr, start_addr as usize + length - self.buffer.len())));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, start_addr: MemoryAddress, data: &[u8]) {
+        self.buffer[start_addr as usize..start_addr as usize + data.len()].copy_from_slice(data);
+        self.invalidate(start_addr as usize, data.len());
+        self.notify(AccessKind::Write, start_addr, data.len());
+    }
+
+    pub fn set_range(&mut self, start_addr: MemoryAddress, data: &[u8]) -> Result<()> {
+        self.check_bounds(start_addr, data.len())?;
+        self.check_write(start_addr, data.len())?;
+        self.write(start_addr, data);
         Ok(())
     }
+
+    // A write the interpreter makes for itself, such as a return address on
+    // the VIP's in-memory stack. Observers see it, but write protection,
+    // which guards against the program, doesn't apply.
+    pub fn set_range_unprotected(&mut self, start_addr: MemoryAddress, data: &[u8]) -> Result<()> {
+        self.check_bounds(start_addr, data.len())?;
+        self.write(start_addr, data);
+        Ok(())
+    }
+
+    pub fn set_byte(&mut self, addr: MemoryAddress, byte: u8) -> Result<()> {
+        self.set_range(addr, &[byte])
+    }
 }
 
 #[derive(Debug)]
 pub enum MemoryError {
     OutOfBounds(MemoryAddress, usize),
-    OutOfMemory(MemoryAddress, usize)
+    OutOfMemory(MemoryAddress, usize),
+    FontOutOfRange(MemoryAddress, usize, MemoryAddress),
+    WriteProtected(MemoryAddress, RegionKind)
 }
 
 impl Display for MemoryError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            MemoryError::OutOfBounds(addr, length) => write!(f, "The memory range accessed is out of bounds (max 4096): [{} ... {}]", addr, *addr + *length as u16),
-            MemoryError::OutOfMemory(addr, excess_bytes) => write!(f, "Attempting to write memory past bounds, starting at {}. [Excess bytes: {}]", addr, excess_bytes)
+            MemoryError::OutOfBounds(addr, length) => write!(f, "The memory range accessed is out of bounds: [{} ... {}]", addr, *addr as usize + *length),
+            MemoryError::OutOfMemory(addr, excess_bytes) => write!(f, "Attempting to write memory past bounds, starting at {}. [Excess bytes: {}]", addr, excess_bytes),
+            MemoryError::FontOutOfRange(addr, length, load_address) => write!(f, "A {} byte font at {:#05x} doesn't fit below the program at {:#05x}", length, addr, load_address),
+            MemoryError::WriteProtected(addr, kind) => write!(f, "Write to {:#05x} in the write-protected {:?} region", addr, kind)
         }
     }
 }
 
-impl std::error::Error for MemoryError {}
\ No newline at end of file
+impl std::error::Error for MemoryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(memory: &Memory) -> Vec<(RegionKind, MemoryAddress, usize)> {
+        memory.regions().iter().map(|region| (region.kind, region.start, region.len)).collect()
+    }
+
+    #[test]
+    fn regions_cover_memory_in_order() {
+        let mut memory = Memory::default();
+        memory.load_font(&Font::default(), 0x50).unwrap();
+        memory.load_program(&[0; 0x10]).unwrap();
+        assert_eq!(kinds(&memory), vec![
+            (RegionKind::Interpreter, 0, 0x50),
+            (RegionKind::Font, 0x50, 80),
+            (RegionKind::Interpreter, 0xA0, 0x160),
+            (RegionKind::Program, 0x200, 0x10),
+            (RegionKind::Free, 0x210, 0xDF0)
+        ]);
+        assert_eq!(memory.region_at(0x20F).unwrap().kind, RegionKind::Program);
+
+        memory.set_layout(MemoryLayout::Vip);
+        assert_eq!(kinds(&memory)[4..], [
+            (RegionKind::Free, 0x210, 0xC90),
+            (RegionKind::Stack, 0xEA0, 0x30),
+            (RegionKind::Interpreter, 0xED0, 0x30),
+            (RegionKind::Display, 0xF00, 0x100)
+        ]);
+    }
+
+    #[test]
+    fn protected_writes_fault_at_the_first_protected_byte() {
+        let mut memory = Memory::default();
+        memory.load_program(&[0; 4]).unwrap();
+        memory.set_protected(RegionKind::Program, true);
+        // Starts in the font's interpreter gap and runs into the program.
+        let error = memory.set_range(0x1FE, &[1, 2, 3]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<MemoryError>(), Some(MemoryError::WriteProtected(0x200, RegionKind::Program))));
+        assert_eq!(memory.get_range(0x1FE, 3).unwrap(), &[0, 0, 0]);
+        assert!(memory.set_byte(0x203, 1).is_err());
+
+        // Other regions, and edits from outside the CPU, still go through.
+        memory.set_range(0x204, &[7, 7]).unwrap();
+        memory.set_byte(0x100, 9).unwrap();
+        memory.poke(0x200, &[5]).unwrap();
+        assert_eq!(memory.get_range(0x200, 1).unwrap(), &[5]);
+
+        memory.set_protected(RegionKind::Program, false);
+        assert!(!memory.is_protected(RegionKind::Program));
+        memory.set_byte(0x203, 1).unwrap();
+    }
+
+    #[test]
+    fn observers_see_cpu_accesses_only() {
+        let mut memory = Memory::default();
+        memory.load_program(&[0x60, 0x01, 0x61, 0x02]).unwrap();
+        let counter = Rc::new(RefCell::new(AccessCounter::default()));
+        memory.add_observer(counter.clone());
+        memory.set_range(0x300, &[1, 2]).unwrap();
+        memory.read(0x300, 1).unwrap();
+        memory.fetch_instruction(0x200).unwrap();
+        // Not reported: inspection and debugger edits.
+        memory.get_range(0x300, 2).unwrap();
+        memory.poke(0x300, &[0]).unwrap();
+        {
+            let counter = counter.borrow();
+            assert_eq!(counter.writes[0x300..0x302], [1, 1]);
+            assert_eq!(counter.reads[0x300], 1);
+            assert_eq!(counter.reads.get(0x301).copied().unwrap_or(0), 0);
+            assert_eq!(counter.fetches[0x200..0x202], [1, 1]);
+        }
+
+        // A refused write isn't reported either.
+        memory.set_protected(RegionKind::Free, true);
+        assert!(memory.set_byte(0x300, 1).is_err());
+        assert_eq!(counter.borrow().writes[0x300], 1);
+
+        memory.clear_observers();
+        memory.read(0x300, 1).unwrap();
+        assert_eq!(counter.borrow().reads[0x300], 1);
+    }
+
+    #[test]
+    fn writes_drop_the_decodes_they_touch() {
+        let mut memory = Memory::default();
+        memory.load_program(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]).unwrap();
+        for addr in [0x200, 0x202, 0x204] {
+            memory.fetch_instruction(addr).unwrap();
+        }
+        // The second byte of 0x200 and the first of 0x202.
+        memory.set_range(0x201, &[0x05, 0x63]).unwrap();
+        assert!(memory.decoded[0x200].is_none());
+        assert!(memory.decoded[0x202].is_none());
+        assert!(memory.decoded[0x204].is_some());
+        assert_eq!(memory.fetch_instruction(0x200).unwrap(), Instruction::LD(0, 0x05));
+        assert_eq!(memory.fetch_instruction(0x202).unwrap(), Instruction::LD(3, 0x02));
+
+        memory.poke(0x204, &[0x64]).unwrap();
+        assert_eq!(memory.fetch_instruction(0x204).unwrap(), Instruction::LD(4, 0x03));
+    }
+
+    #[test]
+    fn a_disabled_decode_cache_decodes_every_fetch() {
+        let mut memory = Memory::default();
+        memory.load_program(&[0x60, 0x01]).unwrap();
+        memory.fetch_instruction(0x200).unwrap();
+        assert!(memory.decoded[0x200].is_some());
+
+        memory.set_decode_cache(false);
+        assert!(memory.decoded.iter().all(Option::is_none));
+        assert_eq!(memory.fetch_instruction(0x200).unwrap(), Instruction::LD(0, 0x01));
+        assert!(memory.decoded[0x200].is_none());
+
+        memory.set_decode_cache(true);
+        memory.fetch_instruction(0x200).unwrap();
+        assert!(memory.decoded[0x200].is_some());
+    }
+}
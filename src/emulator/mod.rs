@@ -1,6 +1,12 @@
+#[allow(clippy::module_inception)]
 pub mod emulator;
 mod registers;
-mod memory;
+pub mod memory;
+pub mod font;
 pub mod instructions;
 mod timed;
-mod display;
\ No newline at end of file
+pub mod quirks;
+pub mod platform;
+pub mod stack;
+pub mod reference;
+pub mod display;
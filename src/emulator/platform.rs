@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::emulator::MemoryAddress;
+use super::memory::MemoryLayout;
+
+// Memory size, where programs are loaded and start running, and how deep
+// subroutine calls can nest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Platform {
+    pub memory_size: usize,
+    pub load_address: MemoryAddress,
+    pub layout: MemoryLayout,
+    pub stack_depth: usize,
+    // Keep return addresses in memory at `Memory::stack_base` like the VIP
+    // does, where programs can read and overwrite them.
+    pub stack_in_memory: bool,
+    // SUPER-CHIP's FX30, which points I at a big font glyph.
+    pub big_font: bool,
+    // XO-CHIP's audio instructions, F002 and FX3A.
+    pub xo_chip_audio: bool
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        PlatformProfile::Chip8.into()
+    }
+}
+
+impl Platform {
+    // Largest program that fits between the load address and the end of
+    // memory.
+    pub fn program_capacity(&self) -> usize {
+        self.memory_size.saturating_sub(self.load_address as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlatformProfile {
+    Chip8,
+    // The COSMAC VIP's 12-level stack lives in memory below the display
+    // buffer.
+    Vip,
+    Schip,
+    // The ETI-660 loads programs at 0x600.
+    Eti660,
+    XoChip
+}
+
+impl From<PlatformProfile> for Platform {
+    fn from(profile: PlatformProfile) -> Self {
+        match profile {
+            PlatformProfile::Chip8 => Platform { memory_size: 0x1000, load_address: 0x200, layout: MemoryLayout::Standard, stack_depth: 16, stack_in_memory: false, big_font: false, xo_chip_audio: false },
+            PlatformProfile::Vip => Platform { memory_size: 0x1000, load_address: 0x200, layout: MemoryLayout::Vip, stack_depth: 12, stack_in_memory: true, big_font: false, xo_chip_audio: false },
+            PlatformProfile::Schip => Platform { memory_size: 0x1000, load_address: 0x200, layout: MemoryLayout::Standard, stack_depth: 16, stack_in_memory: false, big_font: true, xo_chip_audio: false },
+            PlatformProfile::Eti660 => Platform { memory_size: 0x1000, load_address: 0x600, layout: MemoryLayout::Standard, stack_depth: 12, stack_in_memory: false, big_font: false, xo_chip_audio: false },
+            PlatformProfile::XoChip => Platform { memory_size: 0x10000, load_address: 0x200, layout: MemoryLayout::Standard, stack_depth: 256, stack_in_memory: false, big_font: true, xo_chip_audio: true }
+        }
+    }
+}
+
+impl FromStr for PlatformProfile {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip8" => Ok(PlatformProfile::Chip8),
+            "vip" => Ok(PlatformProfile::Vip),
+            "schip" => Ok(PlatformProfile::Schip),
+            "eti660" => Ok(PlatformProfile::Eti660),
+            "xochip" => Ok(PlatformProfile::XoChip),
+            _ => Err(format!("unknown platform: {}", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_set_memory_and_load_address() {
+        let chip8 = Platform::default();
+        assert_eq!(chip8, PlatformProfile::Chip8.into());
+        assert_eq!((chip8.memory_size, chip8.load_address, chip8.stack_depth), (0x1000, 0x200, 16));
+        assert_eq!(chip8.program_capacity(), 0xE00);
+
+        let vip: Platform = PlatformProfile::Vip.into();
+        assert_eq!((vip.layout, vip.stack_depth, vip.stack_in_memory), (MemoryLayout::Vip, 12, true));
+
+        let eti660: Platform = PlatformProfile::Eti660.into();
+        assert_eq!(eti660.load_address, 0x600);
+        assert_eq!(eti660.program_capacity(), 0xA00);
+
+        let schip: Platform = PlatformProfile::Schip.into();
+        assert!(schip.big_font && !schip.xo_chip_audio);
+
+        let xo_chip: Platform = PlatformProfile::XoChip.into();
+        assert_eq!(xo_chip.program_capacity(), 0xFE00);
+        assert!(xo_chip.big_font && xo_chip.xo_chip_audio);
+
+        // Only the VIP keeps its stack in memory.
+        let profiles = [PlatformProfile::Chip8, PlatformProfile::Schip, PlatformProfile::Eti660, PlatformProfile::XoChip];
+        assert!(profiles.iter().all(|profile| !Platform::from(*profile).stack_in_memory));
+    }
+
+    #[test]
+    fn profiles_parse_by_name() {
+        for (name, profile) in [("chip8", PlatformProfile::Chip8), ("vip", PlatformProfile::Vip), ("schip", PlatformProfile::Schip), ("eti660", PlatformProfile::Eti660), ("xochip", PlatformProfile::XoChip)] {
+            assert_eq!(name.parse::<PlatformProfile>(), Ok(profile));
+        }
+        assert_eq!("CHIP8".parse::<PlatformProfile>(), Err(String::from("unknown platform: CHIP8")));
+    }
+
+    #[test]
+    fn missing_fields_default_to_chip8() {
+        let platform: Platform = toml::from_str("load_address = 0x600").unwrap();
+        assert_eq!(platform, Platform { load_address: 0x600, ..Platform::default() });
+    }
+}
@@ -0,0 +1,48 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// Behaviours that differ between CHIP-8 interpreters. The default matches
+// what this interpreter has always done.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 clear VF, as on the COSMAC VIP.
+    pub logic_resets_vf: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0 (CHIP-48, SUPER-CHIP).
+    pub jump_uses_vx: bool,
+    // Sprites are cut off at the screen edge instead of wrapping around.
+    pub clip_sprites: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuirkProfile {
+    Vip,
+    Chip48,
+    Schip,
+    XoChip
+}
+
+impl From<QuirkProfile> for Quirks {
+    fn from(profile: QuirkProfile) -> Self {
+        match profile {
+            QuirkProfile::Vip => Quirks { logic_resets_vf: true, jump_uses_vx: false, clip_sprites: true },
+            QuirkProfile::Chip48 | QuirkProfile::Schip => Quirks { logic_resets_vf: false, jump_uses_vx: true, clip_sprites: true },
+            QuirkProfile::XoChip => Quirks { logic_resets_vf: false, jump_uses_vx: false, clip_sprites: false }
+        }
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vip" => Ok(QuirkProfile::Vip),
+            "chip48" => Ok(QuirkProfile::Chip48),
+            "schip" => Ok(QuirkProfile::Schip),
+            "xochip" => Ok(QuirkProfile::XoChip),
+            _ => Err(format!("unknown quirk profile: {}", s))
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use super::emulator::{Emulator, MemoryAddress, Result};
+use super::quirks::Quirks;
+
+// A deliberately plain CHIP-8 interpreter written straight from the spec,
+// for differential testing. It shares nothing with `Emulator` except the
+// starting machine it copies and the RNG algorithm, so RND agrees. There's
+// no keypad: LDK waits forever and SKP never sees a key.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub memory: Vec<u8>,
+    pub pc: MemoryAddress,
+    pub i: MemoryAddress,
+    pub v: [u8; 16],
+    pub stack: Vec<MemoryAddress>,
+    pub delay: u8,
+    pub sound: u8,
+    pub waiting: bool,
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    // Rows of pixels, `width` by `height`.
+    pub display: Vec<Vec<bool>>,
+    stack_depth: usize,
+    big_font: bool,
+    xo_chip_audio: bool,
+    font_base: MemoryAddress,
+    quirks: Quirks,
+    rng: StdRng
+}
+
+impl Reference {
+    // A copy of a freshly loaded emulator.
+    pub fn new(emulator: &Emulator) -> Self {
+        let display = emulator.display();
+        Self {
+            memory: emulator.memory().as_bytes().to_vec(),
+            pc: emulator.pc(),
+            i: emulator.i(),
+            v: [0; 16],
+            stack: vec![],
+            delay: 0,
+            sound: 0,
+            waiting: false,
+            audio_pattern: None,
+            pitch: emulator.pitch(),
+            display: vec![vec![false; display.width as usize]; display.height as usize],
+            stack_depth: emulator.platform().stack_depth,
+            big_font: emulator.platform().big_font,
+            xo_chip_audio: emulator.platform().xo_chip_audio,
+            font_base: emulator.font_base(),
+            quirks: emulator.quirks(),
+            rng: StdRng::seed_from_u64(emulator.seed())
+        }
+    }
+
+    fn range(&self, start: MemoryAddress, len: usize) -> Result<std::ops::Range<usize>> {
+        let end = start as usize + len;
+        if end > self.memory.len() {
+            return Err(format!("{} bytes at {:#06x} run past the end of memory", len, start).into());
+        }
+        Ok(start as usize..end)
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    pub fn step(&mut self) -> Result<()> {
+        if self.waiting {
+            return Ok(());
+        }
+        let range = self.range(self.pc, 2)?;
+        let opcode = (self.memory[range.start] as u16) << 8 | self.memory[range.start + 1] as u16;
+        self.pc = self.pc.wrapping_add(2);
+
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = (opcode & 0xF) as usize;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        match (opcode >> 12, x, y, n) {
+            (0x0, 0, 0xE, 0x0) => self.display.iter_mut().for_each(|row| row.iter_mut().for_each(|pixel| *pixel = false)),
+            (0x0, 0, 0xE, 0xE) => self.pc = self.stack.pop().ok_or("RET with an empty stack")?,
+            (0x1, ..) => self.pc = nnn,
+            (0x2, ..) => {
+                if self.stack.len() == self.stack_depth {
+                    return Err("CALL with a full stack".into());
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            },
+            (0x3, ..) => self.skip_if(self.v[x] == nn),
+            (0x4, ..) => self.skip_if(self.v[x] != nn),
+            (0x5, _, _, 0x0) => self.skip_if(self.v[x] == self.v[y]),
+            (0x6, ..) => self.v[x] = nn,
+            (0x7, ..) => self.v[x] = self.v[x].wrapping_add(nn),
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            (0x8, _, _, 0x1 ..= 0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y]
+                };
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+            },
+            // The flag is written last, so it wins when X is F.
+            (0x8, _, _, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = (sum > 0xFF) as u8;
+            },
+            (0x8, _, _, 0x5) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vx.wrapping_sub(vy);
+                self.v[0xF] = (vx >= vy) as u8;
+            },
+            (0x8, _, _, 0x6) => {
+                let vx = self.v[x];
+                self.v[x] = vx >> 1;
+                self.v[0xF] = vx & 1;
+            },
+            (0x8, _, _, 0x7) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vy.wrapping_sub(vx);
+                self.v[0xF] = (vy >= vx) as u8;
+            },
+            (0x8, _, _, 0xE) => {
+                let vx = self.v[x];
+                self.v[x] = vx << 1;
+                self.v[0xF] = vx >> 7;
+            },
+            (0x9, _, _, 0x0) => self.skip_if(self.v[x] != self.v[y]),
+            (0xA, ..) => self.i = nnn,
+            (0xB, ..) => {
+                let offset = if self.quirks.jump_uses_vx { self.v[x] } else { self.v[0] };
+                self.pc = nnn + offset as MemoryAddress;
+            },
+            (0xC, ..) => self.v[x] = self.rng.gen::<u8>() & nn,
+            (0xD, ..) => self.draw(x, y, n)?,
+            (0xE, _, 0x9, 0xE) => {},
+            (0xE, _, 0xA, 0x1) => self.skip_if(true),
+            (0xF, 0, 0x0, 0x2) if self.xo_chip_audio => {
+                let range = self.range(self.i, 16)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[range]);
+                self.audio_pattern = Some(pattern);
+            },
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.delay,
+            (0xF, _, 0x0, 0xA) => self.waiting = true,
+            (0xF, _, 0x1, 0x5) => self.delay = self.v[x],
+            (0xF, _, 0x1, 0x8) => self.sound = self.v[x],
+            (0xF, _, 0x1, 0xE) => self.i = self.i.wrapping_add(self.v[x] as MemoryAddress),
+            (0xF, _, 0x2, 0x9) => self.i = self.font_base + (self.v[x] & 0xF) as MemoryAddress * 5,
+            (0xF, _, 0x3, 0x0) if self.big_font => self.i = self.font_base + 16 * 5 + (self.v[x] & 0xF) as MemoryAddress * 10,
+            (0xF, _, 0x3, 0x3) => {
+                let digits = [self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10];
+                for (offset, digit) in digits.iter().enumerate() {
+                    let range = self.range(self.i.wrapping_add(offset as MemoryAddress), 1)?;
+                    self.memory[range.start] = *digit;
+                }
+            },
+            (0xF, _, 0x3, 0xA) if self.xo_chip_audio => self.pitch = self.v[x],
+            (0xF, _, 0x5, 0x5) => {
+                let range = self.range(self.i, x + 1)?;
+                self.memory[range].copy_from_slice(&self.v[..=x]);
+            },
+            (0xF, _, 0x6, 0x5) => {
+                let range = self.range(self.i, x + 1)?;
+                self.v[..=x].copy_from_slice(&self.memory[range]);
+            },
+            _ => return Err(format!("{:04X} is not an instruction", opcode).into())
+        }
+        Ok(())
+    }
+
+    // The origin wraps; the rest of the sprite wraps too, or is cut off at
+    // the edges with `clip_sprites`.
+    fn draw(&mut self, x: usize, y: usize, n: usize) -> Result<()> {
+        let range = self.range(self.i, n)?;
+        let sprite = self.memory[range].to_vec();
+        let height = self.display.len();
+        let width = self.display.first().map_or(0, |row| row.len());
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let (x, y) = (self.v[x] as usize % width, self.v[y] as usize % height);
+        let mut collision = false;
+        for (row, byte) in sprite.iter().enumerate() {
+            if self.quirks.clip_sprites && y + row >= height {
+                break;
+            }
+            for column in 0..8 {
+                if self.quirks.clip_sprites && x + column >= width {
+                    break;
+                }
+                if byte & (0x80 >> column) != 0 {
+                    let pixel = &mut self.display[(y + row) % height][(x + column) % width];
+                    collision |= *pixel;
+                    *pixel = !*pixel;
+                }
+            }
+        }
+        self.v[0xF] = collision as u8;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(program: &[u8]) -> Reference {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.load_program(program).unwrap();
+        Reference::new(&emulator)
+    }
+
+    fn run(program: &[u8]) -> Reference {
+        let mut reference = load(program);
+        for _ in 0..program.len() / 2 {
+            reference.step().unwrap();
+        }
+        reference
+    }
+
+    #[test]
+    fn arithmetic_writes_the_flag_last() {
+        // LD VF, 0xF0; ADD VF, VF: the carry overwrites the sum.
+        assert_eq!(run(&[0x6F, 0xF0, 0x8F, 0xF4]).v[0xF], 1);
+        // LD V0, 5; LD V1, 5; SUB V0, V1: no borrow when equal.
+        let equal = run(&[0x60, 0x05, 0x61, 0x05, 0x80, 0x15]);
+        assert_eq!((equal.v[0], equal.v[0xF]), (0, 1));
+        // LD V0, 0x81; SHL V0
+        let shifted = run(&[0x60, 0x81, 0x80, 0x0E]);
+        assert_eq!((shifted.v[0], shifted.v[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn skips_calls_and_returns() {
+        // CALL 0x206; (skipped); (skipped); SE V0, 0; (skipped); RET
+        let mut reference = load(&[0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x00, 0xEE]);
+        reference.step().unwrap();
+        assert_eq!((reference.pc, reference.stack.clone()), (0x206, vec![0x202]));
+        reference.step().unwrap();
+        assert_eq!(reference.pc, 0x20A);
+        reference.step().unwrap();
+        assert_eq!((reference.pc, reference.stack.len()), (0x202, 0));
+        assert!(reference.step().is_err());
+    }
+
+    #[test]
+    fn stores_and_loads_through_x() {
+        // LD V0, 123; LD I, 0x300; LD B, V0; LD V1, 9; LD [I], V1; LD V0..V2 back
+        let reference = run(&[0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0x61, 0x09, 0xF1, 0x55, 0xF2, 0x65]);
+        assert_eq!(reference.memory[0x300..0x303], [123, 9, 3]);
+        assert_eq!(reference.v[..3], [123, 9, 3]);
+        assert_eq!(reference.i, 0x300);
+    }
+
+    #[test]
+    fn sprites_wrap_and_report_collisions() {
+        // LD V0, 63; LD I, font 0; DRW V0, V1, 1 twice
+        let mut reference = load(&[0x60, 0x3F, 0xF1, 0x29, 0xD0, 0x11, 0xD0, 0x11]);
+        for _ in 0..3 {
+            reference.step().unwrap();
+        }
+        // Font 0's top row is 0xF0: x 63, then 0..2 after wrapping.
+        let lit: Vec<_> = reference.display[0].iter().enumerate().filter(|(_, lit)| **lit).map(|(x, _)| x).collect();
+        assert_eq!(lit, vec![0, 1, 2, 63]);
+        assert_eq!(reference.v[0xF], 0);
+        reference.step().unwrap();
+        assert!(reference.display[0].iter().all(|lit| !lit));
+        assert_eq!(reference.v[0xF], 1);
+    }
+
+    #[test]
+    fn key_waits_hold_and_bad_opcodes_fault() {
+        let mut reference = load(&[0xF0, 0x0A]);
+        reference.step().unwrap();
+        reference.step().unwrap();
+        assert!(reference.waiting);
+        assert_eq!(reference.pc, 0x202);
+        assert!(load(&[0x50, 0x01]).step().is_err());
+    }
+}
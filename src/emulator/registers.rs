@@ -2,7 +2,7 @@ use  super::emulator::{Result, RegisterAddress};
 use std::fmt::{Display, Formatter};
 use std::error::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Registers {
     buffer: [u8; 16]
 }
@@ -12,11 +12,12 @@ impl Registers {
         &self.buffer[..]
     }
 
+    // Sets V0 onwards, as many registers as there are bytes.
     pub fn set_bytes(&mut self, data: &[u8]) -> Result<()> {
         if data.len() > 16 {
             return Err(Box::new(RegisterError::InvalidRegister(data.len() as u8 - 1)));
         }
-        self.buffer[..].copy_from_slice(data);
+        self.buffer[..data.len()].copy_from_slice(data);
         Ok(())
     }
 
@@ -44,14 +45,6 @@ impl Registers {
     }
 }
 
-impl Default for Registers {
-    fn default() -> Self {
-        Self {
-            buffer: [0; 16]
-        }
-    }
-}
-
 #[derive(Debug)]
 pub enum RegisterError {
     InvalidRegister(RegisterAddress)
@@ -67,4 +60,18 @@ impl Display for RegisterError {
     }
 }
 
-impl Error for RegisterError {}
\ No newline at end of file
+impl Error for RegisterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_bytes_writes_from_v0_and_leaves_the_rest() {
+        let mut registers = Registers::default();
+        registers.set_bytes(&[0xFF; 16]).unwrap();
+        registers.set_bytes(&[1, 2, 3]).unwrap();
+        assert_eq!(&registers.as_bytes()[..4], &[1, 2, 3, 0xFF]);
+        assert!(registers.set_bytes(&[0; 17]).is_err());
+    }
+}
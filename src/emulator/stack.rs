@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use super::emulator::{MemoryAddress, Result, StackPointer};
+use super::instructions::Instruction;
+use super::memory::Memory;
+use super::platform::Platform;
+
+// One active subroutine call, outermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    // Address of the CALL instruction.
+    pub caller: MemoryAddress,
+    pub return_address: MemoryAddress,
+    // The called subroutine, if the CALL is still in memory.
+    pub target: Option<MemoryAddress>,
+    // Label of the called subroutine, or of the closest label before it.
+    pub symbol: Option<String>
+}
+
+// Return addresses for CALL and RET, either held by the interpreter or, as
+// on the VIP, stored big-endian in memory from `Memory::stack_base` up.
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    entries: Vec<MemoryAddress>,
+    sp: StackPointer,
+    depth: usize,
+    in_memory: bool
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        CallStack::new(&Platform::default())
+    }
+}
+
+impl CallStack {
+    pub fn new(platform: &Platform) -> Self {
+        Self {
+            entries: if platform.stack_in_memory { vec![] } else { vec![0; platform.stack_depth] },
+            sp: 0,
+            depth: platform.stack_depth,
+            in_memory: platform.stack_in_memory
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn len(&self) -> StackPointer {
+        self.sp
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sp == 0
+    }
+
+    // The interpreter-held slots, including stale entries above the stack
+    // pointer. Empty when the stack lives in memory.
+    pub fn slots(&self) -> &[MemoryAddress] {
+        &self.entries
+    }
+
+    pub fn clear(&mut self) {
+        self.sp = 0;
+        self.entries.iter_mut().for_each(|entry| *entry = 0);
+    }
+
+    fn slot(&self, memory: &Memory, index: StackPointer) -> MemoryAddress {
+        memory.stack_base() + 2 * index as MemoryAddress
+    }
+
+    pub fn push(&mut self, memory: &mut Memory, addr: MemoryAddress) -> Result<()> {
+        if self.sp >= self.depth {
+            return Err(Box::new(StackError::Overflow(self.depth)));
+        }
+        if self.in_memory {
+            memory.set_range_unprotected(self.slot(memory, self.sp), &addr.to_be_bytes())?;
+        } else {
+            self.entries[self.sp] = addr;
+        }
+        self.sp += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self, memory: &Memory) -> Result<MemoryAddress> {
+        if self.sp == 0 {
+            return Err(Box::new(StackError::Underflow));
+        }
+        self.sp -= 1;
+        if self.in_memory {
+            let bytes = memory.read(self.slot(memory, self.sp), 2)?;
+            return Ok(MemoryAddress::from_be_bytes([bytes[0], bytes[1]]));
+        }
+        Ok(self.entries[self.sp])
+    }
+
+    // Return addresses, innermost call last.
+    pub fn entries(&self, memory: &Memory) -> Vec<MemoryAddress> {
+        if !self.in_memory {
+            return self.entries[..self.sp].to_vec();
+        }
+        (0..self.sp)
+            .filter_map(|index| memory.get_range(self.slot(memory, index), 2).ok())
+            .map(|bytes| MemoryAddress::from_be_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    // Rewrites each return address, e.g. after the code around it moved.
+    pub fn remap(&mut self, memory: &mut Memory, remap: &dyn Fn(MemoryAddress) -> MemoryAddress) -> Result<()> {
+        if !self.in_memory {
+            self.entries[..self.sp].iter_mut().for_each(|entry| *entry = remap(*entry));
+            return Ok(());
+        }
+        for (index, entry) in self.entries(memory).into_iter().enumerate() {
+            let slot = self.slot(memory, index);
+            memory.poke(slot, &remap(entry).to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn frames(&self, memory: &Memory, symbols: &BTreeMap<MemoryAddress, String>) -> Vec<StackFrame> {
+        self.entries(memory).into_iter().map(|return_address| {
+            let caller = return_address.wrapping_sub(2);
+            let target = memory.get_range(caller, 2).ok()
+                .and_then(|bytes| Instruction::try_from(bytes).ok())
+                .and_then(|instruction| match instruction {
+                    Instruction::CALL(addr) => Some(addr),
+                    _ => None
+                });
+            let symbol = symbols.range(..=target.unwrap_or(caller)).next_back().map(|(_, name)| name.clone());
+            StackFrame { caller, return_address, target, symbol }
+        }).collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum StackError {
+    Overflow(usize),
+    Underflow
+}
+
+impl Display for StackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackError::Overflow(depth) => write!(f, "Stack overflow: more than {} nested calls", depth),
+            StackError::Underflow => write!(f, "Stack underflow: RET outside of a subroutine")
+        }
+    }
+}
+
+impl std::error::Error for StackError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::emulator::Emulator;
+    use crate::emulator::memory::RegionKind;
+    use crate::emulator::platform::PlatformProfile;
+    use crate::emulator::quirks::Quirks;
+
+    // CALL 0x206 from 0x202, which returns to run LD V1, 1.
+    const PROGRAM: [u8; 8] = [0x60, 0x01, 0x22, 0x06, 0x61, 0x01, 0x00, 0xEE];
+
+    fn vip(protect_stack: bool) -> Emulator {
+        let mut emulator = Emulator::new(0, Quirks::default());
+        emulator.set_platform(PlatformProfile::Vip.into()).unwrap();
+        emulator.memory_mut().set_protected(RegionKind::Stack, protect_stack);
+        emulator.load_program(&PROGRAM).unwrap();
+        emulator
+    }
+
+    fn call_and_return(mut emulator: Emulator) {
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.pc(), 0x206);
+        assert_eq!(emulator.stack(), vec![0x204]);
+        let base = emulator.memory().stack_base();
+        assert_eq!(emulator.memory().get_range(base, 2).unwrap(), &[0x02, 0x04]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.pc(), 0x206);
+        assert_eq!(emulator.registers()[1], 1);
+        assert_eq!(emulator.stack_depth(), 0);
+    }
+
+    #[test]
+    fn vip_calls_return_through_memory() {
+        call_and_return(vip(false));
+    }
+
+    #[test]
+    fn protecting_the_vip_stack_only_stops_the_program_writing_it() {
+        call_and_return(vip(true));
+
+        // FX55 from the program into the stack is still refused.
+        let mut emulator = vip(true);
+        let base = emulator.memory().stack_base().to_be_bytes();
+        emulator.load_program(&[0xA0 | base[0], base[1], 0xF0, 0x55]).unwrap();
+        emulator.step().unwrap();
+        assert!(emulator.step().is_err());
+    }
+
+    #[test]
+    fn stack_depth_follows_the_platform() {
+        let mut emulator = vip(false);
+        // CALL 0x200 forever.
+        emulator.load_program(&[0x22, 0x00]).unwrap();
+        emulator.run_cycles(12).unwrap();
+        let error = emulator.step().unwrap_err();
+        assert!(matches!(error.downcast_ref::<StackError>(), Some(StackError::Overflow(12))));
+        // RET with nothing to return to.
+        emulator.load_program(&[0x00, 0xEE]).unwrap();
+        assert!(emulator.step().is_err());
+    }
+
+    #[test]
+    fn frames_name_the_called_subroutine() {
+        let mut emulator = vip(false);
+        emulator.run_cycles(2).unwrap();
+        let symbols: BTreeMap<MemoryAddress, String> = vec![(0x200, String::from("main")), (0x206, String::from("sub"))].into_iter().collect();
+        assert_eq!(emulator.call_stack(&symbols), vec![StackFrame {
+            caller: 0x202,
+            return_address: 0x204,
+            target: Some(0x206),
+            symbol: Some(String::from("sub"))
+        }]);
+    }
+}
@@ -1,43 +1,36 @@
-use std::{thread::{self, JoinHandle}, sync::{Arc, Mutex}};
-use std::time::Duration;
-
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TimedRegister {
-    value: Arc<Mutex<u8>>,
-    timer: Arc<Mutex<Option<JoinHandle<()>>>>
-}
-
-impl Default for TimedRegister {
-    fn default() -> Self {
-        Self {
-            value: Arc::new(Mutex::new(0)),
-            timer: Arc::new(Mutex::new(None))
-        }
-    }
+    value: u8
 }
 
 impl TimedRegister {
 
     pub fn get(&self) -> u8 {
-        *self.value.lock().unwrap()
+        self.value
     }
 
     pub fn set(&mut self, value: u8) {
-        *self.value.lock().unwrap() = value;
-        let dur = Duration::from_secs(1) * (1 / 60);
-        let v = self.value.clone();
-        let t = self.timer.clone();
-        let handle = thread::spawn(move || {
-            loop {
-                thread::sleep(dur);
-                if *v.lock().unwrap() <= 0 { // Setting this from <= to == leads to odd behavior sometimes.
-                    if let Some(_) = &*t.lock().unwrap() {
-                        *t.lock().unwrap() = None;
-                    }
-                }
-                *v.lock().unwrap() -= 1;
-            }
-        });
-        *self.timer.lock().unwrap() = Some(handle);
+        self.value = value;
+    }
+
+    // Called by the host once per 60Hz frame.
+    pub fn tick(&mut self) {
+        self.value = self.value.saturating_sub(1);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_counts_down_to_zero() {
+        let mut register = TimedRegister::default();
+        register.set(2);
+        register.tick();
+        assert_eq!(register.get(), 1);
+        register.tick();
+        register.tick();
+        assert_eq!(register.get(), 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::emulator::emulator::Result;
+use super::phosphor::GrayFrame;
+use super::palette::Palette;
+use super::render::Framebuffer;
+
+pub const FRAME_RATE: u16 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Png,
+    Gif,
+    Apng
+}
+
+impl CaptureFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()).as_deref() {
+            Some("png") => Ok(CaptureFormat::Png),
+            Some("gif") => Ok(CaptureFormat::Gif),
+            Some("apng") => Ok(CaptureFormat::Apng),
+            _ => Err(Box::new(CaptureError::UnknownFormat(path.display().to_string())))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    UnknownFormat(String),
+    Empty,
+    // Scaled width and height beyond what the format can store.
+    TooLarge { width: usize, height: usize, limit: usize }
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureError::UnknownFormat(path) => write!(f, "Can't tell the capture format of {} (expected .png, .gif or .apng)", path),
+            CaptureError::Empty => write!(f, "No frames were captured"),
+            CaptureError::TooLarge { width, height, limit } =>
+                write!(f, "A {}x{} capture is too large; GIF frames can be at most {} pixels on a side (lower the scale)", width, height, limit)
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+fn png_encoder<'a>(file: BufWriter<File>, framebuffer: &Framebuffer) -> png::Encoder<'a, BufWriter<File>> {
+    let mut encoder = png::Encoder::new(file, framebuffer.width as u32, framebuffer.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+}
+
+pub fn save_png(frame: &GrayFrame, scale: usize, palette: &Palette, path: &Path) -> Result<()> {
+    let framebuffer = Framebuffer::from_gray(frame, scale, palette);
+    let mut writer = png_encoder(BufWriter::new(File::create(path)?), &framebuffer).write_header()?;
+    writer.write_image_data(&framebuffer.to_rgb8())?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Frames recorded at 60Hz. Runs of identical frames are stored once with the
+// number of frames they were shown for.
+#[derive(Debug, Default, Clone)]
+pub struct Animation {
+    frames: Vec<(GrayFrame, u32)>
+}
+
+impl Animation {
+    pub fn push(&mut self, frame: GrayFrame) {
+        match self.frames.last_mut() {
+            Some((last, count)) if *last == frame => *count += 1,
+            _ => self.frames.push((frame, 1))
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Length in 60Hz frames.
+    pub fn duration(&self) -> u32 {
+        self.frames.iter().map(|(_, count)| count).sum()
+    }
+
+    pub fn save(&self, path: &Path, scale: usize, palette: &Palette) -> Result<()> {
+        let (first, _) = self.frames.first().ok_or(CaptureError::Empty)?;
+        match CaptureFormat::from_path(path)? {
+            CaptureFormat::Png => save_png(first, scale, palette, path),
+            CaptureFormat::Gif => self.save_gif(path, scale, palette),
+            CaptureFormat::Apng => self.save_apng(path, scale, palette)
+        }
+    }
+
+    // GIF delays are whole centiseconds and most viewers slow down anything
+    // shorter than 2cs, so frame boundaries are rounded on the running total
+    // and frames that would be shown for less than 2cs are skipped. The
+    // overall length is within half a centisecond of the recording, except
+    // that a last frame shorter than 2cs is still shown for 2cs.
+    fn save_gif(&self, path: &Path, scale: usize, palette: &Palette) -> Result<()> {
+        let first = &self.frames[0].0;
+        let (width, height) = (first.width * scale.max(1), first.height * scale.max(1));
+        let too_large = || CaptureError::TooLarge { width, height, limit: u16::MAX as usize };
+        let (gif_width, gif_height) = (u16::try_from(width).map_err(|_| too_large())?, u16::try_from(height).map_err(|_| too_large())?);
+        let colors: Vec<u8> = (0..=255).flat_map(|level| {
+            let color = palette.shade(level as f32 / 255.0);
+            [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+        }).collect();
+        let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), gif_width, gif_height, &colors)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        let to_centis = |frames: u32| (frames as u64 * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+        let (mut elapsed, mut shown) = (0, 0);
+        for (index, (frame, count)) in self.frames.iter().enumerate() {
+            elapsed += count;
+            let delay = to_centis(elapsed) - shown;
+            let last = index + 1 == self.frames.len();
+            if delay < 2 && !last {
+                continue;
+            }
+            // A black-to-blue palette leaves each pixel's gray level, which is
+            // also its index in `colors`, in the low byte.
+            let indices: Vec<u8> = Framebuffer::from_gray(frame, scale, &Palette::new(0, 0xFF))
+                .pixels.iter().map(|px| *px as u8).collect();
+            let mut gif_frame = gif::Frame::from_indexed_pixels(gif_width, gif_height, indices, None);
+            gif_frame.delay = delay.clamp(2, u16::MAX as u64) as u16;
+            encoder.write_frame(&gif_frame)?;
+            shown += delay;
+        }
+        Ok(())
+    }
+
+    fn save_apng(&self, path: &Path, scale: usize, palette: &Palette) -> Result<()> {
+        let first = Framebuffer::from_gray(&self.frames[0].0, scale, palette);
+        let mut encoder = png_encoder(BufWriter::new(File::create(path)?), &first);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        for (frame, count) in &self.frames {
+            writer.set_frame_delay((*count).min(u16::MAX as u32) as u16, FRAME_RATE)?;
+            writer.write_image_data(&Framebuffer::from_gray(frame, scale, palette).to_rgb8())?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn frame(level: f32) -> GrayFrame {
+        GrayFrame { width: 2, height: 1, pixels: vec![level, 0.0] }
+    }
+
+    // Frame count and total delay in centiseconds.
+    fn read_gif(path: &Path) -> (usize, u32) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+        let (mut frames, mut delay) = (0, 0);
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames += 1;
+            delay += frame.delay as u32;
+        }
+        (frames, delay)
+    }
+
+    fn save_and_read(animation: &Animation, name: &str) -> (usize, u32) {
+        let path = std::env::temp_dir().join(format!("lucid8-{}-{}.gif", name, std::process::id()));
+        animation.save(&path, 1, &Palette::default()).unwrap();
+        let read = read_gif(&path);
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn gif_delays_add_up_to_the_recorded_length() {
+        let mut animation = Animation::default();
+        let counts = [(0.0, 60), (0.25, 1), (0.5, 1), (1.0, 30)];
+        for (level, count) in counts {
+            for _ in 0..count {
+                animation.push(frame(level));
+            }
+        }
+        assert_eq!(animation.duration(), 92);
+        // The third frame would be shown for 1cs and is dropped; 92 frames
+        // at 60Hz is 153.3cs.
+        assert_eq!(save_and_read(&animation, "gif-length"), (3, 153));
+    }
+
+    #[test]
+    fn gifs_wider_than_the_format_allows_are_refused() {
+        let mut animation = Animation::default();
+        animation.push(GrayFrame::new(128, 64));
+        let path = std::env::temp_dir().join(format!("lucid8-gif-too-large-{}.gif", std::process::id()));
+        // 128 * 512 would wrap to 0 as a u16.
+        let error = animation.save(&path, 512, &Palette::default()).unwrap_err();
+        assert!(!path.exists());
+        assert!(matches!(error.downcast_ref::<CaptureError>(), Some(CaptureError::TooLarge { width: 65536, height: 32768, limit: 65535 })));
+        assert!(error.to_string().contains("at most 65535 pixels"), "{}", error);
+    }
+
+    #[test]
+    fn a_short_last_frame_is_shown_for_two_centiseconds() {
+        let mut animation = Animation::default();
+        for _ in 0..61 {
+            animation.push(frame(0.0));
+        }
+        animation.push(frame(1.0));
+        // 102cs, then 1cs stretched to 2.
+        assert_eq!(save_and_read(&animation, "gif-short-last"), (2, 104));
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(CaptureFormat::from_path(Path::new("a.GIF")).unwrap(), CaptureFormat::Gif);
+        assert_eq!(CaptureFormat::from_path(Path::new("a.apng")).unwrap(), CaptureFormat::Apng);
+        assert!(CaptureFormat::from_path(Path::new("a.bmp")).is_err());
+        assert!(Animation::default().save(Path::new("unused.gif"), 1, &Palette::default()).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod capture;
+pub mod palette;
+pub mod phosphor;
+pub mod render;
+pub mod session;
+pub mod window;
@@ -0,0 +1,206 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::emulator::Result;
+
+// Colours as 0RGB, one per combination of lit XO-CHIP bitplanes. Plain
+// CHIP-8 only ever uses the background and foreground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    #[serde(with = "rgb")]
+    pub background: u32,
+    // Plane 1 only.
+    #[serde(with = "rgb")]
+    pub foreground: u32,
+    // Plane 2 only.
+    #[serde(with = "rgb")]
+    pub plane2: u32,
+    // Both planes.
+    #[serde(with = "rgb")]
+    pub blend: u32
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Theme::Monochrome.palette()
+    }
+}
+
+impl Palette {
+    // A two-colour palette, with the XO-CHIP plane colours lit like plane 1.
+    pub fn new(background: u32, foreground: u32) -> Self {
+        Self { background, foreground, plane2: foreground, blend: foreground }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    // Colour for a bitmask of lit planes, plane 1 in bit 0.
+    pub fn plane(&self, planes: u8) -> u32 {
+        match planes & 0b11 {
+            0 => self.background,
+            1 => self.foreground,
+            2 => self.plane2,
+            _ => self.blend
+        }
+    }
+
+    // Mixes background and foreground channel by channel.
+    pub fn shade(&self, intensity: f32) -> u32 {
+        let intensity = intensity.clamp(0.0, 1.0);
+        [16, 8, 0].iter().fold(0, |color, shift| {
+            let bg = ((self.background >> shift) & 0xFF) as f32;
+            let fg = ((self.foreground >> shift) & 0xFF) as f32;
+            color | (((bg + (fg - bg) * intensity).round() as u32) << shift)
+        })
+    }
+}
+
+// `--palette` accepts a theme name or a palette file.
+impl FromStr for Palette {
+    type Err = Box<dyn std::error::Error>;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.parse::<Theme>() {
+            Ok(theme) => Ok(theme.palette()),
+            Err(_) if Path::new(s).exists() => Palette::load(Path::new(s)),
+            Err(error) => Err(Box::new(error))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Monochrome,
+    OctoClassic,
+    LcdGreen,
+    Amber
+}
+
+impl Theme {
+    pub const ALL: [Theme; 4] = [Theme::Monochrome, Theme::OctoClassic, Theme::LcdGreen, Theme::Amber];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Monochrome => "mono",
+            Theme::OctoClassic => "octo",
+            Theme::LcdGreen => "lcd",
+            Theme::Amber => "amber"
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        let [background, foreground, plane2, blend] = match self {
+            Theme::Monochrome => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            Theme::OctoClassic => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            Theme::LcdGreen => [0xF9FFB3, 0x3D8026, 0xABCC47, 0x00131A],
+            Theme::Amber => [0x1A0F00, 0xFFB000, 0x8C5A00, 0xFFE0A0]
+        };
+        Palette { background, foreground, plane2, blend }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownTheme(String);
+
+impl Display for UnknownTheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = Theme::ALL.iter().map(Theme::name).collect();
+        write!(f, "Unknown theme {} (expected one of {} or a palette file)", self.0, names.join(", "))
+    }
+}
+
+impl std::error::Error for UnknownTheme {}
+
+impl FromStr for Theme {
+    type Err = UnknownTheme;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Theme::ALL.iter()
+            .find(|theme| theme.name() == s)
+            .copied()
+            .ok_or_else(|| UnknownTheme(s.to_string()))
+    }
+}
+
+// Colours are written as "#rrggbb".
+mod rgb {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("#{:06x}", value & 0xFF_FFFF))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let hex = text.strip_prefix('#').unwrap_or(&text);
+        if hex.len() != 6 {
+            return Err(serde::de::Error::custom(format!("expected a colour like #rrggbb, got {}", text)));
+        }
+        u32::from_str_radix(hex, 16).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn themes_parse_by_name() {
+        for theme in Theme::ALL {
+            assert_eq!(theme.name().parse::<Theme>().unwrap(), theme);
+        }
+        assert_eq!("lcd".parse::<Palette>().unwrap(), Theme::LcdGreen.palette());
+        assert!("neon".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn palette_files_load_and_fill_in_defaults() {
+        let path = std::env::temp_dir().join(format!("lucid8-palette-{}.toml", std::process::id()));
+        fs::write(&path, "foreground = \"#ffcc00\"\nblend = \"#662200\"\n").unwrap();
+        let loaded: Result<Palette> = path.to_str().unwrap().parse();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), Palette { background: 0x000000, foreground: 0xFFCC00, plane2: 0xAAAAAA, blend: 0x662200 });
+    }
+
+    #[test]
+    fn palettes_round_trip_through_toml() {
+        let palette = Theme::Amber.palette();
+        let text = toml::to_string(&palette).unwrap();
+        assert_eq!(text, "background = \"#1a0f00\"\nforeground = \"#ffb000\"\nplane2 = \"#8c5a00\"\nblend = \"#ffe0a0\"\n");
+        assert_eq!(toml::from_str::<Palette>(&text).unwrap(), palette);
+    }
+
+    #[test]
+    fn colours_must_be_six_hex_digits() {
+        assert!(toml::from_str::<Palette>("background = \"#fff\"").is_err());
+        assert!(toml::from_str::<Palette>("background = \"#gggggg\"").is_err());
+        assert_eq!(toml::from_str::<Palette>("background = \"102030\"").unwrap().background, 0x102030);
+    }
+
+    #[test]
+    fn planes_pick_their_own_colours() {
+        let palette = Theme::OctoClassic.palette();
+        assert_eq!([0, 1, 2, 3].map(|planes| palette.plane(planes)), [0x996600, 0xFFCC00, 0xFF6600, 0x662200]);
+        // Only the low two bits name planes.
+        assert_eq!(palette.plane(0b110), 0xFF6600);
+        assert_eq!(Palette::new(0x000000, 0x00FF00).plane(3), 0x00FF00);
+    }
+
+    #[test]
+    fn shade_mixes_each_channel() {
+        let palette = Palette::new(0x000000, 0xFF8040);
+        assert_eq!(palette.shade(0.0), 0x000000);
+        assert_eq!(palette.shade(0.5), 0x804020);
+        assert_eq!(palette.shade(2.0), 0xFF8040);
+    }
+}
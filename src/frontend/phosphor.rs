@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::emulator::display::{Display, Row, MAX_WIDTH};
+
+// Intensities in [0.0, 1.0], row-major.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrayFrame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<f32>
+}
+
+impl GrayFrame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![0.0; width * height] }
+    }
+
+    pub fn from_display(display: &Display) -> Self {
+        let mut frame = GrayFrame::new(display.width as usize, display.height as usize);
+        for (x, y, lit) in display.pixels() {
+            frame.pixels[y as usize * frame.width + x as usize] = if lit { 1.0 } else { 0.0 };
+        }
+        frame
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.pixels.get(y * self.width + x).copied().unwrap_or(0.0)
+    }
+
+    pub fn to_gray8(&self) -> Vec<u8> {
+        self.pixels.iter().map(|intensity| (intensity.clamp(0.0, 1.0) * 255.0).round() as u8).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhosphorMode {
+    // Unlit pixels keep this fraction of their brightness each frame.
+    Decay(f32),
+    // Each pixel shows how often it was lit over the last N frames.
+    Blend(usize)
+}
+
+impl FromStr for PhosphorMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("expected decay:FACTOR or blend:FRAMES, got {}", s);
+        match s.split_once(':') {
+            Some(("decay", factor)) => factor.parse().ok()
+                .filter(|factor: &f32| (0.0..1.0).contains(factor))
+                .map(PhosphorMode::Decay)
+                .ok_or_else(bad),
+            Some(("blend", frames)) => frames.parse().ok()
+                .filter(|frames: &usize| *frames > 0)
+                .map(PhosphorMode::Blend)
+                .ok_or_else(bad),
+            _ => Err(bad())
+        }
+    }
+}
+
+// Simulates CRT persistence over the XOR-drawn display so sprites that are
+// erased and redrawn every frame don't strobe. Feed it one display per frame.
+#[derive(Debug, Clone)]
+pub struct Phosphor {
+    mode: PhosphorMode,
+    frame: GrayFrame,
+    history: VecDeque<Vec<Row>>
+}
+
+impl Phosphor {
+    pub fn new(mode: PhosphorMode) -> Self {
+        Self {
+            mode,
+            frame: GrayFrame::new(0, 0),
+            history: VecDeque::new()
+        }
+    }
+
+    pub fn mode(&self) -> PhosphorMode {
+        self.mode
+    }
+
+    pub fn frame(&self) -> &GrayFrame {
+        &self.frame
+    }
+
+    // True once further updates with an unchanged display change nothing.
+    pub fn is_settled(&self) -> bool {
+        self.frame.pixels.iter().all(|intensity| *intensity == 0.0 || *intensity == 1.0)
+            && self.history.iter().all(|rows| Some(rows) == self.history.back())
+    }
+
+    pub fn update(&mut self, display: &Display) {
+        let (width, height) = (display.width as usize, display.height as usize);
+        if (self.frame.width, self.frame.height) != (width, height) {
+            self.frame = GrayFrame::new(width, height);
+            self.history.clear();
+        }
+        match self.mode {
+            PhosphorMode::Decay(factor) => {
+                for (x, y, lit) in display.pixels() {
+                    let intensity = &mut self.frame.pixels[y as usize * width + x as usize];
+                    *intensity = if lit {
+                        1.0
+                    } else if *intensity * factor < 1.0 / 255.0 {
+                        0.0
+                    } else {
+                        *intensity * factor
+                    };
+                }
+            },
+            PhosphorMode::Blend(frames) => {
+                self.history.push_back(display.rows().to_vec());
+                while self.history.len() > frames {
+                    self.history.pop_front();
+                }
+                let weight = 1.0 / self.history.len() as f32;
+                for (x, y, _) in display.pixels() {
+                    let bit: Row = 1 << (MAX_WIDTH - 1 - x);
+                    let lit = self.history.iter().filter(|rows| rows[y as usize] & bit != 0).count();
+                    self.frame.pixels[y as usize * width + x as usize] = lit as f32 * weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!("decay:0.5".parse(), Ok(PhosphorMode::Decay(0.5)));
+        assert_eq!("blend:3".parse(), Ok(PhosphorMode::Blend(3)));
+        for bad in ["decay:1", "decay:-0.1", "blend:0", "blend", "fade:2"] {
+            assert!(bad.parse::<PhosphorMode>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn decay_fades_erased_pixels_to_black() {
+        let mut display = Display::new(2, 1);
+        let mut phosphor = Phosphor::new(PhosphorMode::Decay(0.5));
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        phosphor.update(&display);
+        assert_eq!(phosphor.frame().pixels, vec![1.0, 0.0]);
+        assert!(phosphor.is_settled());
+
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        phosphor.update(&display);
+        assert_eq!(phosphor.frame().get(0, 0), 0.5);
+        assert!(!phosphor.is_settled());
+        // Snaps to black once it would round to zero in 8 bits.
+        for _ in 0..8 {
+            phosphor.update(&display);
+        }
+        assert_eq!(phosphor.frame().get(0, 0), 0.0);
+        assert!(phosphor.is_settled());
+    }
+
+    #[test]
+    fn blend_averages_the_last_frames() {
+        let mut display = Display::new(1, 1);
+        let mut phosphor = Phosphor::new(PhosphorMode::Blend(2));
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        phosphor.update(&display);
+        assert_eq!(phosphor.frame().get(0, 0), 1.0);
+
+        display.clear();
+        phosphor.update(&display);
+        assert_eq!(phosphor.frame().get(0, 0), 0.5);
+        assert_eq!(phosphor.frame().to_gray8(), vec![128]);
+        assert!(!phosphor.is_settled());
+
+        phosphor.update(&display);
+        assert_eq!(phosphor.frame().get(0, 0), 0.0);
+        assert!(phosphor.is_settled());
+    }
+
+    #[test]
+    fn resizing_the_display_starts_over() {
+        let mut display = Display::new(1, 1);
+        let mut phosphor = Phosphor::new(PhosphorMode::Blend(4));
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        phosphor.update(&display);
+        display.resize(2, 1);
+        phosphor.update(&display);
+        assert_eq!((phosphor.frame().width, phosphor.frame().height), (2, 1));
+        assert!(phosphor.is_settled());
+    }
+}
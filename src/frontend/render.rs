@@ -0,0 +1,203 @@
+use crate::emulator::display::{Display, Rect};
+use super::palette::Palette;
+use super::phosphor::GrayFrame;
+
+#[derive(Debug)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Palette::default().background; width * height]
+        }
+    }
+
+    pub fn from_display(display: &Display, scale: usize, palette: &Palette) -> Self {
+        let scale = scale.max(1);
+        let mut framebuffer = Framebuffer::new(display.width as usize * scale, display.height as usize * scale);
+        framebuffer.render(display, palette);
+        framebuffer
+    }
+
+    pub fn from_gray(frame: &GrayFrame, scale: usize, palette: &Palette) -> Self {
+        let scale = scale.max(1);
+        let mut framebuffer = Framebuffer::new(frame.width * scale, frame.height * scale);
+        render_gray_into(frame, palette, &mut framebuffer.pixels, framebuffer.width, framebuffer.height);
+        framebuffer
+    }
+
+    pub fn render(&mut self, display: &Display, palette: &Palette) {
+        render_into(display, palette, &mut self.pixels, self.width, self.height);
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|px| [(px >> 16) as u8, (px >> 8) as u8, *px as u8]).collect()
+    }
+}
+
+// Largest whole-number scale at which the display fits in the target area.
+pub fn integer_scale(display: &Display, width: usize, height: usize) -> usize {
+    scale_to_fit(display.width as usize, display.height as usize, width, height)
+}
+
+fn scale_to_fit(columns: usize, rows: usize, width: usize, height: usize) -> usize {
+    if columns == 0 || rows == 0 {
+        return 1;
+    }
+    (width / columns).min(height / rows).max(1)
+}
+
+// Where the display lands in a target of the given size: integer scale and
+// the offsets that center it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub scale: usize,
+    pub x_offset: usize,
+    pub y_offset: usize
+}
+
+impl Placement {
+    pub fn new(display: &Display, width: usize, height: usize) -> Self {
+        Placement::for_size(display.width as usize, display.height as usize, width, height)
+    }
+
+    pub fn for_size(columns: usize, rows: usize, width: usize, height: usize) -> Self {
+        let scale = scale_to_fit(columns, rows, width, height);
+        Self {
+            scale,
+            x_offset: width.saturating_sub(columns * scale) / 2,
+            y_offset: height.saturating_sub(rows * scale) / 2
+        }
+    }
+
+    // Display rectangle in target pixels as (x, y, width, height).
+    pub fn map_rect(&self, rect: &Rect) -> (usize, usize, usize, usize) {
+        (
+            self.x_offset + rect.x as usize * self.scale,
+            self.y_offset + rect.y as usize * self.scale,
+            rect.width as usize * self.scale,
+            rect.height as usize * self.scale
+        )
+    }
+
+    // Like `map_rect`, cut down to a target of the given size; `None` if
+    // nothing of it is visible.
+    pub fn clip_rect(&self, rect: &Rect, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+        let (x, y, w, h) = self.map_rect(rect);
+        let w = w.min(width.saturating_sub(x));
+        let h = h.min(height.saturating_sub(y));
+        Some((x, y, w, h)).filter(|_| w > 0 && h > 0)
+    }
+}
+
+// Blits the display into a 0RGB buffer of the given size, scaled by the
+// largest integer factor that fits and centered with letterboxing.
+pub fn render_into(display: &Display, palette: &Palette, buffer: &mut [u32], width: usize, height: usize) {
+    buffer.iter_mut().for_each(|px| *px = palette.background);
+    let Placement { scale, x_offset, y_offset } = Placement::new(display, width, height);
+    let foreground = palette.plane(1);
+
+    for (x, y, _) in display.pixels().filter(|(_, _, lit)| *lit) {
+        let left = (x_offset + x as usize * scale).min(width);
+        let right = (left + scale).min(width);
+        for dy in 0..scale {
+            let by = y_offset + y as usize * scale + dy;
+            if by >= height {
+                break;
+            }
+            buffer[by * width + left..by * width + right].iter_mut().for_each(|px| *px = foreground);
+        }
+    }
+}
+
+// Like `render_into`, for a grayscale frame such as phosphor output.
+pub fn render_gray_into(frame: &GrayFrame, palette: &Palette, buffer: &mut [u32], width: usize, height: usize) {
+    buffer.iter_mut().for_each(|px| *px = palette.background);
+    let Placement { scale, x_offset, y_offset } = Placement::for_size(frame.width, frame.height, width, height);
+
+    for (index, intensity) in frame.pixels.iter().enumerate().filter(|(_, intensity)| **intensity > 0.0) {
+        let (x, y) = (index % frame.width, index / frame.width);
+        let color = palette.shade(*intensity);
+        let left = (x_offset + x * scale).min(width);
+        let right = (left + scale).min(width);
+        for dy in 0..scale {
+            let by = y_offset + y * scale + dy;
+            if by >= height {
+                break;
+            }
+            buffer[by * width + left..by * width + right].iter_mut().for_each(|px| *px = color);
+        }
+    }
+}
+
+pub fn render_text(display: &Display) -> String {
+    let mut text = String::with_capacity((display.width as usize + 1) * display.height as usize);
+    for y in 0..display.height {
+        text.extend((0..display.width).map(|x| if display.pixel(x, y) { '#' } else { '.' }));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_text_marks_lit_pixels() {
+        let mut display = Display::new(4, 2);
+        display.draw_sprite(1, 1, &[0b1010_0000], false).unwrap();
+        assert_eq!(render_text(&display), "....\n.#.#\n");
+    }
+
+    #[test]
+    fn render_into_scales_and_centers() {
+        let palette = Palette::default();
+        let mut display = Display::new(2, 1);
+        display.draw_sprite(1, 0, &[0x80], false).unwrap();
+        // 2x1 in 5x3: scale 2, one column of letterbox on the right and one
+        // row below.
+        let mut buffer = vec![0x123456; 5 * 3];
+        render_into(&display, &palette, &mut buffer, 5, 3);
+        let (bg, fg) = (palette.background, palette.foreground);
+        assert_eq!(buffer, vec![
+            bg, bg, fg, fg, bg,
+            bg, bg, fg, fg, bg,
+            bg, bg, bg, bg, bg
+        ]);
+    }
+
+    #[test]
+    fn placement_maps_display_rects_to_target_pixels() {
+        let placement = Placement::for_size(64, 32, 660, 330);
+        assert_eq!(placement, Placement { scale: 10, x_offset: 10, y_offset: 5 });
+        let rect = Rect { x: 2, y: 3, width: 8, height: 4 };
+        assert_eq!(placement.map_rect(&rect), (30, 35, 80, 40));
+        // Too small to scale up: drawn at 1x from the corner.
+        let placement = Placement::for_size(64, 32, 32, 16);
+        assert_eq!(placement.map_rect(&rect), (2, 3, 8, 4));
+    }
+
+    #[test]
+    fn clip_rect_cuts_damage_to_the_target() {
+        let placement = Placement::for_size(64, 32, 32, 16);
+        let rect = Rect { x: 28, y: 14, width: 8, height: 4 };
+        assert_eq!(placement.clip_rect(&rect, 32, 16), Some((28, 14, 4, 2)));
+        let off_screen = Rect { x: 40, y: 0, width: 8, height: 1 };
+        assert_eq!(placement.clip_rect(&off_screen, 32, 16), None);
+    }
+
+    #[test]
+    fn framebuffer_converts_to_rgb8() {
+        let mut display = Display::new(1, 1);
+        display.draw_sprite(0, 0, &[0x80], false).unwrap();
+        let framebuffer = Framebuffer::from_display(&display, 1, &Palette::default());
+        assert_eq!(framebuffer.to_rgb8(), vec![0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,714 @@
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::audio::AudioBackend;
+use crate::audio::buzzer::{Buzzer, Tone, Voice};
+use crate::emulator::display::DirtyRegion;
+use crate::emulator::emulator::{Emulator, MemoryAddress, Result};
+use crate::emulator::font::Font;
+use crate::emulator::instructions::Instruction;
+use crate::emulator::memory::RegionKind;
+use crate::emulator::platform::Platform;
+use crate::emulator::quirks::Quirks;
+use crate::input::keymap::{Keymap, KeymapConfig};
+use crate::input::keypad::{Key, Keypad};
+use crate::input::movie::{Movie, MovieRecorder};
+use super::capture::{save_png, Animation, CaptureFormat};
+use super::palette::Palette;
+use super::phosphor::{GrayFrame, Phosphor, PhosphorMode};
+
+pub const CYCLES_PER_FRAME: usize = 10;
+
+#[derive(Debug)]
+pub struct Session {
+    emulator: Emulator,
+    rom: Vec<u8>,
+    rom_path: Option<PathBuf>,
+    keymaps: KeymapConfig,
+    keymap: Keymap,
+    paused: bool,
+    cycles_per_frame: usize,
+    recording: Option<(PathBuf, MovieRecorder)>,
+    // Key changes made while recording and stopped mid-frame, held back
+    // until the next frame starts so the movie sees them when the CPU does.
+    deferred_keys: Option<Keypad>,
+    playback: Option<Playback>,
+    audio: Option<(Buzzer, Box<dyn AudioBackend>)>,
+    phosphor: Option<Phosphor>,
+    palette: Palette,
+    frame: u64,
+    capture: Option<Capture>,
+    breakpoints: BTreeSet<MemoryAddress>,
+    // Instructions already run in the current frame, which a breakpoint can
+    // interrupt.
+    cycle: usize,
+    in_frame: bool,
+    // Set when stopped at a breakpoint so resuming runs that instruction.
+    resuming: bool,
+    track_events: bool,
+    events: Vec<SessionEvent>,
+    // Debug text to show over the game, such as a script's overlay lines.
+    overlay: Vec<String>
+}
+
+// Things that happened while running, collected when enabled with
+// `Session::set_event_tracking`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    // Paused before the instruction at this address.
+    Breakpoint(MemoryAddress),
+    // A DRW at this position; `collision` is VF afterwards.
+    Draw { x: u8, y: u8, height: u8, collision: bool },
+    // The sound timer went from zero to running.
+    SoundStart,
+    // `Session::frame` finished.
+    FrameEnd(u64)
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    NotPaused,
+    MovieActive
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::NotPaused => write!(f, "Memory can only be edited while paused"),
+            SessionError::MovieActive => write!(f, "Memory can't be edited or reloaded while recording or playing a movie")
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+#[derive(Debug)]
+struct Capture {
+    path: PathBuf,
+    scale: usize,
+    frames: Range<u64>,
+    animation: Animation
+}
+
+#[derive(Debug)]
+struct Playback {
+    movie: Movie,
+    next_input: usize,
+    frame: u64
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            emulator: Emulator::default(),
+            rom: vec![],
+            rom_path: None,
+            keymaps: KeymapConfig::default(),
+            keymap: Keymap::default(),
+            paused: false,
+            cycles_per_frame: CYCLES_PER_FRAME,
+            recording: None,
+            deferred_keys: None,
+            playback: None,
+            audio: None,
+            phosphor: None,
+            palette: Palette::default(),
+            frame: 0,
+            capture: None,
+            breakpoints: BTreeSet::new(),
+            cycle: 0,
+            in_frame: false,
+            resuming: false,
+            track_events: false,
+            events: vec![],
+            overlay: vec![]
+        }
+    }
+}
+
+impl Session {
+    pub fn load_rom_file(&mut self, path: &Path) -> Result<()> {
+        let rom = fs::read(path)?;
+        self.load_rom(&rom)?;
+        self.rom_path = Some(path.to_path_buf());
+        self.keymap = self.keymaps.keymap_for(self.rom_name().as_deref())?;
+        Ok(())
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
+        self.stop_recording()?;
+        self.playback = None;
+        self.emulator.load_program(rom)?;
+        self.frame = 0;
+        self.cycle = 0;
+        self.in_frame = false;
+        self.resuming = false;
+        self.rom = rom.to_vec();
+        self.rom_path = None;
+        self.keymap = self.keymaps.keymap_for(None)?;
+        Ok(())
+    }
+
+    // Resetting while recording restarts the recording from the fresh state.
+    pub fn reset(&mut self) -> Result<()> {
+        self.playback = None;
+        self.emulator.load_program(&self.rom)?;
+        self.frame = 0;
+        self.cycle = 0;
+        self.in_frame = false;
+        self.resuming = false;
+        self.apply_deferred_keys();
+        if let Some((_, recorder)) = &mut self.recording {
+            *recorder = MovieRecorder::new(&self.emulator, &self.rom, self.cycles_per_frame);
+        }
+        Ok(())
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.emulator.set_quirks(quirks);
+    }
+
+    // Reloads the current ROM at the new platform's load address.
+    pub fn set_platform(&mut self, platform: Platform) -> Result<()> {
+        self.emulator.set_platform(platform)?;
+        self.reset()
+    }
+
+    // Takes effect immediately; the font is not part of the ROM, so a reset
+    // keeps it.
+    pub fn set_font(&mut self, font: &Font, base: MemoryAddress) -> Result<()> {
+        self.emulator.set_font(font, base)
+    }
+
+    pub fn set_protected(&mut self, kind: RegionKind, protected: bool) {
+        self.emulator.memory_mut().set_protected(kind, protected);
+    }
+
+    pub fn start_recording(&mut self, path: &Path) -> Result<()> {
+        self.stop_recording()?;
+        self.reset()?;
+        self.recording = Some((path.to_path_buf(), MovieRecorder::new(&self.emulator, &self.rom, self.cycles_per_frame)));
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Saves the movie recorded so far, if any.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        self.apply_deferred_keys();
+        if let Some((path, recorder)) = self.recording.take() {
+            recorder.finish(&self.emulator).save(&path)?;
+        }
+        Ok(())
+    }
+
+    // Replays a movie against the loaded ROM. Host keys are ignored until it
+    // ends, and any held beforehand are released, as they were when it was
+    // recorded; the session then pauses and reports a desync if the final
+    // state differs from the recording.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<()> {
+        movie.check_rom(&self.rom)?;
+        self.stop_recording()?;
+        movie.configure(&mut self.emulator)?;
+        self.emulator.load_program(&self.rom)?;
+        self.emulator.keypad_mut().release_all();
+        self.deferred_keys = None;
+        self.frame = 0;
+        self.cycle = 0;
+        self.in_frame = false;
+        self.resuming = false;
+        self.playback = Some(Playback { movie, next_input: 0, frame: 0 });
+        self.paused = false;
+        Ok(())
+    }
+
+    // Replaces the audio output, finishing the previous one.
+    pub fn set_audio(&mut self, tone: Tone, backend: Box<dyn AudioBackend>) -> Result<()> {
+        self.stop_audio()?;
+        self.audio = Some((Buzzer::new(tone, backend.sample_rate()), backend));
+        Ok(())
+    }
+
+    pub fn stop_audio(&mut self) -> Result<()> {
+        if let Some((_, mut backend)) = self.audio.take() {
+            backend.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn set_phosphor(&mut self, mode: Option<PhosphorMode>) {
+        self.phosphor = mode.map(|mode| {
+            let mut phosphor = Phosphor::new(mode);
+            phosphor.update(self.emulator.display());
+            phosphor
+        });
+    }
+
+    pub fn phosphor(&self) -> Option<&Phosphor> {
+        self.phosphor.as_ref()
+    }
+
+    // Frames run since the ROM was loaded or reset.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // What the display currently looks like, through the phosphor if enabled.
+    pub fn gray_frame(&self) -> GrayFrame {
+        match &self.phosphor {
+            Some(phosphor) => phosphor.frame().clone(),
+            None => GrayFrame::from_display(self.emulator.display())
+        }
+    }
+
+    pub fn overlay(&self) -> &[String] {
+        &self.overlay
+    }
+
+    pub fn set_overlay(&mut self, lines: Vec<String>) {
+        self.overlay = lines;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn screenshot(&self, path: &Path, scale: usize) -> Result<()> {
+        save_png(&self.gray_frame(), scale, &self.palette, path)
+    }
+
+    // Records the frames numbered `frames` (see `frame`) to a GIF or APNG,
+    // saved once the range has passed or the capture is stopped.
+    pub fn start_capture(&mut self, path: &Path, scale: usize, frames: Range<u64>) -> Result<()> {
+        CaptureFormat::from_path(path)?;
+        self.stop_capture()?;
+        self.capture = Some(Capture { path: path.to_path_buf(), scale, frames, animation: Animation::default() });
+        Ok(())
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    pub fn stop_capture(&mut self) -> Result<()> {
+        match self.capture.take() {
+            Some(capture) if !capture.animation.is_empty() => capture.animation.save(&capture.path, capture.scale, &self.palette),
+            _ => Ok(())
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
+    pub fn rom_name(&self) -> Option<String> {
+        self.rom_path()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    pub fn set_keymaps(&mut self, keymaps: KeymapConfig) -> Result<()> {
+        self.keymap = keymaps.keymap_for(self.rom_name().as_deref())?;
+        self.keymaps = keymaps;
+        Ok(())
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn take_dirty(&mut self) -> DirtyRegion {
+        self.emulator.take_dirty()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles: usize) {
+        self.cycles_per_frame = cycles;
+    }
+
+    // The movie's rate while one is playing, otherwise the host's.
+    pub fn cycles_per_frame(&self) -> usize {
+        self.playback.as_ref().map_or(self.cycles_per_frame, |playback| playback.movie.cycles_per_frame)
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) -> Result<()> {
+        if self.playback.is_some() {
+            return Ok(());
+        }
+        if self.recording.is_some() && self.in_frame {
+            let keypad = *self.emulator.keypad();
+            return self.deferred_keys.get_or_insert(keypad).set(key, pressed);
+        }
+        self.emulator.keypad_mut().set(key, pressed)
+    }
+
+    fn apply_deferred_keys(&mut self) {
+        if let Some(keypad) = self.deferred_keys.take() {
+            *self.emulator.keypad_mut() = keypad;
+        }
+    }
+
+    // Routes a host key through the active keymap. Returns whether it was bound.
+    pub fn set_host_key<'a, I: IntoIterator<Item = &'a str>>(&mut self, names: I, pressed: bool) -> Result<bool> {
+        match self.keymap.resolve(names) {
+            Some(key) => {
+                self.set_key(key, pressed)?;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    // Live edits are only allowed while paused, and not during a recording or
+    // playback since the movie couldn't reproduce them.
+    pub fn poke(&mut self, addr: MemoryAddress, data: &[u8]) -> Result<()> {
+        if !self.paused {
+            return Err(Box::new(SessionError::NotPaused));
+        }
+        self.write_memory(addr, data)
+    }
+
+    // Edits from automation, which runs between frames rather than from a
+    // pause.
+    pub fn write_memory(&mut self, addr: MemoryAddress, data: &[u8]) -> Result<()> {
+        self.check_no_movie()?;
+        self.emulator.memory_mut().poke(addr, data)
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u8) -> Result<()> {
+        self.check_no_movie()?;
+        self.emulator.set_register(register, value)
+    }
+
+    pub fn set_i(&mut self, addr: MemoryAddress) -> Result<()> {
+        self.check_no_movie()?;
+        self.emulator.set_i(addr);
+        Ok(())
+    }
+
+    fn check_no_movie(&self) -> Result<()> {
+        if self.recording.is_some() || self.playback.is_some() {
+            return Err(Box::new(SessionError::MovieActive));
+        }
+        Ok(())
+    }
+
+    pub fn add_breakpoint(&mut self, addr: MemoryAddress) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: MemoryAddress) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<MemoryAddress> {
+        &self.breakpoints
+    }
+
+    pub fn set_event_tracking(&mut self, enabled: bool) {
+        self.track_events = enabled;
+        self.events.clear();
+    }
+
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Replaces the running program in place (see `Emulator::patch_program`).
+    // Sessions only run between frames, so the CPU is always between
+    // instructions here. Not allowed during a movie, which couldn't
+    // reproduce it.
+    pub fn hot_reload(&mut self, rom: &[u8], remap: &dyn Fn(MemoryAddress) -> MemoryAddress, remap_return: &dyn Fn(MemoryAddress) -> MemoryAddress) -> Result<()> {
+        self.check_no_movie()?;
+        self.emulator.patch_program(rom, remap, remap_return)?;
+        self.rom = rom.to_vec();
+        Ok(())
+    }
+
+    // Runs one instruction as part of the current frame, so the next
+    // `run_frame` only runs what's left of it. Not allowed during a movie.
+    pub fn step_instruction(&mut self) -> Result<()> {
+        self.check_no_movie()?;
+        self.resuming = false;
+        self.emulator.step()?;
+        self.in_frame = true;
+        self.cycle += 1;
+        Ok(())
+    }
+
+    // Runs the rest of the frame's instructions, stopping at breakpoints.
+    // A frame interrupted by a breakpoint picks up where it left off, and
+    // keys changed meanwhile wait for the next frame (see `set_key`), so
+    // movies stay in sync.
+    fn run_cycles(&mut self) -> Result<bool> {
+        let cycles_per_frame = self.cycles_per_frame();
+        if self.breakpoints.is_empty() && !self.track_events {
+            let remaining = cycles_per_frame.saturating_sub(self.cycle);
+            self.cycle = cycles_per_frame;
+            if let Err(e) = self.emulator.run_cycles(remaining) {
+                self.paused = true;
+                return Err(e);
+            }
+        }
+        while self.cycle < cycles_per_frame {
+            let pc = self.emulator.pc();
+            let running = !self.emulator.is_waiting_for_key();
+            if running && !self.resuming && self.breakpoints.contains(&pc) {
+                self.paused = true;
+                self.resuming = true;
+                if self.track_events {
+                    self.events.push(SessionEvent::Breakpoint(pc));
+                }
+                return Ok(false);
+            }
+            self.resuming = false;
+            let registers = self.emulator.registers();
+            let draw = match self.emulator.instruction_at(pc).filter(|_| running && self.track_events) {
+                Some(Instruction::DRW(vx, vy, n)) => Some((registers[vx as usize], registers[vy as usize], n)),
+                _ => None
+            };
+            let sounding = self.emulator.is_sound_playing();
+            if let Err(e) = self.emulator.step() {
+                self.paused = true;
+                return Err(e);
+            }
+            self.cycle += 1;
+            if !self.track_events {
+                continue;
+            }
+            if let Some((x, y, height)) = draw {
+                let collision = self.emulator.registers()[0xF] == 1;
+                self.events.push(SessionEvent::Draw { x, y, height, collision });
+            }
+            if !sounding && self.emulator.is_sound_playing() {
+                self.events.push(SessionEvent::SoundStart);
+            }
+        }
+        self.cycle = 0;
+        self.in_frame = false;
+        self.resuming = false;
+        Ok(true)
+    }
+
+    // Runs one 60Hz frame worth of instructions. A faulting instruction pauses
+    // the session so the host can keep rendering the last good state.
+    pub fn run_frame(&mut self) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        if self.in_frame {
+            return self.finish_frame();
+        }
+        if let Some(playback) = &mut self.playback {
+            if playback.frame >= playback.movie.frames {
+                let movie = self.playback.take().map(|playback| playback.movie);
+                self.paused = true;
+                return movie.map_or(Ok(()), |movie| movie.check_final_state(&self.emulator));
+            }
+            while let Some(change) = playback.movie.inputs.get(playback.next_input).filter(|change| change.frame <= playback.frame) {
+                self.emulator.keypad_mut().set_bits(change.keys);
+                playback.next_input += 1;
+            }
+            playback.frame += 1;
+        }
+        self.apply_deferred_keys();
+        if let Some((_, recorder)) = &mut self.recording {
+            recorder.record_frame(&self.emulator);
+        }
+        self.in_frame = true;
+        self.finish_frame()
+    }
+
+    fn finish_frame(&mut self) -> Result<()> {
+        if !self.run_cycles()? {
+            return Ok(());
+        }
+        if let Some((buzzer, backend)) = &mut self.audio {
+            buzzer.set_voice(match self.emulator.audio_pattern() {
+                Some(bits) => Voice::Pattern { bits, pitch: self.emulator.pitch() },
+                None => Voice::Tone
+            });
+            backend.queue(&buzzer.render_frame(self.emulator.is_sound_playing()))?;
+        }
+        self.emulator.tick_timers();
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.update(self.emulator.display());
+        }
+        let frame = self.frame;
+        self.frame += 1;
+        if self.track_events {
+            self.events.push(SessionEvent::FrameEnd(frame));
+        }
+        if self.capture.as_ref().is_some_and(|capture| capture.frames.contains(&frame)) {
+            let gray = self.gray_frame();
+            if let Some(capture) = &mut self.capture {
+                capture.animation.push(gray);
+            }
+        }
+        if self.capture.as_ref().is_some_and(|capture| self.frame >= capture.frames.end) {
+            self.stop_capture()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::audio::wav::WavWriter;
+    use crate::emulator::platform::PlatformProfile;
+
+    // Set LUCID8_BLESS=1 to rewrite the golden files from the current output.
+    fn check_golden(name: &str, actual: &[u8]) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/frontend/testdata").join(name);
+        if std::env::var_os("LUCID8_BLESS").is_some() {
+            fs::write(&path, actual).unwrap();
+        }
+        let expected = fs::read(&path).unwrap();
+        assert!(expected == actual, "{} differs from the golden file", name);
+    }
+
+    // A WAV sink the test can still read after the session owns the writer.
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for SharedBuffer {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.0.borrow_mut().seek(pos)
+        }
+    }
+
+    #[test]
+    fn xo_chip_audio_matches_the_golden_wav() {
+        const ROM: [u8; 32] = [
+            0xA2, 0x10, // LD I, pattern
+            0xF0, 0x02, // AUDIO
+            0x60, 0x70, // LD V0, 0x70
+            0xF0, 0x3A, // PITCH V0
+            0x61, 0x03, // LD V1, 3
+            0xF1, 0x18, // LD ST, V1
+            0x12, 0x0C, // JP 0x20C
+            0x00, 0x00,
+            // pattern:
+            0xF0, 0xF0, 0xCC, 0xCC, 0xAA, 0xAA, 0x00, 0xFF,
+            0x0F, 0x0F, 0x33, 0x33, 0x55, 0x55, 0xFF, 0x00
+        ];
+        let buffer = SharedBuffer::default();
+        let mut session = Session::default();
+        session.set_platform(PlatformProfile::XoChip.into()).unwrap();
+        session.load_rom(&ROM).unwrap();
+        // Pitch 0x70 plays 8000 pattern bits a second, one per sample.
+        session.set_audio(Tone::default(), Box::new(WavWriter::new(buffer.clone(), 8000).unwrap())).unwrap();
+        for _ in 0..6 {
+            session.run_frame().unwrap();
+        }
+        session.stop_audio().unwrap();
+        check_golden("xo_chip_audio.wav", buffer.0.borrow().get_ref());
+    }
+
+    #[test]
+    fn keys_pressed_at_a_breakpoint_stay_in_sync_with_the_recording() {
+        const ROM: [u8; 8] = [
+            0x60, 0x00, // LD V0, 0
+            0xE0, 0xA1, // SKNP V0
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x02  // JP 0x202
+        ];
+        let path = std::env::temp_dir().join(format!("lucid8-breakpoint-movie-{}.toml", std::process::id()));
+        let mut session = Session::default();
+        session.load_rom(&ROM).unwrap();
+        session.start_recording(&path).unwrap();
+        session.add_breakpoint(0x206);
+        session.run_frame().unwrap();
+        assert!(session.is_paused());
+        // Held down while stopped mid-frame: the rest of this frame runs
+        // without it, as the movie will replay it.
+        session.set_key(0, true).unwrap();
+        session.remove_breakpoint(0x206);
+        session.toggle_pause();
+        for _ in 0..5 {
+            session.run_frame().unwrap();
+        }
+        session.stop_recording().unwrap();
+        let movie = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+        movie.unwrap().verify(&ROM).unwrap();
+    }
+
+    #[test]
+    fn movies_replay_without_the_host_keys_or_speed() {
+        const ROM: [u8; 8] = [
+            0x60, 0x00, // LD V0, 0
+            0xE0, 0xA1, // SKNP V0
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x02  // JP 0x202
+        ];
+        let path = std::env::temp_dir().join(format!("lucid8-held-key-movie-{}.toml", std::process::id()));
+        let mut session = Session::default();
+        session.load_rom(&ROM).unwrap();
+        session.start_recording(&path).unwrap();
+        for _ in 0..3 {
+            session.run_frame().unwrap();
+        }
+        session.set_key(0, true).unwrap();
+        for _ in 0..3 {
+            session.run_frame().unwrap();
+        }
+        session.stop_recording().unwrap();
+        let movie = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mut session = Session::default();
+        session.load_rom(&ROM).unwrap();
+        session.set_cycles_per_frame(7);
+        session.run_frame().unwrap();
+        session.set_key(0, true).unwrap();
+        session.play_movie(movie.unwrap()).unwrap();
+        assert_eq!(session.frame(), 0);
+        assert_eq!(session.cycles_per_frame(), CYCLES_PER_FRAME);
+        while session.is_playing() {
+            // The last call checks the final state against the recording.
+            session.run_frame().unwrap();
+        }
+        assert_eq!(session.frame(), 6);
+        assert_eq!(session.cycles_per_frame(), 7);
+    }
+}
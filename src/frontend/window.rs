@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{Key, KeyCode, PhysicalKey};
+use winit::window::{Window, WindowBuilder};
+
+use crate::emulator::emulator::Result;
+use crate::emulator::display::Rect;
+use super::render::{render_gray_into, render_into, Placement};
+use super::session::Session;
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+type Surface = softbuffer::Surface<Rc<Window>, Rc<Window>>;
+
+// The overlay goes in the title bar, one line after another.
+fn title(session: &Session) -> String {
+    let name = session.rom_name().unwrap_or_else(|| String::from("no rom"));
+    let mut title = format!("lucid8 - {}", name);
+    if session.is_paused() {
+        title.push_str(" [paused]");
+    }
+    for line in session.overlay() {
+        title.push_str(" | ");
+        title.push_str(line);
+    }
+    title
+}
+
+// Keymaps name keys by the character they type, or by their code name when
+// they don't type one (numpad layouts bind "Numpad7" rather than "7").
+fn host_key_names(code: KeyCode, logical: &Key) -> Vec<String> {
+    let mut names = vec![format!("{:?}", code)];
+    if let Key::Character(text) = logical {
+        names.push(text.to_lowercase());
+    }
+    names
+}
+
+// Names each physical key was pressed under. The character a key types can
+// change while it is held (Shift turns "1" into "!"), so releases reuse the
+// names from the press rather than resolving them again.
+#[derive(Debug, Default)]
+struct HeldKeys {
+    names: HashMap<KeyCode, Vec<String>>
+}
+
+impl HeldKeys {
+    fn update(&mut self, code: KeyCode, logical: &Key, pressed: bool) -> Vec<String> {
+        if pressed {
+            self.names.entry(code).or_insert_with(|| host_key_names(code, logical)).clone()
+        } else {
+            self.names.remove(&code).unwrap_or_else(|| host_key_names(code, logical))
+        }
+    }
+}
+
+// Redraws the whole buffer but, when only `damage` changed since the last
+// present, tells the compositor so it can skip the rest. Empty damage (e.g. a
+// redraw the system asked for) presents everything.
+fn redraw(surface: &mut Surface, window: &Window, session: &Session, damage: Option<&[Rect]>) -> Result<()> {
+    let size = window.inner_size();
+    let (width, height) = match (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Ok(())
+    };
+    let display = session.emulator().display();
+    surface.resize(width, height)?;
+    let mut buffer = surface.buffer_mut()?;
+    if let Some(phosphor) = session.phosphor() {
+        render_gray_into(phosphor.frame(), session.palette(), &mut buffer, width.get() as usize, height.get() as usize);
+        buffer.present()?;
+        return Ok(());
+    }
+    render_into(display, session.palette(), &mut buffer, width.get() as usize, height.get() as usize);
+    match damage.filter(|damage| !damage.is_empty()) {
+        Some(damage) => {
+            let placement = Placement::new(display, width.get() as usize, height.get() as usize);
+            let rects: Vec<softbuffer::Rect> = damage.iter()
+                .filter_map(|rect| placement.clip_rect(rect, width.get() as usize, height.get() as usize))
+                .filter_map(|(x, y, w, h)| Some(softbuffer::Rect { x: x as u32, y: y as u32, width: NonZeroU32::new(w as u32)?, height: NonZeroU32::new(h as u32)? }))
+                .collect();
+            if rects.is_empty() {
+                buffer.present()?;
+            } else {
+                buffer.present_with_damage(&rects)?;
+            }
+        },
+        None => buffer.present()?
+    }
+    Ok(())
+}
+
+// Hotkeys: P pauses, N steps one instruction while paused, F5 resets the ROM,
+// F12 saves a screenshot, F9 starts and stops a GIF capture and Escape quits.
+// ROMs can be dropped onto the window to load them.
+fn handle_hotkey(session: &mut Session, code: KeyCode, scale: u32) -> Result<bool> {
+    match code {
+        KeyCode::KeyP => session.toggle_pause(),
+        KeyCode::KeyN if session.is_paused() => session.step_instruction()?,
+        KeyCode::F5 => session.reset()?,
+        KeyCode::F12 => {
+            let path = PathBuf::from(format!("lucid8-{}.png", session.frame()));
+            session.screenshot(&path, scale as usize)?;
+        },
+        KeyCode::F9 if session.is_capturing() => session.stop_capture()?,
+        KeyCode::F9 => {
+            let path = PathBuf::from(format!("lucid8-{}.gif", session.frame()));
+            session.start_capture(&path, scale as usize, session.frame()..u64::MAX)?;
+        },
+        KeyCode::Escape => return Ok(false),
+        _ => {}
+    }
+    Ok(true)
+}
+
+pub fn run(session: Session, scale: u32) -> Result<()> {
+    run_with(session, scale, None, |_| Ok(()))
+}
+
+// Like `run`, calling `on_frame` before every frame; it may change the
+// session, e.g. to reload the program. The last frame is saved to
+// `screenshot` when the window closes.
+pub fn run_with(mut session: Session, scale: u32, screenshot: Option<PathBuf>, mut on_frame: impl FnMut(&mut Session) -> Result<()> + 'static) -> Result<()> {
+    let event_loop = EventLoop::new()?;
+    let (width, height) = {
+        let display = session.emulator().display();
+        (display.width as u32 * scale, display.height as u32 * scale)
+    };
+    let window = Rc::new(WindowBuilder::new()
+        .with_title(title(&session))
+        .with_inner_size(PhysicalSize::new(width, height))
+        .with_min_inner_size(PhysicalSize::new(64, 32))
+        .build(&event_loop)?);
+    let context = softbuffer::Context::new(window.clone())?;
+    let mut surface = Surface::new(&context, window.clone())?;
+    let mut next_frame = Instant::now();
+    let mut damage: Vec<Rect> = vec![];
+    let mut full_redraw = true;
+    let mut held = HeldKeys::default();
+
+    event_loop.run(move |event, elwt| {
+        let result = match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => {
+                    elwt.exit();
+                    Ok(())
+                },
+                WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(code), logical_key, state, repeat, .. }, .. } => {
+                    let pressed = state == ElementState::Pressed;
+                    let names = held.update(code, &logical_key, pressed);
+                    match session.set_host_key(names.iter().map(String::as_str), pressed) {
+                        Ok(true) => Ok(()),
+                        Ok(false) if pressed && !repeat => {
+                            let handled = handle_hotkey(&mut session, code, scale);
+                            if let Ok(false) = handled {
+                                elwt.exit();
+                            }
+                            window.set_title(&title(&session));
+                            full_redraw = true;
+                            window.request_redraw();
+                            handled.map(|_| ())
+                        },
+                        Ok(false) => Ok(()),
+                        Err(e) => Err(e)
+                    }
+                },
+                WindowEvent::DroppedFile(path) => {
+                    let loaded = session.load_rom_file(&path);
+                    window.set_title(&title(&session));
+                    full_redraw = true;
+                    window.request_redraw();
+                    loaded
+                },
+                WindowEvent::Resized(_) => {
+                    full_redraw = true;
+                    window.request_redraw();
+                    Ok(())
+                },
+                WindowEvent::RedrawRequested => {
+                    let drawn = redraw(&mut surface, &window, &session, if full_redraw { None } else { Some(&damage) });
+                    damage.clear();
+                    full_redraw = false;
+                    drawn
+                },
+                _ => Ok(())
+            },
+            Event::AboutToWait => {
+                let now = Instant::now();
+                let mut ran = Ok(());
+                if now >= next_frame {
+                    let before = title(&session);
+                    ran = on_frame(&mut session);
+                    let framed = session.run_frame();
+                    ran = ran.and(framed);
+                    let after = title(&session);
+                    if after != before {
+                        window.set_title(&after);
+                    }
+                    next_frame = (next_frame + FRAME).max(now);
+                    let dirty = session.take_dirty();
+                    let fading = session.phosphor().is_some_and(|phosphor| !phosphor.is_settled());
+                    if fading || (session.phosphor().is_some() && !dirty.is_empty()) {
+                        full_redraw = true;
+                        window.request_redraw();
+                    } else if !dirty.is_empty() {
+                        damage.extend(dirty.rects());
+                        window.request_redraw();
+                    }
+                }
+                elwt.set_control_flow(ControlFlow::WaitUntil(next_frame));
+                ran
+            },
+            Event::LoopExiting => session.stop_recording()
+                .and_then(|_| session.stop_audio())
+                .and_then(|_| session.stop_capture())
+                .and_then(|_| match &screenshot {
+                    Some(path) => session.screenshot(path, scale as usize),
+                    None => Ok(())
+                }),
+            _ => Ok(())
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_shows_the_pause_state_and_overlay() {
+        let mut session = Session::default();
+        assert_eq!(title(&session), "lucid8 - no rom");
+        session.toggle_pause();
+        session.set_overlay(vec![String::from("lives 3"), String::from("hit at 4,5")]);
+        assert_eq!(title(&session), "lucid8 - no rom [paused] | lives 3 | hit at 4,5");
+    }
+
+    #[test]
+    fn release_uses_the_names_from_the_press() {
+        let mut held = HeldKeys::default();
+        let pressed = held.update(KeyCode::Digit1, &Key::Character("1".into()), true);
+        assert_eq!(pressed, vec!["Digit1", "1"]);
+        // Shift went down while "1" was held.
+        let repeated = held.update(KeyCode::Digit1, &Key::Character("!".into()), true);
+        assert_eq!(repeated, pressed);
+        let released = held.update(KeyCode::Digit1, &Key::Character("!".into()), false);
+        assert_eq!(released, pressed);
+        // Nothing is held any more.
+        assert_eq!(held.update(KeyCode::Digit1, &Key::Character("!".into()), false), vec!["Digit1", "!"]);
+    }
+}
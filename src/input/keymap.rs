@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::emulator::Result;
+use super::keypad::{Key, KeypadError};
+
+// Host keys are named by the character they type ("q", "&", "7") or, for keys
+// that don't type one, by their W3C code name ("Numpad7", "ArrowUp").
+pub type HostKey = String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    // 1234 / QWER / ASDF / ZXCV
+    #[default]
+    Qwerty,
+    // 1234 / AZER / QSDF / WXCV, with the unshifted AZERTY digit row
+    Azerty,
+    // 789 / 456 / 123 / 0 plus the operator keys
+    Numpad,
+    // Every hex digit on its own key, as printed on the COSMAC VIP pad
+    Vip
+}
+
+impl Layout {
+    fn bindings(&self) -> Vec<(&'static str, Key)> {
+        match self {
+            Layout::Qwerty => vec![
+                ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xC),
+                ("q", 0x4), ("w", 0x5), ("e", 0x6), ("r", 0xD),
+                ("a", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xE),
+                ("z", 0xA), ("x", 0x0), ("c", 0xB), ("v", 0xF)
+            ],
+            Layout::Azerty => vec![
+                ("&", 0x1), ("é", 0x2), ("\"", 0x3), ("'", 0xC),
+                ("a", 0x4), ("z", 0x5), ("e", 0x6), ("r", 0xD),
+                ("q", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xE),
+                ("w", 0xA), ("x", 0x0), ("c", 0xB), ("v", 0xF)
+            ],
+            Layout::Numpad => vec![
+                ("Numpad7", 0x1), ("Numpad8", 0x2), ("Numpad9", 0x3), ("NumpadDivide", 0xC),
+                ("Numpad4", 0x4), ("Numpad5", 0x5), ("Numpad6", 0x6), ("NumpadMultiply", 0xD),
+                ("Numpad1", 0x7), ("Numpad2", 0x8), ("Numpad3", 0x9), ("NumpadSubtract", 0xE),
+                ("NumpadDecimal", 0xA), ("Numpad0", 0x0), ("NumpadEnter", 0xB), ("NumpadAdd", 0xF)
+            ],
+            Layout::Vip => vec![
+                ("0", 0x0), ("1", 0x1), ("2", 0x2), ("3", 0x3),
+                ("4", 0x4), ("5", 0x5), ("6", 0x6), ("7", 0x7),
+                ("8", 0x8), ("9", 0x9), ("a", 0xA), ("b", 0xB),
+                ("c", 0xC), ("d", 0xD), ("e", 0xE), ("f", 0xF)
+            ]
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeymapProfile {
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bindings: BTreeMap<HostKey, Key>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeymapConfig {
+    #[serde(flatten)]
+    pub default: KeymapProfile,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roms: BTreeMap<String, KeymapProfile>
+}
+
+impl KeymapConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: KeymapConfig = toml::from_str(&fs::read_to_string(path)?)?;
+        config.keymap_for(None)?;
+        for profile in config.roms.values() {
+            Keymap::from_profile(profile)?;
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    // Keymap for a ROM, keyed by file name, falling back to the default profile.
+    pub fn keymap_for(&self, rom_name: Option<&str>) -> Result<Keymap> {
+        let profile = rom_name
+            .and_then(|name| self.roms.get(name))
+            .unwrap_or(&self.default);
+        Keymap::from_profile(profile)
+    }
+
+    pub fn set_override(&mut self, rom_name: &str, profile: KeymapProfile) {
+        self.roms.insert(rom_name.to_string(), profile);
+    }
+
+    pub fn remove_override(&mut self, rom_name: &str) -> Option<KeymapProfile> {
+        self.roms.remove(rom_name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: BTreeMap<HostKey, Key>
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::layout(Layout::default())
+    }
+}
+
+impl Keymap {
+    pub fn layout(layout: Layout) -> Self {
+        Self {
+            bindings: layout.bindings().into_iter().map(|(host, key)| (host.to_string(), key)).collect()
+        }
+    }
+
+    pub fn from_profile(profile: &KeymapProfile) -> Result<Self> {
+        let mut keymap = Keymap::layout(profile.layout);
+        for (host, key) in &profile.bindings {
+            keymap.bind(host, *key)?;
+        }
+        Ok(keymap)
+    }
+
+    pub fn bind(&mut self, host: &str, key: Key) -> Result<()> {
+        if key > 0xF {
+            return Err(Box::new(KeypadError::InvalidKey(key)));
+        }
+        self.bindings.insert(host.to_string(), key);
+        Ok(())
+    }
+
+    pub fn unbind(&mut self, host: &str) -> Option<Key> {
+        self.bindings.remove(host)
+    }
+
+    pub fn get(&self, host: &str) -> Option<Key> {
+        self.bindings.get(host).copied()
+    }
+
+    // First of several names for the same physical key press that is bound.
+    pub fn resolve<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Option<Key> {
+        names.into_iter().find_map(|name| self.get(name))
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, Key)> {
+        self.bindings.iter().map(|(host, key)| (host.as_str(), *key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_takes_the_first_bound_name() {
+        let keymap = Keymap::layout(Layout::Qwerty);
+        assert_eq!(keymap.resolve(["KeyQ", "q"]), Some(0x4));
+        assert_eq!(keymap.resolve(["Space", " "]), None);
+        let keymap = Keymap::layout(Layout::Numpad);
+        assert_eq!(keymap.resolve(["Numpad7", "7"]), Some(0x1));
+    }
+
+    #[test]
+    fn bindings_override_the_layout() {
+        let mut profile = KeymapProfile { layout: Layout::Azerty, bindings: BTreeMap::new() };
+        profile.bindings.insert(String::from("a"), 0xF);
+        let keymap = Keymap::from_profile(&profile).unwrap();
+        assert_eq!(keymap.get("a"), Some(0xF));
+        assert_eq!(keymap.get("&"), Some(0x1));
+
+        profile.bindings.insert(String::from("b"), 0x10);
+        assert!(Keymap::from_profile(&profile).is_err());
+    }
+
+    #[test]
+    fn config_round_trips_through_toml() {
+        let mut config = KeymapConfig::default();
+        config.default.bindings.insert(String::from("ArrowUp"), 0x5);
+        config.set_override("pong.ch8", KeymapProfile { layout: Layout::Vip, bindings: BTreeMap::new() });
+        let text = toml::to_string(&config).unwrap();
+        let parsed: KeymapConfig = toml::from_str(&text).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.keymap_for(Some("pong.ch8")).unwrap().get("0"), Some(0x0));
+        assert_eq!(parsed.keymap_for(Some("other.ch8")).unwrap().get("ArrowUp"), Some(0x5));
+    }
+
+    #[test]
+    fn load_rejects_out_of_range_keys() {
+        let path = std::env::temp_dir().join(format!("lucid8-keymap-{}.toml", std::process::id()));
+        fs::write(&path, "layout = \"qwerty\"\n\n[roms.\"pong.ch8\".bindings]\nq = 16\n").unwrap();
+        let loaded = KeymapConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::{Display, Formatter};
+
+use crate::emulator::emulator::Result;
+
+pub type Key = u8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [bool; 16]
+}
+
+impl Keypad {
+    pub fn set(&mut self, key: Key, pressed: bool) -> Result<()> {
+        if key as usize >= self.keys.len() {
+            return Err(Box::new(KeypadError::InvalidKey(key)));
+        }
+        self.keys[key as usize] = pressed;
+        Ok(())
+    }
+
+    pub fn press(&mut self, key: Key) -> Result<()> {
+        self.set(key, true)
+    }
+
+    pub fn release(&mut self, key: Key) -> Result<()> {
+        self.set(key, false)
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [false; 16];
+    }
+
+    pub fn is_pressed(&self, key: Key) -> Result<bool> {
+        match self.keys.get(key as usize) {
+            Some(pressed) => Ok(*pressed),
+            None => Err(Box::new(KeypadError::InvalidKey(key)))
+        }
+    }
+
+    pub fn as_bits(&self) -> u16 {
+        self.keys.iter().enumerate().fold(0, |bits, (key, pressed)| bits | ((*pressed as u16) << key))
+    }
+
+    pub fn set_bits(&mut self, bits: u16) {
+        for (key, pressed) in self.keys.iter_mut().enumerate() {
+            *pressed = bits & (1 << key) != 0;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeypadError {
+    InvalidKey(Key)
+}
+
+impl Display for KeypadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeypadError::InvalidKey(key) => write!(f, "Invalid keypad key: {:#X}", key)
+        }
+    }
+}
+
+impl std::error::Error for KeypadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_past_f_are_invalid() {
+        let mut keypad = Keypad::default();
+        for error in [keypad.set(0x10, true).unwrap_err(), keypad.press(0xFF).unwrap_err(), keypad.is_pressed(0x10).unwrap_err()] {
+            assert!(matches!(error.downcast_ref::<KeypadError>(), Some(KeypadError::InvalidKey(_))));
+        }
+        assert_eq!(keypad.release(0x10).unwrap_err().to_string(), "Invalid keypad key: 0x10");
+        assert_eq!(keypad, Keypad::default());
+    }
+
+    #[test]
+    fn release_all_lets_go_of_every_key() {
+        let mut keypad = Keypad::default();
+        keypad.press(0x0).unwrap();
+        keypad.press(0xF).unwrap();
+        assert!(keypad.is_pressed(0xF).unwrap());
+        keypad.release_all();
+        assert_eq!(keypad.as_bits(), 0);
+    }
+
+    #[test]
+    fn bits_round_trip_with_key_n_in_bit_n() {
+        let mut keypad = Keypad::default();
+        keypad.press(0x1).unwrap();
+        keypad.press(0xA).unwrap();
+        assert_eq!(keypad.as_bits(), 0b0000_0100_0000_0010);
+        let mut copy = Keypad::default();
+        copy.set_bits(keypad.as_bits());
+        assert_eq!(copy, keypad);
+        copy.set_bits(0x8001);
+        assert!(copy.is_pressed(0x0).unwrap() && copy.is_pressed(0xF).unwrap() && !copy.is_pressed(0xA).unwrap());
+    }
+}
@@ -1 +1,4 @@
-pub mod program;
\ No newline at end of file
+pub mod keymap;
+pub mod keypad;
+pub mod movie;
+pub mod program;
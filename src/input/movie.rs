@@ -0,0 +1,300 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::emulator::{Emulator, MemoryAddress, Result};
+use crate::emulator::font::Font;
+use crate::emulator::platform::Platform;
+use crate::emulator::quirks::Quirks;
+use crate::util::content_hash;
+
+pub const MOVIE_VERSION: u32 = 3;
+
+// Keypad state (one bit per key) that takes effect before the given frame runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputChange {
+    pub frame: u64,
+    pub keys: u16
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Movie {
+    pub version: u32,
+    #[serde(with = "hex_u64")]
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub platform: Platform,
+    #[serde(with = "hex_bytes")]
+    pub font: Vec<u8>,
+    pub font_base: MemoryAddress,
+    pub cycles_per_frame: usize,
+    #[serde(with = "hex_u64")]
+    pub rom_hash: u64,
+    pub frames: u64,
+    #[serde(with = "hex_u64")]
+    pub final_hash: u64,
+    #[serde(default)]
+    pub inputs: Vec<InputChange>
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    UnsupportedVersion(u32),
+    RomMismatch { expected: u64, actual: u64 },
+    Desync { expected: u64, actual: u64 }
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MovieError::UnsupportedVersion(version) => write!(f, "Unsupported movie version: {}", version),
+            MovieError::RomMismatch { expected, actual } => write!(f, "Movie was recorded with a different ROM (expected {:016x}, got {:016x})", expected, actual),
+            MovieError::Desync { expected, actual } => write!(f, "Playback desynced: final state {:016x}, recorded {:016x}", actual, expected)
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Self> {
+        let movie: Movie = toml::from_str(&fs::read_to_string(path)?)?;
+        if movie.version != MOVIE_VERSION {
+            return Err(Box::new(MovieError::UnsupportedVersion(movie.version)));
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<()> {
+        let actual = content_hash(rom);
+        if actual != self.rom_hash {
+            return Err(Box::new(MovieError::RomMismatch { expected: self.rom_hash, actual }));
+        }
+        Ok(())
+    }
+
+    // Whether a replay ended in the recorded state.
+    pub fn check_final_state(&self, emulator: &Emulator) -> Result<()> {
+        let actual = emulator.state_hash();
+        if actual != self.final_hash {
+            return Err(Box::new(MovieError::Desync { expected: self.final_hash, actual }));
+        }
+        Ok(())
+    }
+
+    // Puts `emulator` on the recorded platform and font. The program has to
+    // be loaded afterwards.
+    pub fn configure(&self, emulator: &mut Emulator) -> Result<()> {
+        emulator.set_seed(self.seed);
+        emulator.set_quirks(self.quirks);
+        emulator.set_platform(self.platform)?;
+        emulator.set_font(&Font::from_bytes(&self.font)?, self.font_base)
+    }
+
+    // Replays the recorded inputs through a fresh emulator and returns it in
+    // its final state.
+    pub fn play(&self, rom: &[u8]) -> Result<Emulator> {
+        self.check_rom(rom)?;
+        let mut emulator = Emulator::new(self.seed, self.quirks);
+        self.configure(&mut emulator)?;
+        emulator.load_program(rom)?;
+        let mut inputs = self.inputs.iter().peekable();
+        for frame in 0..self.frames {
+            while let Some(change) = inputs.next_if(|change| change.frame <= frame) {
+                emulator.keypad_mut().set_bits(change.keys);
+            }
+            emulator.run_frame(self.cycles_per_frame)?;
+        }
+        Ok(emulator)
+    }
+
+    pub fn verify(&self, rom: &[u8]) -> Result<()> {
+        self.check_final_state(&self.play(rom)?)
+    }
+}
+
+#[derive(Debug)]
+pub struct MovieRecorder {
+    movie: Movie,
+    last_keys: u16
+}
+
+impl MovieRecorder {
+    // Start recording right after `emulator` has loaded `rom`.
+    pub fn new(emulator: &Emulator, rom: &[u8], cycles_per_frame: usize) -> Self {
+        Self {
+            movie: Movie {
+                version: MOVIE_VERSION,
+                seed: emulator.seed(),
+                quirks: emulator.quirks(),
+                platform: emulator.platform(),
+                font: emulator.font().to_bytes(),
+                font_base: emulator.font_base(),
+                cycles_per_frame,
+                rom_hash: content_hash(rom),
+                frames: 0,
+                final_hash: emulator.state_hash(),
+                inputs: vec![]
+            },
+            last_keys: 0
+        }
+    }
+
+    // Call before each frame runs, with the keypad state that frame will see.
+    pub fn record_frame(&mut self, emulator: &Emulator) {
+        let keys = emulator.keypad().as_bits();
+        if keys != self.last_keys {
+            self.movie.inputs.push(InputChange { frame: self.movie.frames, keys });
+            self.last_keys = keys;
+        }
+        self.movie.frames += 1;
+    }
+
+    pub fn finish(mut self, emulator: &Emulator) -> Movie {
+        self.movie.final_hash = emulator.state_hash();
+        self.movie
+    }
+}
+
+// Byte strings such as the font are stored as one hex string.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if !text.len().is_multiple_of(2) || !text.is_ascii() {
+            return Err(serde::de::Error::custom(format!("expected pairs of hex digits, got {}", text)));
+        }
+        (0..text.len()).step_by(2)
+            .map(|start| u8::from_str_radix(&text[start..start + 2], 16).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+// TOML integers are signed 64-bit, so hashes and seeds are stored as hex strings.
+mod hex_u64 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:016x}", value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        u64::from_str_radix(&text, 16).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::font::FontSet;
+    use crate::emulator::platform::PlatformProfile;
+
+    // Draws the glyph for 5 forever, moving it right while key 0 is held. RND
+    // and the font make the final state depend on the seed and font too.
+    fn rom(load_address: MemoryAddress) -> Vec<u8> {
+        let loop_start = (0x1000 | (load_address + 2)).to_be_bytes();
+        vec![
+            0x61, 0x05, // LD V1, 5
+            0xF1, 0x29, // LD F, V1
+            0xD0, 0x05, // DRW V0, V0, 5
+            0xC2, 0xFF, // RND V2, 0xFF
+            0xE3, 0xA1, // SKNP V3
+            0x70, 0x01, // ADD V0, 1
+            loop_start[0], loop_start[1]
+        ]
+    }
+
+    fn record(platform: Platform, font: &Font, font_base: MemoryAddress) -> (Movie, Vec<u8>) {
+        let rom = rom(platform.load_address);
+        let mut emulator = Emulator::new(0x5eed, Quirks::default());
+        emulator.set_platform(platform).unwrap();
+        emulator.set_font(font, font_base).unwrap();
+        emulator.load_program(&rom).unwrap();
+        let mut recorder = MovieRecorder::new(&emulator, &rom, 10);
+        for frame in 0..30 {
+            match frame {
+                5 => emulator.keypad_mut().press(0).unwrap(),
+                12 => emulator.keypad_mut().release(0).unwrap(),
+                _ => {}
+            }
+            recorder.record_frame(&emulator);
+            emulator.run_frame(10).unwrap();
+        }
+        (recorder.finish(&emulator), rom)
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("lucid8-{}-{}.toml", name, std::process::id()))
+    }
+
+    #[test]
+    fn replay_reaches_the_recorded_state() {
+        let (movie, rom) = record(Platform::default(), &Font::default(), 0);
+        assert_eq!(movie.frames, 30);
+        assert_eq!(movie.inputs, vec![InputChange { frame: 5, keys: 1 }, InputChange { frame: 12, keys: 0 }]);
+        movie.verify(&rom).unwrap();
+    }
+
+    #[test]
+    fn replay_uses_the_recorded_platform_and_font() {
+        let profiles = [PlatformProfile::Vip, PlatformProfile::Schip, PlatformProfile::Eti660, PlatformProfile::XoChip];
+        for (profile, set) in profiles.iter().zip(FontSet::ALL.iter()) {
+            let (movie, rom) = record((*profile).into(), &set.font(), 0x50);
+            assert_eq!(movie.platform, (*profile).into());
+            assert_eq!(movie.font_base, 0x50);
+            assert!(movie.verify(&rom).is_ok(), "{:?} with the {} font desynced", profile, set.name());
+        }
+    }
+
+    #[test]
+    fn movies_round_trip_through_toml() {
+        let (movie, rom) = record(PlatformProfile::XoChip.into(), &FontSet::Octo.font(), 0x20);
+        let path = temp_path("movie-round-trip");
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, movie);
+        loaded.verify(&rom).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_other_versions() {
+        let (mut movie, _) = record(Platform::default(), &Font::default(), 0);
+        movie.version = MOVIE_VERSION - 1;
+        let path = temp_path("movie-old-version");
+        movie.save(&path).unwrap();
+        let loaded = Movie::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded.unwrap_err().downcast_ref::<MovieError>(), Some(MovieError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn a_different_rom_is_rejected() {
+        let (movie, mut rom) = record(Platform::default(), &Font::default(), 0);
+        rom[1] = 0x06;
+        let error = movie.play(&rom).unwrap_err();
+        assert!(matches!(error.downcast_ref::<MovieError>(), Some(MovieError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn tampered_inputs_desync() {
+        let (mut movie, rom) = record(Platform::default(), &Font::default(), 0);
+        movie.inputs[1].frame += 1;
+        let error = movie.verify(&rom).unwrap_err();
+        assert!(matches!(error.downcast_ref::<MovieError>(), Some(MovieError::Desync { .. })));
+    }
+}
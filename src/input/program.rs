@@ -2,22 +2,23 @@ use std::fmt::{Display, Formatter};
 
 use crate::emulator::instructions::Instruction;
 use crate::emulator::emulator::Result;
+use crate::emulator::platform::Platform;
 
 pub struct Program {
-    bytes: [u8; 3584],
+    bytes: Vec<u8>,
     instructions: Vec<Instruction>,
     script: Option<String>
 }
 
 #[derive(Debug)]
 pub enum ProgramError {
-    SpaceExceeded(usize)
+    SpaceExceeded { size: usize, capacity: usize }
 }
 
 impl Display for ProgramError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ProgramError::SpaceExceeded(size) => write!(f, "Program too large! Max Bytes: [3584], Given: [{}]", size)
+            ProgramError::SpaceExceeded { size, capacity } => write!(f, "Program too large! Max Bytes: [{}], Given: [{}]", capacity, size)
         }
     }
 }
@@ -26,20 +27,64 @@ impl std::error::Error for ProgramError {}
 
 impl Default for Program {
     fn default() -> Self {
+        Program::new(&Platform::default())
+    }
+}
+
+impl Program {
+    // Program space is whatever the platform leaves above its load address.
+    pub fn new(platform: &Platform) -> Self {
         Self {
-            bytes: [0; 3584],
+            bytes: vec![0; platform.program_capacity()],
             instructions: vec![],
             script: None
         }
     }
-}
 
-impl Program {
+    pub fn capacity(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // Replaces the whole program; space past the end of `bytes` is zeroed.
     pub fn save_bytes(&mut self, bytes: &[u8]) -> Result<()> {
-        if bytes.len() > 3584 {
-            return Err(Box::new(ProgramError::SpaceExceeded(bytes.len())));
+        if bytes.len() > self.capacity() {
+            return Err(Box::new(ProgramError::SpaceExceeded { size: bytes.len(), capacity: self.capacity() }));
         }
-        self.bytes.copy_from_slice(&bytes[..]);
+        self.bytes[..bytes.len()].copy_from_slice(bytes);
+        self.bytes[bytes.len()..].fill(0);
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn script(&self) -> Option<&str> {
+        self.script.as_deref()
+    }
+
+    // Rhai source run alongside the program, see `application::script`.
+    pub fn set_script(&mut self, script: Option<String>) {
+        self.script = script;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_bytes_fills_from_the_start_up_to_capacity() {
+        let mut program = Program::default();
+        program.save_bytes(&[1, 2, 3]).unwrap();
+        assert_eq!(&program.bytes[..4], &[1, 2, 3, 0]);
+        // A shorter program leaves nothing of the longer one behind.
+        program.save_bytes(&[9; 6]).unwrap();
+        program.save_bytes(&[4, 5]).unwrap();
+        assert_eq!(&program.bytes[..7], &[4, 5, 0, 0, 0, 0, 0]);
+        assert!(program.bytes[2..].iter().all(|byte| *byte == 0));
+        let capacity = program.capacity();
+        assert!(program.save_bytes(&vec![0; capacity]).is_ok());
+        assert!(program.save_bytes(&vec![0; capacity + 1]).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod emulator;
+pub mod input;
+pub mod audio;
+pub mod application;
+pub mod frontend;
+pub mod util;
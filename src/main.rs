@@ -1,7 +1,340 @@
-mod emulator;
-mod input;
-mod application;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use lucid8::audio::buzzer::Tone;
+use lucid8::audio::wav::WavWriter;
+use lucid8::application::app::Project;
+use lucid8::application::build::{self, Build};
+use lucid8::application::harness::Suite;
+use lucid8::application::script::Script;
+use lucid8::emulator::emulator::{MemoryAddress, Result};
+use lucid8::emulator::font::Font;
+use lucid8::emulator::memory::RegionKind;
+use lucid8::emulator::platform::PlatformProfile;
+use lucid8::emulator::quirks::QuirkProfile;
+use lucid8::frontend::phosphor::PhosphorMode;
+use lucid8::frontend::palette::Palette;
+use lucid8::frontend::render::render_text;
+use lucid8::frontend::session::Session;
+use lucid8::frontend::window;
+use lucid8::input::keymap::KeymapConfig;
+use lucid8::input::movie::Movie;
+
+const SAMPLE_RATE: u32 = 44_100;
+const USAGE: &str = "usage: lucid8 [--scale N] [--keymap FILE] [--quirks PROFILE] [--platform NAME] [--font NAME|FILE] [--font-base ADDR] [--protect REGION,...] [--record MOVIE | --play MOVIE] [--wav FILE] [--phosphor decay:F|blend:N] [--palette THEME|FILE] [--screenshot PNG] [--capture FILE [--capture-frames START:END]] [--script FILE] [--headless FRAMES] [--build PROJECT [--watch] | --test SUITE | ROM]";
+
+struct Options {
+    rom: Option<PathBuf>,
+    build: Option<PathBuf>,
+    watch: bool,
+    test: Option<PathBuf>,
+    script: Option<PathBuf>,
+    scale: u32,
+    keymap: Option<PathBuf>,
+    quirks: Option<QuirkProfile>,
+    platform: Option<PlatformProfile>,
+    font: Option<Font>,
+    font_base: MemoryAddress,
+    protect: Vec<RegionKind>,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    phosphor: Option<PhosphorMode>,
+    palette: Option<Palette>,
+    screenshot: Option<PathBuf>,
+    capture: Option<PathBuf>,
+    capture_frames: Range<u64>,
+    headless: Option<usize>
+}
+
+fn parse_args() -> std::result::Result<Options, String> {
+    let mut options = Options {
+        rom: None,
+        build: None,
+        watch: false,
+        test: None,
+        script: None,
+        scale: 10,
+        keymap: None,
+        quirks: None,
+        platform: None,
+        font: None,
+        font_base: 0,
+        protect: vec![],
+        record: None,
+        play: None,
+        wav: None,
+        phosphor: None,
+        palette: None,
+        screenshot: None,
+        capture: None,
+        capture_frames: 0..u64::MAX,
+        headless: None
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => {
+                options.scale = args.next().and_then(|n| n.parse().ok()).filter(|n| *n > 0).ok_or("--scale expects a positive integer")?;
+            },
+            "--keymap" => {
+                options.keymap = Some(args.next().map(PathBuf::from).ok_or("--keymap expects a file")?);
+            },
+            "--quirks" => {
+                options.quirks = Some(args.next().ok_or("--quirks expects a profile")?.parse()?);
+            },
+            "--platform" => {
+                options.platform = Some(args.next().ok_or("--platform expects a name")?.parse()?);
+            },
+            "--font" => {
+                options.font = Some(args.next().ok_or("--font expects a name or file")?.parse().map_err(|e| format!("{}", e))?);
+            },
+            "--font-base" => {
+                options.font_base = args.next()
+                    .and_then(|addr| match addr.strip_prefix("0x") {
+                        Some(hex) => MemoryAddress::from_str_radix(hex, 16).ok(),
+                        None => addr.parse().ok()
+                    })
+                    .ok_or("--font-base expects an address")?;
+            },
+            "--protect" => {
+                options.protect = args.next().ok_or("--protect expects a list of regions")?
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<std::result::Result<_, _>>()?;
+            },
+            "--record" => {
+                options.record = Some(args.next().map(PathBuf::from).ok_or("--record expects a file")?);
+            },
+            "--play" => {
+                options.play = Some(args.next().map(PathBuf::from).ok_or("--play expects a file")?);
+            },
+            "--wav" => {
+                options.wav = Some(args.next().map(PathBuf::from).ok_or("--wav expects a file")?);
+            },
+            "--phosphor" => {
+                options.phosphor = Some(args.next().ok_or("--phosphor expects a mode")?.parse()?);
+            },
+            "--palette" => {
+                options.palette = Some(args.next().ok_or("--palette expects a theme or file")?.parse().map_err(|e| format!("{}", e))?);
+            },
+            "--screenshot" => {
+                options.screenshot = Some(args.next().map(PathBuf::from).ok_or("--screenshot expects a file")?);
+            },
+            "--capture" => {
+                options.capture = Some(args.next().map(PathBuf::from).ok_or("--capture expects a file")?);
+            },
+            "--capture-frames" => {
+                options.capture_frames = args.next()
+                    .and_then(|range| {
+                        let (start, end) = range.split_once(':')?;
+                        Some(start.parse().ok()?..end.parse().ok()?)
+                    })
+                    .ok_or("--capture-frames expects START:END")?;
+            },
+            "--build" => {
+                options.build = Some(args.next().map(PathBuf::from).ok_or("--build expects a project")?);
+            },
+            "--watch" => options.watch = true,
+            "--test" => {
+                options.test = Some(args.next().map(PathBuf::from).ok_or("--test expects a suite")?);
+            },
+            "--script" => {
+                options.script = Some(args.next().map(PathBuf::from).ok_or("--script expects a file")?);
+            },
+            "--headless" => {
+                options.headless = Some(args.next().and_then(|n| n.parse().ok()).ok_or("--headless expects a frame count")?);
+            },
+            "-h" | "--help" => return Err(String::new()),
+            _ if options.rom.is_none() && !arg.starts_with('-') => options.rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument: {}", arg))
+        }
+    }
+    if options.record.is_some() && options.play.is_some() {
+        return Err(String::from("--record and --play cannot be combined"));
+    }
+    if options.build.is_some() && options.rom.is_some() {
+        return Err(String::from("--build and a ROM cannot be combined"));
+    }
+    if options.test.is_some() && (options.build.is_some() || options.rom.is_some()) {
+        return Err(String::from("--test takes its project or ROM from the suite"));
+    }
+    if options.watch && options.build.is_none() {
+        return Err(String::from("--watch needs --build"));
+    }
+    Ok(options)
+}
+
+// Assembles the project, printing its diagnostics, and points the options at
+// the built ROM. Flags given on the command line win over the manifest.
+fn build_project(options: &mut Options, path: &Path) -> Result<(Project, Build)> {
+    let mut project = Project::load(path)?;
+    let (build, emulator) = build::build_and_load(&mut project, 0);
+    for diagnostic in &build.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if emulator.is_none() {
+        return Err(format!("{} failed to build", project.manifest().name).into());
+    }
+    let manifest = project.manifest();
+    options.rom = Some(project.resolve(&manifest.output));
+    options.quirks = options.quirks.or(manifest.quirks);
+    options.platform = options.platform.or(Some(manifest.platform));
+    options.palette = options.palette.or(Some(manifest.palette));
+    Ok((project, build))
+}
+
+// Checks the project's files twice a second and patches the running ROM when
+// one of them changes.
+fn watch(mut project: Project, mut previous: Build) -> impl FnMut(&mut Session) -> Result<()> {
+    let mut frames = 0;
+    move |session| {
+        frames += 1;
+        if frames % 30 != 0 || project.changed_files().is_empty() {
+            return Ok(());
+        }
+        let (build, reloaded) = build::hot_reload(&mut project, session, &previous);
+        for diagnostic in &build.diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        // Until a reload goes through the session still runs the old build.
+        if reloaded.is_ok() {
+            previous = build;
+            eprintln!("reloaded {}", project.manifest().name);
+        } else {
+            project.mark_built();
+        }
+        reloaded
+    }
+}
+
+// Runs a subroutine test suite, failing if any test does.
+fn run_tests(path: &Path) -> Result<()> {
+    let outcomes = Suite::load(path)?.run()?;
+    for outcome in &outcomes {
+        println!("{}", outcome);
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if failed > 0 {
+        return Err(format!("{} of {} tests failed", failed, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+fn run(mut options: Options) -> Result<()> {
+    if let Some(suite) = &options.test {
+        return run_tests(suite);
+    }
+    let mut session = Session::default();
+    let project = match options.build.clone() {
+        Some(path) => Some(build_project(&mut options, &path)?),
+        None => None
+    };
+    match (&options.keymap, &project) {
+        (Some(keymap), _) => session.set_keymaps(KeymapConfig::load(keymap)?)?,
+        (None, Some((project, _))) => session.set_keymaps(KeymapConfig { default: project.manifest().keymap.clone(), ..KeymapConfig::default() })?,
+        (None, None) => {}
+    }
+    if let Some(profile) = options.quirks {
+        session.set_quirks(profile.into());
+    }
+    if let Some(profile) = options.platform {
+        session.set_platform(profile.into())?;
+    }
+    if options.font.is_some() || options.font_base != 0 {
+        session.set_font(&options.font.clone().unwrap_or_default(), options.font_base)?;
+    }
+    for kind in &options.protect {
+        session.set_protected(*kind, true);
+    }
+    if let Some(rom) = &options.rom {
+        session.load_rom_file(rom)?;
+    }
+    session.set_phosphor(options.phosphor);
+    if let Some(palette) = options.palette {
+        session.set_palette(palette);
+    }
+    if let Some(wav) = &options.wav {
+        session.set_audio(Tone::default(), Box::new(WavWriter::create(wav, SAMPLE_RATE)?))?;
+    }
+    if let Some(movie) = &options.play {
+        let movie = Movie::load(movie)?;
+        if options.headless.is_some() {
+            let rom = fs::read(options.rom.as_ref().ok_or("--play needs a ROM")?)?;
+            let emulator = movie.play(&rom)?;
+            print!("{}", render_text(emulator.display()));
+            return movie.check_final_state(&emulator).map(|_| eprintln!("playback matches recording ({} frames)", movie.frames));
+        }
+        session.play_movie(movie)?;
+    }
+    if let Some(movie) = &options.record {
+        session.start_recording(movie)?;
+    }
+    if let Some(capture) = &options.capture {
+        session.start_capture(capture, options.scale as usize, options.capture_frames.clone())?;
+    }
+    let mut script = match &options.script {
+        Some(path) => {
+            let mut script = Script::load(path)?;
+            script.run(&mut session)?;
+            Some(script)
+        },
+        None => None
+    };
+    let mut reload = project.filter(|_| options.watch).map(|(project, build)| watch(project, build));
+    if let Some(script) = &script {
+        session.set_overlay(script.overlay());
+    }
+    // Runs between frames: script event handlers, then reloads.
+    let mut on_frame = move |session: &mut Session| -> Result<()> {
+        if let Some(script) = &mut script {
+            script.handle_events(session)?;
+            session.set_overlay(script.overlay());
+        }
+        match &mut reload {
+            Some(reload) => reload(session),
+            None => Ok(())
+        }
+    };
+    match options.headless {
+        Some(frames) => {
+            for _ in 0..frames {
+                session.run_frame()?;
+                on_frame(&mut session)?;
+            }
+            session.stop_recording()?;
+            session.stop_audio()?;
+            session.stop_capture()?;
+            if let Some(screenshot) = &options.screenshot {
+                session.screenshot(screenshot, options.scale as usize)?;
+            }
+            for line in session.overlay() {
+                eprintln!("{}", line);
+            }
+            print!("{}", render_text(session.emulator().display()));
+            Ok(())
+        },
+        None => window::run_with(session, options.scale, options.screenshot.clone(), on_frame)
+    }
+}
 
 fn main() {
-    println!("Hello, world!");
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("{}", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
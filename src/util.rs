@@ -0,0 +1,50 @@
+// FNV-1a, fed a piece at a time. Unlike `DefaultHasher` it's the same in
+// every build, so the result can be saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl ContentHasher {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// FNV-1a over `bytes`, e.g. to tell whether a ROM or source file changed.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_published_fnv1a_values() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(content_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn hashing_in_pieces_matches_hashing_at_once() {
+        let mut hasher = ContentHasher::default();
+        hasher.write(b"foo");
+        hasher.write(b"");
+        hasher.write(b"bar");
+        assert_eq!(hasher.finish(), content_hash(b"foobar"));
+    }
+}